@@ -0,0 +1,115 @@
+use std::time::Duration;
+
+/* ====== Настройки из ENV ======
+   мелкие числовые параметры; при мусоре в переменной — дефолт и предупреждение */
+
+/// Таймаут в секундах из переменной `var` (1..=300), иначе `default_secs`.
+pub fn env_secs(var: &str, default_secs: u64) -> Duration {
+    Duration::from_secs(parse_secs(
+        var,
+        std::env::var(var).ok().as_deref(),
+        default_secs,
+    ))
+}
+
+fn parse_secs(var: &str, raw: Option<&str>, default_secs: u64) -> u64 {
+    let Some(raw) = raw else {
+        return default_secs;
+    };
+    match raw.trim().parse::<u64>() {
+        Ok(n) if (1..=300).contains(&n) => n,
+        _ => {
+            tracing::warn!(
+                "{}={} ignored: expected 1..=300 seconds, using {}",
+                var,
+                raw,
+                default_secs
+            );
+            default_secs
+        }
+    }
+}
+
+/// Число из переменной `var` в 1..=`max` (например, параллельность), иначе `default`.
+pub fn env_count(var: &str, default: usize, max: usize) -> usize {
+    parse_count(var, std::env::var(var).ok().as_deref(), default, max)
+}
+
+fn parse_count(var: &str, raw: Option<&str>, default: usize, max: usize) -> usize {
+    let Some(raw) = raw else {
+        return default;
+    };
+    match raw.trim().parse::<usize>() {
+        Ok(n) if (1..=max).contains(&n) => n,
+        _ => {
+            tracing::warn!(
+                "{}={} ignored: expected 1..={}, using {}",
+                var,
+                raw,
+                max,
+                default
+            );
+            default
+        }
+    }
+}
+
+/// Пауза в миллисекундах из переменной `var` (0..=`max_ms`; 0 — без паузы), иначе `default_ms`.
+pub fn env_millis(var: &str, default_ms: u64, max_ms: u64) -> Duration {
+    Duration::from_millis(parse_millis(
+        var,
+        std::env::var(var).ok().as_deref(),
+        default_ms,
+        max_ms,
+    ))
+}
+
+fn parse_millis(var: &str, raw: Option<&str>, default_ms: u64, max_ms: u64) -> u64 {
+    let Some(raw) = raw else {
+        return default_ms;
+    };
+    match raw.trim().parse::<u64>() {
+        Ok(n) if n <= max_ms => n,
+        _ => {
+            tracing::warn!(
+                "{}={} ignored: expected 0..={} ms, using {}",
+                var,
+                raw,
+                max_ms,
+                default_ms
+            );
+            default_ms
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_secs() {
+        assert_eq!(parse_secs("X", None, 12), 12);
+        assert_eq!(parse_secs("X", Some("5"), 12), 5);
+        assert_eq!(parse_secs("X", Some(" 30 "), 12), 30);
+        assert_eq!(parse_secs("X", Some("0"), 12), 12);
+        assert_eq!(parse_secs("X", Some("soon"), 12), 12);
+    }
+
+    #[test]
+    fn test_parse_count() {
+        assert_eq!(parse_count("X", None, 4, 10), 4);
+        assert_eq!(parse_count("X", Some("8"), 4, 10), 8);
+        assert_eq!(parse_count("X", Some("0"), 4, 10), 4);
+        assert_eq!(parse_count("X", Some("11"), 4, 10), 4);
+    }
+
+    #[test]
+    fn test_parse_millis() {
+        assert_eq!(parse_millis("X", None, 300, 5000), 300);
+        assert_eq!(parse_millis("X", Some("0"), 300, 5000), 0);
+        assert_eq!(parse_millis("X", Some(" 750 "), 300, 5000), 750);
+        assert_eq!(parse_millis("X", Some("9000"), 300, 5000), 300);
+        assert_eq!(parse_millis("X", Some("-1"), 300, 5000), 300);
+    }
+}
@@ -0,0 +1,110 @@
+/* ====== Числа и длительности по-человечески ======
+   язык берём из настроек чата (ChatSettings.language): "ru" или "en",
+   всё остальное — по-русски */
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Lang {
+    #[default]
+    Ru,
+    En,
+}
+
+impl Lang {
+    pub fn from_code(code: &str) -> Self {
+        if code.trim().eq_ignore_ascii_case("en") {
+            Lang::En
+        } else {
+            Lang::Ru
+        }
+    }
+}
+
+/// Русская форма по числу: 1 сезон, 2 сезона, 5 сезонов (11–14 — «много»).
+pub fn plural_ru<'a>(n: u64, one: &'a str, few: &'a str, many: &'a str) -> &'a str {
+    let (n10, n100) = (n % 10, n % 100);
+    if n10 == 1 && n100 != 11 {
+        one
+    } else if (2..=4).contains(&n10) && !(12..=14).contains(&n100) {
+        few
+    } else {
+        many
+    }
+}
+
+fn plural_en<'a>(n: u64, one: &'a str, many: &'a str) -> &'a str {
+    if n == 1 {
+        one
+    } else {
+        many
+    }
+}
+
+pub fn seasons(n: u32, lang: Lang) -> String {
+    let n = u64::from(n);
+    let word = match lang {
+        Lang::Ru => plural_ru(n, "сезон", "сезона", "сезонов"),
+        Lang::En => plural_en(n, "season", "seasons"),
+    };
+    format!("{} {}", n, word)
+}
+
+/// Хронометраж: «2 ч 8 мин», «48 мин», «2 ч»; в английском — «2 h 8 min».
+pub fn runtime(minutes: u32, lang: Lang) -> String {
+    let (h, m) = (minutes / 60, minutes % 60);
+    let (hu, mu) = match lang {
+        Lang::Ru => ("ч", "мин"),
+        Lang::En => ("h", "min"),
+    };
+    match (h, m) {
+        (0, m) => format!("{} {}", m, mu),
+        (h, 0) => format!("{} {}", h, hu),
+        (h, m) => format!("{} {} {} {}", h, hu, m, mu),
+    }
+}
+
+/// Год из даты TMDb («2019-05-30» → «2019»); обрезки вроде «20» и пустые строки — None.
+pub fn year_of(date: &Option<String>) -> Option<&str> {
+    date.as_deref()
+        .map(str::trim)
+        .and_then(|d| d.get(..4))
+        .filter(|y| y.bytes().all(|b| b.is_ascii_digit()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_seasons_ru_plural() {
+        assert_eq!(seasons(1, Lang::Ru), "1 сезон");
+        assert_eq!(seasons(2, Lang::Ru), "2 сезона");
+        assert_eq!(seasons(5, Lang::Ru), "5 сезонов");
+        assert_eq!(seasons(11, Lang::Ru), "11 сезонов");
+        assert_eq!(seasons(21, Lang::Ru), "21 сезон");
+        assert_eq!(seasons(22, Lang::Ru), "22 сезона");
+        assert_eq!(seasons(1, Lang::En), "1 season");
+        assert_eq!(seasons(3, Lang::En), "3 seasons");
+    }
+
+    #[test]
+    fn test_runtime() {
+        assert_eq!(runtime(128, Lang::Ru), "2 ч 8 мин");
+        assert_eq!(runtime(48, Lang::Ru), "48 мин");
+        assert_eq!(runtime(120, Lang::Ru), "2 ч");
+        assert_eq!(runtime(128, Lang::En), "2 h 8 min");
+        assert_eq!(Lang::from_code("EN"), Lang::En);
+        assert_eq!(Lang::from_code("de"), Lang::Ru);
+    }
+
+    #[test]
+    fn test_year_of() {
+        let date = |d: &str| Some(d.to_string());
+        assert_eq!(year_of(&date("2019-05-30")), Some("2019"));
+        assert_eq!(year_of(&date("2025")), Some("2025"));
+        assert_eq!(year_of(&date("20")), None);
+        assert_eq!(year_of(&date("")), None);
+        assert_eq!(year_of(&date("n/a-01-01")), None);
+        assert_eq!(year_of(&date("Дюна")), None);
+        assert_eq!(year_of(&None), None);
+    }
+}
@@ -0,0 +1,171 @@
+use moka::future::Cache;
+use moka::policy::EvictionPolicy;
+use once_cell::sync::Lazy;
+use std::time::Duration;
+
+/* ====== Кэш постеров ======
+   URL -> байты картинки; LRU с ограничением по суммарному размеру (64 МБ)
+   и TTL, чтобы постеры со временем обновлялись */
+static IMAGE_CACHE: Lazy<Cache<String, Vec<u8>>> = Lazy::new(|| {
+    Cache::builder()
+        .eviction_policy(EvictionPolicy::lru())
+        .weigher(|k: &String, v: &Vec<u8>| (k.len() + v.len()).try_into().unwrap_or(u32::MAX))
+        .max_capacity(64 * 1024 * 1024)
+        .time_to_live(Duration::from_secs(24 * 60 * 60))
+        .build()
+});
+
+/* один клиент на все загрузки: пул keep-alive соединений и TLS-сессий
+   переживает альбом из 10 постеров */
+static IMAGE_CLIENT: Lazy<reqwest::Client> = Lazy::new(|| {
+    reqwest::Client::builder()
+        .timeout(crate::config::env_secs("IMAGE_TIMEOUT_SECS", 15))
+        .user_agent("Mozilla/5.0 (compatible; tg-bot/1.0)")
+        .build()
+        .expect("reqwest client")
+});
+
+/* ====== Загрузка постера байтами (устойчиво к редиректам/CDN) ====== */
+pub async fn fetch_image(url: &str) -> anyhow::Result<Vec<u8>> {
+    if let Some(bytes) = IMAGE_CACHE.get(url).await {
+        return Ok(bytes);
+    }
+    let bytes = download(url).await?;
+    IMAGE_CACHE.insert(url.to_string(), bytes.clone()).await;
+    Ok(bytes)
+}
+
+/// Сбрасывает кэш постеров; возвращает, сколько записей было.
+pub async fn clear_image_cache() -> u64 {
+    IMAGE_CACHE.run_pending_tasks().await;
+    let dropped = IMAGE_CACHE.entry_count();
+    IMAGE_CACHE.invalidate_all();
+    IMAGE_CACHE.run_pending_tasks().await;
+    dropped
+}
+
+async fn download(url: &str) -> anyhow::Result<Vec<u8>> {
+    let resp = IMAGE_CLIENT
+        .get(url)
+        .header(reqwest::header::ACCEPT, "image/*")
+        .send()
+        .await?;
+    if !resp.status().is_success() {
+        anyhow::bail!("status {}", resp.status());
+    }
+    if let Some(ct) = resp.headers().get(reqwest::header::CONTENT_TYPE) {
+        let ct = ct.to_str().unwrap_or("");
+        if !ct.starts_with("image/") {
+            anyhow::bail!("unexpected content-type: {ct}");
+        }
+    }
+    let bytes = resp.bytes().await?;
+    Ok(bytes.to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    #[tokio::test]
+    async fn test_fetch_image_is_cached() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/poster.jpg"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header("content-type", "image/jpeg")
+                    .set_body_bytes(vec![1u8, 2, 3]),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let url = format!("{}/poster.jpg", server.uri());
+        assert_eq!(fetch_image(&url).await.unwrap(), vec![1, 2, 3]);
+        assert_eq!(fetch_image(&url).await.unwrap(), vec![1, 2, 3]);
+    }
+
+    #[tokio::test]
+    async fn test_clear_image_cache_refetches() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/cleared.jpg"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header("content-type", "image/jpeg")
+                    .set_body_bytes(vec![7u8]),
+            )
+            .expect(2)
+            .mount(&server)
+            .await;
+
+        let url = format!("{}/cleared.jpg", server.uri());
+        fetch_image(&url).await.unwrap();
+        assert!(clear_image_cache().await >= 1);
+        fetch_image(&url).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_fetch_image_rejects_non_image() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/page.html"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header("content-type", "text/html")
+                    .set_body_string("<html></html>"),
+            )
+            .mount(&server)
+            .await;
+
+        let url = format!("{}/page.html", server.uri());
+        assert!(fetch_image(&url).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_downloads_reuse_connection() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Arc;
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        // мини-сервер с keep-alive: считаем принятые соединения
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let accepted = Arc::new(AtomicUsize::new(0));
+        let counter = accepted.clone();
+        tokio::spawn(async move {
+            loop {
+                let Ok((mut sock, _)) = listener.accept().await else {
+                    break;
+                };
+                counter.fetch_add(1, Ordering::SeqCst);
+                tokio::spawn(async move {
+                    let mut buf = Vec::new();
+                    let mut chunk = [0u8; 1024];
+                    while let Ok(n) = sock.read(&mut chunk).await {
+                        if n == 0 {
+                            break;
+                        }
+                        buf.extend_from_slice(&chunk[..n]);
+                        while let Some(end) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+                            buf.drain(..end + 4);
+                            let resp = b"HTTP/1.1 200 OK\r\ncontent-type: image/jpeg\r\ncontent-length: 3\r\n\r\nabc";
+                            if sock.write_all(resp).await.is_err() {
+                                return;
+                            }
+                        }
+                    }
+                });
+            }
+        });
+
+        for i in 0..3 {
+            let url = format!("http://{}/reuse_{}.jpg", addr, i);
+            assert_eq!(fetch_image(&url).await.unwrap(), b"abc".to_vec());
+        }
+        assert_eq!(accepted.load(Ordering::SeqCst), 1);
+    }
+}
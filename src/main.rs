@@ -1,3 +1,8 @@
+mod config;
+mod format;
+mod images;
+mod ratelimit;
+mod regions;
 mod storage;
 mod tg;
 mod tmdb;
@@ -20,7 +25,13 @@ async fn main() -> anyhow::Result<()> {
     // путь к файлу хранения (можно через ENV)
     let store_path =
         std::env::var("STORE_PATH").unwrap_or_else(|_| "movie_bot_state.json".to_string());
-    let storage = storage::Storage::new(store_path).await?;
+    // раздел в общем файле, если несколько ботов делят один стор
+    let storage = match std::env::var("STORE_NAMESPACE") {
+        Ok(ns) if !ns.trim().is_empty() => {
+            storage::Storage::with_namespace(store_path, Some(ns.trim().to_string())).await?
+        }
+        _ => storage::Storage::new(store_path).await?,
+    };
 
     tg::run(bot, tmdb, storage, false, true).await;
     Ok(())
@@ -0,0 +1,63 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/* ====== Антиспам по чатам ======
+   не больше `burst` действий за `window` на чат; держим в памяти только
+   метки последних действий — после рестарта всё начинается заново */
+pub struct RateLimiter {
+    window: Duration,
+    burst: usize,
+    hits: Mutex<HashMap<i64, VecDeque<Instant>>>,
+}
+
+impl RateLimiter {
+    pub fn new(window: Duration, burst: usize) -> Self {
+        Self {
+            window,
+            burst: burst.max(1),
+            hits: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// true — можно выполнять (и действие засчитано), false — чат упёрся в лимит.
+    pub fn check(&self, chat_id: i64) -> bool {
+        self.check_at(chat_id, Instant::now())
+    }
+
+    fn check_at(&self, chat_id: i64, now: Instant) -> bool {
+        let mut hits = self.hits.lock().unwrap_or_else(|e| e.into_inner());
+        let q = hits.entry(chat_id).or_default();
+        while q
+            .front()
+            .is_some_and(|t| now.duration_since(*t) >= self.window)
+        {
+            q.pop_front();
+        }
+        if q.len() >= self.burst {
+            return false;
+        }
+        q.push_back(now);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_burst_then_window_reset() {
+        let limiter = RateLimiter::new(Duration::from_secs(10), 3);
+        let t0 = Instant::now();
+        assert!(limiter.check_at(1, t0));
+        assert!(limiter.check_at(1, t0 + Duration::from_secs(1)));
+        assert!(limiter.check_at(1, t0 + Duration::from_secs(2)));
+        assert!(!limiter.check_at(1, t0 + Duration::from_secs(3)));
+        // у другого чата свой счётчик
+        assert!(limiter.check_at(2, t0 + Duration::from_secs(3)));
+        // первая метка ушла из окна — место освободилось
+        assert!(limiter.check_at(1, t0 + Duration::from_secs(10)));
+        assert!(!limiter.check_at(1, t0 + Duration::from_secs(10)));
+    }
+}
@@ -1,9 +1,18 @@
-use crate::tmdb::MediaKind;
+use crate::tmdb::{MediaKind, MultiNorm, SearchFilter};
+use fs2::FileExt;
 use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, path::PathBuf, sync::Arc};
+use std::{collections::HashMap, fs::File, path::PathBuf, sync::Arc};
 use tokio::fs;
 use tokio::sync::RwLock;
 
+/// Текущая версия формата файла; поднимать вместе с новым шагом в `migrate`.
+const STORE_VERSION: u32 = 1;
+pub const MAX_LIST_LEN: usize = 10;
+/// Бэклог — длинный список «на потом», из него /promote переносит в шортлист.
+const MAX_BACKLOG_LEN: usize = 100;
+/// Сколько резервных копий (<store>.1 … .N) держим при flush.
+const BACKUP_COUNT: usize = 3;
+
 fn default_media_kind() -> MediaKind {
     MediaKind::Movie
 }
@@ -17,47 +26,231 @@ pub struct StoredMovie {
     pub media_type: MediaKind,
     pub poster_path: Option<String>,
     pub release_date: Option<String>,
+    #[serde(default)]
+    pub watched: bool,
+    #[serde(default)]
+    pub vote_average: Option<f64>,
+    /// название, данное чатом (/rename); детали всё равно тянем по id
+    #[serde(default)]
+    pub custom_title: Option<String>,
+}
+
+impl StoredMovie {
+    /// Что показываем в списке и опросе: своё название, если задано.
+    pub fn display_title(&self) -> &str {
+        self.custom_title.as_deref().unwrap_or(&self.title)
+    }
+}
+
+impl From<MultiNorm> for StoredMovie {
+    fn from(m: MultiNorm) -> Self {
+        let poster_path = m.poster_path().map(str::to_owned);
+        Self {
+            id: m.id,
+            title: m.title,
+            original_title: m.original_title,
+            media_type: m.media_type,
+            poster_path,
+            release_date: m.release_date,
+            watched: false,
+            vote_average: m.vote_average,
+            custom_title: None,
+        }
+    }
+}
+
+/// Настройки чата; новые поля добавлять с дефолтом — старые файлы должны читаться.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ChatSettings {
+    /// ISO 3166-1 alpha-2: где смотреть, возрастной рейтинг
+    pub region: String,
+    /// ISO 639-1: трейлеры на этом языке в приоритете
+    pub language: String,
+    /// взрослый контент в поиске TMDb (include_adult); включается с подтверждением
+    pub include_adult: bool,
+    /// в поиске прячем тайтлы с меньшим числом голосов TMDb (если есть известнее)
+    pub min_votes: u64,
+    /// в /list и опросе дописывать оригинальное название, если оно другое
+    pub show_original_title: bool,
+    /// свой вопрос опроса (/setquestion); None — стандартный
+    pub vote_question: Option<String>,
+    /// постеры в /vote и карточке; выключают в чатах с дорогим трафиком
+    pub posters: bool,
+    /// после добавления из поиска сразу присылать список; выключают при пакетном добавлении
+    pub auto_list: bool,
+    /// трейлеры в /vote — отдельными сообщениями с превью YouTube, а не списком ссылок
+    pub rich_trailers: bool,
+    /// порядок выдачи поиска (/searchsort); по умолчанию — как отдал TMDb
+    pub search_sort: SearchSort,
+    /// закреплять опрос /vote в чате
+    pub auto_pin: bool,
+}
+
+impl Default for ChatSettings {
+    fn default() -> Self {
+        Self {
+            region: "RU".to_string(),
+            language: "ru".to_string(),
+            include_adult: false,
+            min_votes: 10,
+            show_original_title: false,
+            vote_question: None,
+            posters: true,
+            auto_list: true,
+            rich_trailers: false,
+            search_sort: SearchSort::default(),
+            auto_pin: false,
+        }
+    }
+}
+
+impl ChatSettings {
+    pub fn search_filter(&self) -> SearchFilter {
+        SearchFilter {
+            include_adult: self.include_adult,
+            min_votes: self.min_votes,
+        }
+    }
+}
+
+/// Порядок результатов поиска в чате.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SearchSort {
+    /// как отдал TMDb (по популярности)
+    #[default]
+    Popularity,
+    /// сначала новые
+    Date,
+    /// сначала с лучшим рейтингом
+    Rating,
+}
+
+impl SearchSort {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "popularity" => Some(Self::Popularity),
+            "date" => Some(Self::Date),
+            "rating" => Some(Self::Rating),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Popularity => "popularity",
+            Self::Date => "date",
+            Self::Rating => "rating",
+        }
+    }
+}
+
+/// Отложенное действие в чате; `at` — unix-время (сек), когда выполнить.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScheduledJob {
+    pub id: u64,
+    pub chat_id: i64,
+    pub at: i64,
+    pub kind: JobKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JobKind {
+    /// запустить голосование по списку
+    Vote,
+    /// напоминание с текстом
+    Remind(String),
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 struct FileState {
+    #[serde(default)]
     version: u32,
     // chat_id -> movies
     chats: HashMap<i64, Vec<StoredMovie>>,
+    // chat_id -> бэклог; `chats` — это шортлист, по нему идёт голосование
+    #[serde(default)]
+    backlogs: HashMap<i64, Vec<StoredMovie>>,
+    // chat_id -> настройки (если нет — дефолтные)
+    #[serde(default)]
+    settings: HashMap<i64, ChatSettings>,
+    // запланированные действия (переживают рестарт)
+    #[serde(default)]
+    jobs: Vec<ScheduledJob>,
+    #[serde(default)]
+    next_job_id: u64,
+    // STORE_NAMESPACE -> данные другого бота; своё (без namespace) лежит на верхнем уровне
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    namespaces: HashMap<String, FileState>,
+}
+
+/// Чем закончился перенос из бэклога в шортлист.
+#[derive(Debug)]
+pub enum Promote {
+    /// перенесён (или уже был в шортлисте — тогда просто убран из бэклога)
+    Moved(StoredMovie),
+    NoSuchItem,
+    ShortlistFull,
 }
 
 #[derive(Clone)]
 pub struct Storage {
     inner: Arc<RwLock<FileState>>,
     path: PathBuf,
+    // раздел файла этого бота (STORE_NAMESPACE); None — верхний уровень
+    namespace: Option<String>,
+    // держим эксклюзивный лок, пока жив хотя бы один клон
+    _lock: Arc<File>,
 }
 
 impl Storage {
     pub async fn new(path: impl Into<PathBuf>) -> anyhow::Result<Self> {
+        Self::with_namespace(path, None).await
+    }
+
+    /// Стор в своём разделе общего файла: несколько ботов (STORE_NAMESPACE)
+    /// делят один файл, но списки у каждого свои.
+    pub async fn with_namespace(
+        path: impl Into<PathBuf>,
+        namespace: Option<String>,
+    ) -> anyhow::Result<Self> {
         let path = path.into();
-        let state = if fs::try_exists(&path).await.unwrap_or(false) {
+        if let Some(ns) = &namespace {
+            if ns.is_empty()
+                || !ns
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+            {
+                anyhow::bail!("invalid store namespace {ns:?}: use [A-Za-z0-9_-]");
+            }
+        }
+        let lock = lock_store(&path, namespace.as_deref())?;
+        let root = if fs::try_exists(&path).await.unwrap_or(false) {
             let data = fs::read(&path).await?;
             match serde_json::from_slice::<FileState>(&data) {
-                Ok(mut s) => {
-                    if s.version == 0 {
-                        s.version = 1;
-                    }
-                    s
+                Ok(s) => Some(s),
+                Err(e) => {
+                    quarantine_corrupt(&path, &e).await;
+                    load_backup(&path).await
                 }
-                Err(_) => FileState {
-                    version: 1,
-                    ..Default::default()
-                },
             }
         } else {
-            FileState {
-                version: 1,
-                ..Default::default()
-            }
+            None
         };
+        let state = root
+            .and_then(|root| select_namespace(root, namespace.as_deref()))
+            .map(migrate)
+            .unwrap_or_else(|| FileState {
+                version: STORE_VERSION,
+                ..Default::default()
+            });
         Ok(Self {
             inner: Arc::new(RwLock::new(state)),
             path,
+            namespace,
+            _lock: Arc::new(lock),
         })
     }
 
@@ -66,6 +259,29 @@ impl Storage {
         guard.chats.get(&chat_id).cloned().unwrap_or_default()
     }
 
+    /// Сколько фильмов в списке — без копии самого списка.
+    pub async fn count(&self, chat_id: i64) -> usize {
+        let guard = self.inner.read().await;
+        guard.chats.get(&chat_id).map_or(0, Vec::len)
+    }
+
+    pub async fn settings(&self, chat_id: i64) -> ChatSettings {
+        let guard = self.inner.read().await;
+        guard.settings.get(&chat_id).cloned().unwrap_or_default()
+    }
+
+    pub async fn update_settings(
+        &self,
+        chat_id: i64,
+        f: impl FnOnce(&mut ChatSettings),
+    ) -> anyhow::Result<()> {
+        {
+            let mut guard = self.inner.write().await;
+            f(guard.settings.entry(chat_id).or_default());
+        }
+        self.flush().await
+    }
+
     pub async fn remove_chat(&self, chat_id: i64) -> anyhow::Result<()> {
         {
             let mut guard = self.inner.write().await;
@@ -74,6 +290,48 @@ impl Storage {
         self.flush().await
     }
 
+    /// Всё, что знаем о чате: список, бэклог, настройки и задания (бота удалили из группы).
+    pub async fn forget_chat(&self, chat_id: i64) -> anyhow::Result<()> {
+        // одной записью: промежуточное состояние не должно попасть в файл и бэкапы
+        {
+            let mut guard = self.inner.write().await;
+            guard.chats.remove(&chat_id);
+            guard.backlogs.remove(&chat_id);
+            guard.settings.remove(&chat_id);
+            guard.jobs.retain(|j| j.chat_id != chat_id);
+        }
+        self.flush().await
+    }
+
+    /// Заменяет список чата целиком (перенос между чатами); лишнее сверх лимита отбрасывается.
+    pub async fn put(&self, chat_id: i64, mut list: Vec<StoredMovie>) -> anyhow::Result<()> {
+        list.truncate(MAX_LIST_LEN);
+        {
+            let mut guard = self.inner.write().await;
+            guard.chats.insert(chat_id, list);
+        }
+        self.flush().await
+    }
+
+    /// Как `put`, но только в пустой список; false — пока готовили, в него уже добавили.
+    pub async fn put_if_empty(
+        &self,
+        chat_id: i64,
+        mut list: Vec<StoredMovie>,
+    ) -> anyhow::Result<bool> {
+        list.truncate(MAX_LIST_LEN);
+        {
+            let mut guard = self.inner.write().await;
+            let entry = guard.chats.entry(chat_id).or_default();
+            if !entry.is_empty() {
+                return Ok(false);
+            }
+            *entry = list;
+        }
+        self.flush().await?;
+        Ok(true)
+    }
+
     pub async fn add_movie(&self, chat_id: i64, m: StoredMovie) -> anyhow::Result<bool> {
         // возвращает: true — если добавили, false — если уже был/переполнен
         let added;
@@ -83,7 +341,7 @@ impl Storage {
             if entry
                 .iter()
                 .any(|x| x.id == m.id && x.media_type == m.media_type)
-                || entry.len() >= 10
+                || entry.len() >= MAX_LIST_LEN
             {
                 added = false;
             } else {
@@ -97,6 +355,100 @@ impl Storage {
         Ok(added)
     }
 
+    /// Добавляет чужой список к текущему: чего ещё нет — в конец, пока есть место.
+    /// Возвращает (добавлено, пропущено — дубликаты и не влезшие).
+    pub async fn merge_movies(
+        &self,
+        chat_id: i64,
+        movies: Vec<StoredMovie>,
+    ) -> anyhow::Result<(usize, usize)> {
+        let total = movies.len();
+        let added = {
+            let mut guard = self.inner.write().await;
+            let entry = guard.chats.entry(chat_id).or_default();
+            let mut added = 0;
+            for m in movies {
+                if entry.len() >= MAX_LIST_LEN {
+                    break;
+                }
+                if entry
+                    .iter()
+                    .any(|x| x.id == m.id && x.media_type == m.media_type)
+                {
+                    continue;
+                }
+                entry.push(m);
+                added += 1;
+            }
+            added
+        };
+        if added > 0 {
+            self.flush().await?;
+        }
+        Ok((added, total - added))
+    }
+
+    pub async fn backlog(&self, chat_id: i64) -> Vec<StoredMovie> {
+        let guard = self.inner.read().await;
+        guard.backlogs.get(&chat_id).cloned().unwrap_or_default()
+    }
+
+    /// false — уже есть в бэклоге или шортлисте, либо бэклог переполнен.
+    pub async fn add_to_backlog(&self, chat_id: i64, m: StoredMovie) -> anyhow::Result<bool> {
+        let added;
+        {
+            let mut guard = self.inner.write().await;
+            let same = |x: &StoredMovie| x.id == m.id && x.media_type == m.media_type;
+            let in_shortlist = guard
+                .chats
+                .get(&chat_id)
+                .is_some_and(|list| list.iter().any(same));
+            let entry = guard.backlogs.entry(chat_id).or_default();
+            if in_shortlist || entry.iter().any(same) || entry.len() >= MAX_BACKLOG_LEN {
+                added = false;
+            } else {
+                entry.push(m);
+                added = true;
+            }
+        }
+        if added {
+            self.flush().await?;
+        }
+        Ok(added)
+    }
+
+    /// Переносит элемент бэклога (индекс с 0) в конец шортлиста.
+    pub async fn promote(&self, chat_id: i64, index: usize) -> anyhow::Result<Promote> {
+        let result;
+        {
+            let mut guard = self.inner.write().await;
+            let Some(m) = guard
+                .backlogs
+                .get(&chat_id)
+                .and_then(|b| b.get(index))
+                .cloned()
+            else {
+                return Ok(Promote::NoSuchItem);
+            };
+            let list = guard.chats.entry(chat_id).or_default();
+            let listed = list
+                .iter()
+                .any(|x| x.id == m.id && x.media_type == m.media_type);
+            if !listed && list.len() >= MAX_LIST_LEN {
+                return Ok(Promote::ShortlistFull);
+            }
+            if !listed {
+                list.push(m.clone());
+            }
+            if let Some(b) = guard.backlogs.get_mut(&chat_id) {
+                b.remove(index);
+            }
+            result = Promote::Moved(m);
+        }
+        self.flush().await?;
+        Ok(result)
+    }
+
     pub async fn delete_movie(
         &self,
         chat_id: i64,
@@ -118,35 +470,434 @@ impl Storage {
         Ok(removed)
     }
 
+    /// Оставляет в списке только фильмы, для которых `keep` вернул true;
+    /// возвращает, сколько удалено (одна запись на диск на всё).
+    pub async fn retain(
+        &self,
+        chat_id: i64,
+        keep: impl Fn(&StoredMovie) -> bool,
+    ) -> anyhow::Result<usize> {
+        let removed = {
+            let mut guard = self.inner.write().await;
+            match guard.chats.get_mut(&chat_id) {
+                Some(list) => {
+                    let before = list.len();
+                    list.retain(|m| keep(m));
+                    before - list.len()
+                }
+                None => 0,
+            }
+        };
+        if removed > 0 {
+            self.flush().await?;
+        }
+        Ok(removed)
+    }
+
+    /// Переключает отметку «просмотрено». None — фильма нет в списке.
+    pub async fn toggle_watched(
+        &self,
+        chat_id: i64,
+        movie_id: u64,
+        media_kind: MediaKind,
+    ) -> anyhow::Result<Option<bool>> {
+        let toggled = {
+            let mut guard = self.inner.write().await;
+            guard.chats.get_mut(&chat_id).and_then(|list| {
+                list.iter_mut()
+                    .find(|m| m.id == movie_id && m.media_type == media_kind)
+                    .map(|m| {
+                        m.watched = !m.watched;
+                        m.watched
+                    })
+            })
+        };
+        if toggled.is_some() {
+            self.flush().await?;
+        }
+        Ok(toggled)
+    }
+
+    /// Своё название для элемента списка (индекс с 0); None — вернуть название TMDb.
+    pub async fn rename_movie(
+        &self,
+        chat_id: i64,
+        index: usize,
+        custom_title: Option<String>,
+    ) -> anyhow::Result<bool> {
+        let renamed = {
+            let mut guard = self.inner.write().await;
+            match guard.chats.get_mut(&chat_id).and_then(|l| l.get_mut(index)) {
+                Some(m) => {
+                    m.custom_title = custom_title;
+                    true
+                }
+                None => false,
+            }
+        };
+        if renamed {
+            self.flush().await?;
+        }
+        Ok(renamed)
+    }
+
+    /// Сортирует текущий список под локом; рейтинги из `ratings` подставляются по
+    /// (id, тип), так что добавленное за время запросов к TMDb не теряется.
+    pub async fn reorder(
+        &self,
+        chat_id: i64,
+        ratings: &HashMap<(u64, &'static str), f64>,
+        sort: impl FnOnce(&mut [StoredMovie]),
+    ) -> anyhow::Result<()> {
+        {
+            let mut guard = self.inner.write().await;
+            let Some(list) = guard.chats.get_mut(&chat_id) else {
+                return Ok(());
+            };
+            for m in list.iter_mut() {
+                if let Some(r) = ratings.get(&(m.id, m.media_type.as_str())) {
+                    m.vote_average = Some(*r);
+                }
+            }
+            sort(list);
+        }
+        self.flush().await
+    }
+
+    /// Ставит задание; возвращает его id.
+    pub async fn add_job(&self, chat_id: i64, at: i64, kind: JobKind) -> anyhow::Result<u64> {
+        let id = {
+            let mut guard = self.inner.write().await;
+            guard.next_job_id += 1;
+            let id = guard.next_job_id;
+            guard.jobs.push(ScheduledJob {
+                id,
+                chat_id,
+                at,
+                kind,
+            });
+            id
+        };
+        self.flush().await?;
+        Ok(id)
+    }
+
+    pub async fn jobs(&self, chat_id: i64) -> Vec<ScheduledJob> {
+        let guard = self.inner.read().await;
+        guard
+            .jobs
+            .iter()
+            .filter(|j| j.chat_id == chat_id)
+            .cloned()
+            .collect()
+    }
+
+    /// Снимает задания чата, подходящие под `pred`; возвращает, сколько сняли.
+    pub async fn cancel_jobs(
+        &self,
+        chat_id: i64,
+        pred: impl Fn(&ScheduledJob) -> bool,
+    ) -> anyhow::Result<usize> {
+        let removed = {
+            let mut guard = self.inner.write().await;
+            let before = guard.jobs.len();
+            guard.jobs.retain(|j| !(j.chat_id == chat_id && pred(j)));
+            before - guard.jobs.len()
+        };
+        if removed > 0 {
+            self.flush().await?;
+        }
+        Ok(removed)
+    }
+
+    /// Забирает задания, время которых наступило (к `now`), и убирает их из стора.
+    pub async fn take_due_jobs(&self, now: i64) -> anyhow::Result<Vec<ScheduledJob>> {
+        let due: Vec<ScheduledJob> = {
+            let mut guard = self.inner.write().await;
+            let (due, rest) = std::mem::take(&mut guard.jobs)
+                .into_iter()
+                .partition(|j| j.at <= now);
+            guard.jobs = rest;
+            due
+        };
+        if !due.is_empty() {
+            self.flush().await?;
+        }
+        Ok(due)
+    }
+
     async fn flush(&self) -> anyhow::Result<()> {
-        // клонируем снапшот под read‑локом и пишем вне лока (без дедлоков)
+        // файл может делить другой процесс со своим namespace: перечитываем его
+        // под межпроцессным локом и заменяем только свой раздел
+        let _write = lock_for_write(&self.path).await?;
+        // снапшот — уже под локом записи: иначе два flush могут взять лок
+        // в обратном порядке, и старое состояние затрёт новое
         let snapshot = {
             let guard = self.inner.read().await;
-            serde_json::to_vec_pretty(&*guard)?
+            guard.clone()
+        };
+        let root = self.with_snapshot(snapshot).await?;
+        let data = serde_json::to_vec_pretty(&root)?;
+        write_atomic(&self.path, &data).await
+    }
+
+    // файл целиком (все разделы) с нашим разделом из snapshot; вызывать под lock_for_write
+    async fn with_snapshot(&self, snapshot: FileState) -> anyhow::Result<FileState> {
+        let mut root = read_root(&self.path).await?;
+        match &self.namespace {
+            None => {
+                let others = std::mem::take(&mut root.namespaces);
+                root = snapshot;
+                root.namespaces = others;
+            }
+            Some(ns) => {
+                if root.version == 0 {
+                    root.version = STORE_VERSION;
+                }
+                root.namespaces.insert(ns.clone(), snapshot);
+            }
+        }
+        Ok(root)
+    }
+
+    /// Полная копия хранилища (все чаты и разделы) в `dir` с датой в имени;
+    /// возвращает путь к снимку.
+    pub async fn backup(&self, dir: &std::path::Path) -> anyhow::Result<PathBuf> {
+        let root = {
+            let _write = lock_for_write(&self.path).await?;
+            let snapshot = {
+                let guard = self.inner.read().await;
+                guard.clone()
+            };
+            self.with_snapshot(snapshot).await?
+        };
+        fs::create_dir_all(dir).await?;
+        let stem = self
+            .path
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .unwrap_or("store");
+        let stamp = chrono::Local::now().format("%Y%m%d-%H%M%S");
+        let target = dir.join(format!("{stem}-{stamp}.json"));
+        write_atomic(&target, &serde_json::to_vec_pretty(&root)?).await?;
+        Ok(target)
+    }
+}
+
+/* ====== Атомарная запись ======
+   tmp → fsync → ротация копий → rename → fsync каталога: без fsync после
+   падения сразу за rename на некоторых ФС остаётся пустой/обрезанный файл */
+async fn write_atomic(path: &std::path::Path, data: &[u8]) -> anyhow::Result<()> {
+    use tokio::io::AsyncWriteExt;
+
+    let tmp = path.with_extension("json.tmp");
+    let mut file = fs::File::create(&tmp).await?;
+    file.write_all(data).await?;
+    file.sync_all().await?;
+    drop(file);
+    rotate_backups(path).await;
+    fs::rename(&tmp, path).await?;
+    sync_dir(path).await;
+    Ok(())
+}
+
+// запись о rename живёт в каталоге; на Windows каталог так не открыть — пропускаем
+#[cfg(unix)]
+async fn sync_dir(path: &std::path::Path) {
+    let dir = match path.parent() {
+        Some(d) if !d.as_os_str().is_empty() => d,
+        _ => std::path::Path::new("."),
+    };
+    let res = match fs::File::open(dir).await {
+        Ok(d) => d.sync_all().await,
+        Err(e) => Err(e),
+    };
+    if let Err(e) = res {
+        tracing::warn!("fsync of {} failed: {e}", dir.display());
+    }
+}
+
+#[cfg(not(unix))]
+async fn sync_dir(_path: &std::path::Path) {}
+
+/* ====== Разделы (STORE_NAMESPACE) ======
+   без namespace — верхний уровень файла, как раньше; с namespace — запись в
+   `namespaces`; чужие разделы при записи сохраняются как есть */
+fn select_namespace(mut root: FileState, namespace: Option<&str>) -> Option<FileState> {
+    match namespace {
+        None => {
+            root.namespaces.clear();
+            Some(root)
+        }
+        Some(ns) => root.namespaces.remove(ns),
+    }
+}
+
+/* файл перед записью: нет файла — пустой корень; не читается или не парсится —
+   ошибка, и flush его не трогает: иначе вместе с битым файлом пропали бы
+   разделы других ботов */
+async fn read_root(path: &std::path::Path) -> anyhow::Result<FileState> {
+    let data = match fs::read(path).await {
+        Ok(data) => data,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return Ok(FileState {
+                version: STORE_VERSION,
+                ..Default::default()
+            });
+        }
+        Err(e) => anyhow::bail!("store {} unreadable before write: {e}", path.display()),
+    };
+    serde_json::from_slice(&data)
+        .map_err(|e| anyhow::anyhow!("store {} is corrupt, not writing: {e}", path.display()))
+}
+
+/* ====== Резервные копии ======
+   перед каждой записью текущий файл уходит в <store>.1, старые сдвигаются
+   до <store>.N; при битом основном файле читаем самую свежую живую копию */
+fn backup_path(path: &std::path::Path, n: usize) -> PathBuf {
+    path.with_extension(format!("json.{n}"))
+}
+
+async fn rotate_backups(path: &std::path::Path) {
+    if !fs::try_exists(path).await.unwrap_or(false) {
+        return;
+    }
+    for n in (1..BACKUP_COUNT).rev() {
+        let from = backup_path(path, n);
+        if fs::try_exists(&from).await.unwrap_or(false) {
+            let _ = fs::rename(&from, backup_path(path, n + 1)).await;
+        }
+    }
+    // копия, а не rename: основной файл не должен пропадать ни на миг
+    if let Err(e) = fs::copy(path, backup_path(path, 1)).await {
+        tracing::warn!("store backup failed: {e}");
+    }
+}
+
+/// Битый файл не перезаписываем: откладываем в <store>.corrupt.<unix_ts> для ручного разбора.
+async fn quarantine_corrupt(path: &std::path::Path, err: &serde_json::Error) {
+    let ts = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let aside = path.with_extension(format!("json.corrupt.{ts}"));
+    match fs::rename(path, &aside).await {
+        Ok(()) => tracing::error!(
+            "store {} is corrupt ({err}); moved to {}, trying backups",
+            path.display(),
+            aside.display()
+        ),
+        Err(e) => tracing::error!(
+            "store {} is corrupt ({err}) and could not be moved aside: {e}",
+            path.display()
+        ),
+    }
+}
+
+async fn load_backup(path: &std::path::Path) -> Option<FileState> {
+    for n in 1..=BACKUP_COUNT {
+        let backup = backup_path(path, n);
+        let Ok(data) = fs::read(&backup).await else {
+            continue;
         };
-        let tmp = self.path.with_extension("json.tmp");
-        fs::write(&tmp, &snapshot).await?;
-        fs::rename(&tmp, &self.path).await?;
-        Ok(())
+        if let Ok(s) = serde_json::from_slice::<FileState>(&data) {
+            tracing::warn!("store restored from backup {}", backup.display());
+            return Some(migrate(s));
+        }
+    }
+    None
+}
+
+/* ====== Миграции формата ======
+   шаги применяются по порядку от версии файла до STORE_VERSION;
+   новые поля StoredMovie заполняются serde-дефолтами ещё при чтении */
+fn migrate(mut state: FileState) -> FileState {
+    if state.version > STORE_VERSION {
+        tracing::warn!(
+            "store version {} is newer than supported {}",
+            state.version,
+            STORE_VERSION
+        );
+        return state;
+    }
+    // 0 -> 1: файлы без версии, формат тот же
+    if state.version < 1 {
+        state.version = 1;
+    }
+    state
+}
+
+/* ====== Защита от второго процесса ======
+   advisory-лок на соседнем файле <store>.lock (для namespace — <store>.<ns>.lock):
+   сам файл стора подменяется через rename, поэтому лочить его бесполезно */
+fn lock_store(path: &std::path::Path, namespace: Option<&str>) -> anyhow::Result<File> {
+    let lock_path = match namespace {
+        None => path.with_extension("json.lock"),
+        Some(ns) => path.with_extension(format!("json.{ns}.lock")),
+    };
+    let file = std::fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&lock_path)?;
+    if file.try_lock_exclusive().is_err() {
+        anyhow::bail!("store is locked by another process: {}", path.display());
+    }
+    Ok(file)
+}
+
+// короткий лок на время перечитывания и записи файла (ждём, а не падаем)
+async fn lock_for_write(path: &std::path::Path) -> anyhow::Result<File> {
+    let lock_path = path.with_extension("json.write.lock");
+    tokio::task::spawn_blocking(move || {
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&lock_path)?;
+        file.lock_exclusive()?;
+        Ok(file)
+    })
+    .await?
+}
+
+/// Путь к тестовому стору в собственном временном каталоге: блокировки,
+/// бэкапы и прочие соседние файлы ложатся туда же и убираются
+/// `remove_temp_store` одним махом.
+#[cfg(test)]
+pub(crate) fn temp_store_path(name: &str) -> PathBuf {
+    use std::sync::atomic::{AtomicU64, Ordering};
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_nanos();
+    let counter = COUNTER.fetch_add(1, Ordering::SeqCst);
+    let dir = std::env::temp_dir().join(format!(
+        "kinoclub_{}_{}_{}_{}",
+        name,
+        std::process::id(),
+        now,
+        counter
+    ));
+    std::fs::create_dir_all(&dir).unwrap();
+    dir.join("store.json")
+}
+
+#[cfg(test)]
+pub(crate) fn remove_temp_store(path: &std::path::Path) {
+    if let Some(dir) = path.parent() {
+        let _ = std::fs::remove_dir_all(dir);
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::sync::atomic::{AtomicU64, Ordering};
     use tokio::fs;
 
-    static TEST_COUNTER: AtomicU64 = AtomicU64::new(0);
-
     async fn setup_temp_storage() -> (Storage, PathBuf) {
-        let mut tmp_path = PathBuf::from("tests/data");
-        let now = std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .unwrap()
-            .as_nanos();
-        let counter = TEST_COUNTER.fetch_add(1, Ordering::SeqCst);
-        tmp_path.push(format!("test_storage_{}_{}.json", now, counter));
+        let tmp_path = temp_store_path("storage");
         let storage = Storage::new(tmp_path.clone())
             .await
             .expect("Failed to create storage");
@@ -157,7 +908,7 @@ mod tests {
     async fn test_storage_new_empty() {
         let (storage, path) = setup_temp_storage().await;
         assert_eq!(storage.get(123).await.len(), 0);
-        let _ = fs::remove_file(path).await;
+        remove_temp_store(&path);
     }
 
     #[tokio::test]
@@ -170,6 +921,9 @@ mod tests {
             media_type: MediaKind::Movie,
             poster_path: None,
             release_date: None,
+            watched: false,
+            vote_average: None,
+            custom_title: None,
         };
 
         let added = storage.add_movie(123, movie.clone()).await.unwrap();
@@ -179,7 +933,7 @@ mod tests {
         assert_eq!(movies.len(), 1);
         assert_eq!(movies[0].id, 1);
 
-        let _ = fs::remove_file(path).await;
+        remove_temp_store(&path);
     }
 
     #[tokio::test]
@@ -192,6 +946,9 @@ mod tests {
             media_type: MediaKind::Movie,
             poster_path: None,
             release_date: None,
+            watched: false,
+            vote_average: None,
+            custom_title: None,
         };
 
         storage.add_movie(123, movie.clone()).await.unwrap();
@@ -199,7 +956,7 @@ mod tests {
         assert!(!added);
         assert_eq!(storage.get(123).await.len(), 1);
 
-        let _ = fs::remove_file(path).await;
+        remove_temp_store(&path);
     }
 
     #[tokio::test]
@@ -213,6 +970,9 @@ mod tests {
                 media_type: MediaKind::Movie,
                 poster_path: None,
                 release_date: None,
+                watched: false,
+                vote_average: None,
+                custom_title: None,
             };
             assert!(storage.add_movie(123, movie).await.unwrap());
         }
@@ -224,12 +984,17 @@ mod tests {
             media_type: MediaKind::Movie,
             poster_path: None,
             release_date: None,
+            watched: false,
+            vote_average: None,
+            custom_title: None,
         };
         let added = storage.add_movie(123, extra_movie).await.unwrap();
         assert!(!added);
         assert_eq!(storage.get(123).await.len(), 10);
+        assert_eq!(storage.count(123).await, 10);
+        assert_eq!(storage.count(124).await, 0);
 
-        let _ = fs::remove_file(path).await;
+        remove_temp_store(&path);
     }
 
     #[tokio::test]
@@ -242,6 +1007,9 @@ mod tests {
             media_type: MediaKind::Movie,
             poster_path: None,
             release_date: None,
+            watched: false,
+            vote_average: None,
+            custom_title: None,
         };
 
         storage.add_movie(123, movie).await.unwrap();
@@ -258,22 +1026,73 @@ mod tests {
             .unwrap();
         assert!(!deleted_again);
 
-        let _ = fs::remove_file(path).await;
+        remove_temp_store(&path);
     }
 
     #[tokio::test]
-    async fn test_persistence() {
-        let (tmp_path, storage) = {
-            let mut p = PathBuf::from("tests/data");
-            let now = std::time::SystemTime::now()
-                .duration_since(std::time::UNIX_EPOCH)
-                .unwrap()
-                .as_nanos();
-            let counter = TEST_COUNTER.fetch_add(1, Ordering::SeqCst);
-            p.push(format!("test_persistence_{}_{}.json", now, counter));
-            let s = Storage::new(p.clone()).await.unwrap();
-            (p.clone(), s)
+    async fn test_retain_drops_watched() {
+        let (storage, path) = setup_temp_storage().await;
+        for (id, watched) in [(1, true), (2, false), (3, true)] {
+            let movie = StoredMovie {
+                id,
+                title: format!("Movie {}", id),
+                original_title: format!("Movie {}", id),
+                media_type: MediaKind::Movie,
+                poster_path: None,
+                release_date: None,
+                watched,
+                vote_average: None,
+                custom_title: None,
+            };
+            storage.add_movie(123, movie).await.unwrap();
+        }
+
+        assert_eq!(storage.retain(123, |m| !m.watched).await.unwrap(), 2);
+        let left: Vec<u64> = storage.get(123).await.iter().map(|m| m.id).collect();
+        assert_eq!(left, vec![2]);
+        assert_eq!(storage.retain(123, |m| !m.watched).await.unwrap(), 0);
+        assert_eq!(storage.retain(999, |_| false).await.unwrap(), 0);
+
+        remove_temp_store(&path);
+    }
+
+    #[tokio::test]
+    async fn test_merge_movies() {
+        let (storage, path) = setup_temp_storage().await;
+        let movie = |id: u64| StoredMovie {
+            id,
+            title: format!("Movie {}", id),
+            original_title: format!("Movie {}", id),
+            media_type: MediaKind::Movie,
+            poster_path: None,
+            release_date: None,
+            watched: false,
+            vote_average: None,
+            custom_title: None,
         };
+        for id in 1..=8 {
+            storage.add_movie(123, movie(id)).await.unwrap();
+        }
+
+        // 7 и 8 уже есть, 9 и 10 влезают, 11 — сверх лимита
+        let merged = storage
+            .merge_movies(
+                123,
+                vec![movie(7), movie(9), movie(8), movie(10), movie(11)],
+            )
+            .await
+            .unwrap();
+        assert_eq!(merged, (2, 3));
+        let ids: Vec<u64> = storage.get(123).await.iter().map(|m| m.id).collect();
+        assert_eq!(ids, (1..=10).collect::<Vec<_>>());
+
+        remove_temp_store(&path);
+    }
+
+    #[tokio::test]
+    async fn test_persistence() {
+        let tmp_path = temp_store_path("persistence");
+        let storage = Storage::new(tmp_path.clone()).await.unwrap();
 
         let movie = StoredMovie {
             id: 1,
@@ -282,8 +1101,12 @@ mod tests {
             media_type: MediaKind::Movie,
             poster_path: None,
             release_date: None,
+            watched: false,
+            vote_average: None,
+            custom_title: None,
         };
         storage.add_movie(123, movie).await.unwrap();
+        drop(storage);
 
         // Re-load storage from the same file
         let reloaded_storage = Storage::new(tmp_path.clone()).await.unwrap();
@@ -291,6 +1114,571 @@ mod tests {
         assert_eq!(movies.len(), 1);
         assert_eq!(movies[0].title, "Persistent Movie");
 
-        let _ = fs::remove_file(tmp_path).await;
+        remove_temp_store(&tmp_path);
+    }
+
+    #[tokio::test]
+    async fn test_toggle_watched() {
+        let (storage, path) = setup_temp_storage().await;
+        let movie = StoredMovie {
+            id: 1,
+            title: "Test Movie".to_string(),
+            original_title: "Test Movie".to_string(),
+            media_type: MediaKind::Movie,
+            poster_path: None,
+            release_date: None,
+            watched: false,
+            vote_average: None,
+            custom_title: None,
+        };
+        storage.add_movie(123, movie).await.unwrap();
+
+        let state = storage
+            .toggle_watched(123, 1, MediaKind::Movie)
+            .await
+            .unwrap();
+        assert_eq!(state, Some(true));
+        assert!(storage.get(123).await[0].watched);
+
+        let state = storage
+            .toggle_watched(123, 1, MediaKind::Movie)
+            .await
+            .unwrap();
+        assert_eq!(state, Some(false));
+
+        let missing = storage.toggle_watched(123, 2, MediaKind::Tv).await.unwrap();
+        assert_eq!(missing, None);
+
+        remove_temp_store(&path);
+    }
+
+    #[tokio::test]
+    async fn test_rename_movie() {
+        let (storage, path) = setup_temp_storage().await;
+        let movie = StoredMovie {
+            id: 1,
+            title: "Test Movie".to_string(),
+            original_title: "Test Movie".to_string(),
+            media_type: MediaKind::Movie,
+            poster_path: None,
+            release_date: None,
+            watched: false,
+            vote_average: None,
+            custom_title: None,
+        };
+        storage.add_movie(123, movie).await.unwrap();
+
+        assert!(storage
+            .rename_movie(123, 0, Some("Тот самый".to_string()))
+            .await
+            .unwrap());
+        assert!(!storage.rename_movie(123, 5, None).await.unwrap());
+        drop(storage);
+
+        let storage = Storage::new(path.clone()).await.unwrap();
+        let m = &storage.get(123).await[0];
+        assert_eq!(m.display_title(), "Тот самый");
+        assert_eq!(m.title, "Test Movie");
+        storage.rename_movie(123, 0, None).await.unwrap();
+        assert_eq!(storage.get(123).await[0].display_title(), "Test Movie");
+
+        drop(storage);
+        remove_temp_store(&path);
+    }
+
+    #[test]
+    fn test_stored_movie_without_watched_field() {
+        let json = r#"{
+            "id": 1,
+            "title": "Old",
+            "original_title": "Old",
+            "poster_path": null,
+            "release_date": null
+        }"#;
+        let m: StoredMovie = serde_json::from_str(json).unwrap();
+        assert!(!m.watched);
+        assert_eq!(m.media_type, MediaKind::Movie);
+    }
+
+    #[tokio::test]
+    async fn test_reorder_keeps_concurrent_adds() {
+        let (storage, path) = setup_temp_storage().await;
+        let movie = |id: u64, media_type: MediaKind| StoredMovie {
+            id,
+            title: format!("Movie {}", id),
+            original_title: format!("Movie {}", id),
+            media_type,
+            poster_path: None,
+            release_date: None,
+            watched: false,
+            vote_average: None,
+            custom_title: None,
+        };
+        storage
+            .add_movie(123, movie(1, MediaKind::Movie))
+            .await
+            .unwrap();
+        storage
+            .add_movie(123, movie(2, MediaKind::Movie))
+            .await
+            .unwrap();
+        // пока ходили за рейтингами, в список добавили сериал с тем же id
+        storage
+            .add_movie(123, movie(1, MediaKind::Tv))
+            .await
+            .unwrap();
+
+        let ratings = HashMap::from([((1, "movie"), 6.0), ((2, "movie"), 8.0)]);
+        storage
+            .reorder(123, &ratings, |list| {
+                list.sort_by(|a, b| {
+                    b.vote_average
+                        .unwrap_or(0.0)
+                        .total_cmp(&a.vote_average.unwrap_or(0.0))
+                })
+            })
+            .await
+            .unwrap();
+
+        let list = storage.get(123).await;
+        let got: Vec<_> = list
+            .iter()
+            .map(|m| (m.id, m.media_type.as_str(), m.vote_average))
+            .collect();
+        assert_eq!(
+            got,
+            vec![
+                (2, "movie", Some(8.0)),
+                (1, "movie", Some(6.0)),
+                (1, "tv", None)
+            ]
+        );
+
+        drop(storage);
+        remove_temp_store(&path);
+    }
+
+    #[tokio::test]
+    async fn test_put_if_empty() {
+        let (storage, path) = setup_temp_storage().await;
+        let movie = |id: u64| StoredMovie {
+            id,
+            title: format!("Movie {}", id),
+            original_title: format!("Movie {}", id),
+            media_type: MediaKind::Movie,
+            poster_path: None,
+            release_date: None,
+            watched: false,
+            vote_average: None,
+            custom_title: None,
+        };
+        assert!(storage
+            .put_if_empty(123, vec![movie(1), movie(2)])
+            .await
+            .unwrap());
+        // второй раз (повторное нажатие) — список уже не пуст, не трогаем
+        assert!(!storage.put_if_empty(123, vec![movie(3)]).await.unwrap());
+        let ids: Vec<u64> = storage.get(123).await.iter().map(|m| m.id).collect();
+        assert_eq!(ids, vec![1, 2]);
+
+        drop(storage);
+        remove_temp_store(&path);
+    }
+
+    #[tokio::test]
+    async fn test_settings_default_and_update() {
+        let (storage, path) = setup_temp_storage().await;
+        assert_eq!(storage.settings(123).await.region, "RU");
+        assert!(!storage.settings(123).await.include_adult);
+        assert!(!storage.settings(123).await.show_original_title);
+        assert_eq!(storage.settings(123).await.vote_question, None);
+        assert!(storage.settings(123).await.posters);
+
+        storage
+            .update_settings(123, |s| {
+                s.region = "US".to_string();
+                s.include_adult = true;
+                s.show_original_title = true;
+                s.vote_question = Some("Пятничное кино?".to_string());
+                s.posters = false;
+            })
+            .await
+            .unwrap();
+        assert_eq!(storage.settings(123).await.region, "US");
+        assert_eq!(storage.settings(456).await.region, "RU");
+        drop(storage);
+
+        let reloaded = Storage::new(path.clone()).await.unwrap();
+        assert_eq!(reloaded.settings(123).await.region, "US");
+        assert!(reloaded.settings(123).await.include_adult);
+        assert!(reloaded.settings(123).await.show_original_title);
+        assert_eq!(
+            reloaded.settings(123).await.vote_question.as_deref(),
+            Some("Пятничное кино?")
+        );
+        assert!(!reloaded.settings(123).await.posters);
+
+        remove_temp_store(&path);
+    }
+
+    #[tokio::test]
+    async fn test_second_instance_is_locked_out() {
+        let (storage, path) = setup_temp_storage().await;
+
+        let err = Storage::new(path.clone()).await.err().unwrap();
+        assert!(err
+            .to_string()
+            .contains("store is locked by another process"));
+
+        // клон держит тот же лок; после drop всех клонов стор снова открывается
+        let clone = storage.clone();
+        drop(storage);
+        assert!(Storage::new(path.clone()).await.is_err());
+        drop(clone);
+        assert!(Storage::new(path.clone()).await.is_ok());
+
+        remove_temp_store(&path);
+    }
+
+    #[tokio::test]
+    async fn test_backup_snapshots_all_namespaces() {
+        let (plain, path) = setup_temp_storage().await;
+        let ru = Storage::with_namespace(path.clone(), Some("ru".to_string()))
+            .await
+            .unwrap();
+        let movie = |id: u64| StoredMovie {
+            id,
+            title: format!("Movie {}", id),
+            original_title: format!("Movie {}", id),
+            media_type: MediaKind::Movie,
+            poster_path: None,
+            release_date: None,
+            watched: false,
+            vote_average: None,
+            custom_title: None,
+        };
+        plain.add_movie(1, movie(1)).await.unwrap();
+        ru.add_movie(2, movie(2)).await.unwrap();
+
+        let dir = path.with_extension("backups");
+        let snap = plain.backup(&dir).await.unwrap();
+        assert!(snap.starts_with(&dir));
+        let stem = path.file_stem().unwrap().to_str().unwrap();
+        let name = snap.file_name().unwrap().to_str().unwrap();
+        assert!(name.starts_with(stem) && name.ends_with(".json"));
+
+        let root: FileState = serde_json::from_slice(&fs::read(&snap).await.unwrap()).unwrap();
+        assert_eq!(root.chats[&1][0].id, 1);
+        assert_eq!(root.namespaces["ru"].chats[&2][0].id, 2);
+
+        drop((plain, ru));
+        let _ = fs::remove_dir_all(dir).await;
+        remove_temp_store(&path);
+    }
+
+    #[tokio::test]
+    async fn test_namespaces_share_file_but_not_lists() {
+        let (plain, path) = setup_temp_storage().await;
+        let movie = |id: u64| StoredMovie {
+            id,
+            title: format!("Movie {}", id),
+            original_title: format!("Movie {}", id),
+            media_type: MediaKind::Movie,
+            poster_path: None,
+            release_date: None,
+            watched: false,
+            vote_average: None,
+            custom_title: None,
+        };
+        let ru = Storage::with_namespace(path.clone(), Some("ru".to_string()))
+            .await
+            .unwrap();
+        let en = Storage::with_namespace(path.clone(), Some("en".to_string()))
+            .await
+            .unwrap();
+        assert!(
+            Storage::with_namespace(path.clone(), Some("ru".to_string()))
+                .await
+                .is_err()
+        );
+        assert!(
+            Storage::with_namespace(path.clone(), Some("../x".to_string()))
+                .await
+                .is_err()
+        );
+
+        plain.add_movie(123, movie(1)).await.unwrap();
+        ru.add_movie(123, movie(2)).await.unwrap();
+        en.add_movie(123, movie(3)).await.unwrap();
+        drop((plain, ru, en));
+
+        let ids = |list: Vec<StoredMovie>| list.iter().map(|m| m.id).collect::<Vec<_>>();
+        let plain = Storage::new(path.clone()).await.unwrap();
+        let ru = Storage::with_namespace(path.clone(), Some("ru".to_string()))
+            .await
+            .unwrap();
+        let en = Storage::with_namespace(path.clone(), Some("en".to_string()))
+            .await
+            .unwrap();
+        assert_eq!(ids(plain.get(123).await), vec![1]);
+        assert_eq!(ids(ru.get(123).await), vec![2]);
+        assert_eq!(ids(en.get(123).await), vec![3]);
+
+        // файл испортился, пока боты работают: запись отказывает и файл не трогает,
+        // а не переписывает его одним своим разделом
+        fs::write(&path, b"{ not json").await.unwrap();
+        assert!(ru.add_movie(123, movie(4)).await.is_err());
+        assert_eq!(fs::read(&path).await.unwrap(), b"{ not json");
+
+        drop((plain, ru, en));
+        remove_temp_store(&path);
+    }
+
+    #[test]
+    fn test_migrate_v1_bare_movies() {
+        let json = r#"{
+            "version": 1,
+            "chats": {
+                "123": [
+                    {"id": 1, "title": "A", "original_title": "A", "poster_path": null, "release_date": null},
+                    {"id": 2, "title": "B", "original_title": "B", "poster_path": "/b.jpg", "release_date": "2001-01-01"}
+                ]
+            }
+        }"#;
+        let state = migrate(serde_json::from_str::<FileState>(json).unwrap());
+        assert_eq!(state.version, STORE_VERSION);
+        let list = &state.chats[&123];
+        assert_eq!(list.len(), 2);
+        assert!(list
+            .iter()
+            .all(|m| m.media_type == MediaKind::Movie && !m.watched && m.vote_average.is_none()));
+        assert!(state.settings.is_empty());
+    }
+
+    #[test]
+    fn test_migrate_unversioned_and_current() {
+        let state = migrate(serde_json::from_str::<FileState>(r#"{"chats": {}}"#).unwrap());
+        assert_eq!(state.version, STORE_VERSION);
+
+        let current = FileState {
+            version: STORE_VERSION,
+            ..Default::default()
+        };
+        assert_eq!(migrate(current).version, STORE_VERSION);
+    }
+
+    #[tokio::test]
+    async fn test_migrated_file_rewritten_on_flush() {
+        let (storage, path) = setup_temp_storage().await;
+        drop(storage);
+        let old = r#"{"version": 1, "chats": {"123": [
+            {"id": 7, "title": "Old", "original_title": "Old", "poster_path": null, "release_date": null}
+        ]}}"#;
+        fs::write(&path, old).await.unwrap();
+
+        let storage = Storage::new(path.clone()).await.unwrap();
+        storage
+            .update_settings(123, |s| s.region = "US".to_string())
+            .await
+            .unwrap();
+
+        let raw: serde_json::Value =
+            serde_json::from_slice(&fs::read(&path).await.unwrap()).unwrap();
+        assert_eq!(raw["version"], STORE_VERSION);
+        assert_eq!(raw["chats"]["123"][0]["media_type"], "movie");
+        assert_eq!(raw["chats"]["123"][0]["watched"], false);
+
+        remove_temp_store(&path);
+    }
+
+    #[tokio::test]
+    async fn test_write_atomic_replaces_file_and_keeps_backup() {
+        let path = temp_store_path("atomic_write");
+        fs::write(&path, b"old").await.unwrap();
+
+        write_atomic(&path, b"new").await.unwrap();
+
+        assert_eq!(fs::read(&path).await.unwrap(), b"new");
+        assert_eq!(fs::read(backup_path(&path, 1)).await.unwrap(), b"old");
+        assert!(!fs::try_exists(path.with_extension("json.tmp"))
+            .await
+            .unwrap());
+
+        remove_temp_store(&path);
+    }
+
+    #[tokio::test]
+    async fn test_corrupt_store_recovers_from_backup() {
+        let (storage, path) = setup_temp_storage().await;
+        for id in 1..=3 {
+            let movie = StoredMovie {
+                id,
+                title: format!("Movie {id}"),
+                original_title: format!("Movie {id}"),
+                media_type: MediaKind::Movie,
+                poster_path: None,
+                release_date: None,
+                watched: false,
+                vote_average: None,
+                custom_title: None,
+            };
+            storage.add_movie(123, movie).await.unwrap();
+        }
+        drop(storage);
+
+        // .1 — состояние до последней записи, .2 — до предпоследней
+        assert!(fs::try_exists(backup_path(&path, 1)).await.unwrap());
+        assert!(fs::try_exists(backup_path(&path, 2)).await.unwrap());
+        assert!(!fs::try_exists(backup_path(&path, 3)).await.unwrap());
+
+        fs::write(&path, b"{ not json").await.unwrap();
+        let storage = Storage::new(path.clone()).await.unwrap();
+        assert_eq!(storage.get(123).await.len(), 2);
+        drop(storage);
+
+        // битый файл отложен, а не затёрт
+        let mut corrupt = Vec::new();
+        let mut dir = fs::read_dir(path.parent().unwrap()).await.unwrap();
+        let prefix = format!("{}.corrupt.", path.file_name().unwrap().to_string_lossy());
+        while let Some(entry) = dir.next_entry().await.unwrap() {
+            if entry.file_name().to_string_lossy().starts_with(&prefix) {
+                corrupt.push(entry.path());
+            }
+        }
+        assert_eq!(corrupt.len(), 1);
+        assert_eq!(fs::read(&corrupt[0]).await.unwrap(), b"{ not json");
+
+        remove_temp_store(&path);
+    }
+
+    #[tokio::test]
+    async fn test_jobs_survive_reload_and_fire_once() {
+        let (storage, path) = setup_temp_storage().await;
+        let a = storage.add_job(123, 1_000, JobKind::Vote).await.unwrap();
+        let b = storage.add_job(123, 2_000, JobKind::Vote).await.unwrap();
+        storage
+            .add_job(456, 1_500, JobKind::Remind("Смотрим!".to_string()))
+            .await
+            .unwrap();
+        assert_ne!(a, b);
+        drop(storage);
+
+        let storage = Storage::new(path.clone()).await.unwrap();
+        assert_eq!(storage.jobs(123).await.len(), 2);
+
+        let due = storage.take_due_jobs(1_600).await.unwrap();
+        assert!(due
+            .iter()
+            .any(|j| j.kind == JobKind::Remind("Смотрим!".to_string())));
+        let mut ids: Vec<i64> = due.iter().map(|j| j.chat_id).collect();
+        ids.sort();
+        assert_eq!(ids, vec![123, 456]);
+        assert!(storage.take_due_jobs(1_600).await.unwrap().is_empty());
+
+        let cancelled = storage.cancel_jobs(123, |j| j.id == b).await.unwrap();
+        assert_eq!(cancelled, 1);
+        assert!(storage.jobs(123).await.is_empty());
+
+        drop(storage);
+        remove_temp_store(&path);
+    }
+
+    #[tokio::test]
+    async fn test_forget_chat_drops_everything_for_that_chat() {
+        let (storage, path) = setup_temp_storage().await;
+        let movie = StoredMovie {
+            id: 1,
+            title: "Test Movie".to_string(),
+            original_title: "Test Movie".to_string(),
+            media_type: MediaKind::Movie,
+            poster_path: None,
+            release_date: None,
+            watched: false,
+            vote_average: None,
+            custom_title: None,
+        };
+        for chat in [123, 456] {
+            storage.add_movie(chat, movie.clone()).await.unwrap();
+            let later = StoredMovie {
+                id: 2,
+                ..movie.clone()
+            };
+            storage.add_to_backlog(chat, later).await.unwrap();
+            storage
+                .update_settings(chat, |s| s.region = "US".to_string())
+                .await
+                .unwrap();
+            storage.add_job(chat, 1_000, JobKind::Vote).await.unwrap();
+        }
+
+        storage.forget_chat(123).await.unwrap();
+        // одна запись: в .1 — состояние до forget_chat, а не полуочищенное
+        let prev: serde_json::Value =
+            serde_json::from_slice(&fs::read(backup_path(&path, 1)).await.unwrap()).unwrap();
+        assert_eq!(prev["chats"]["123"].as_array().unwrap().len(), 1);
+        assert!(storage.get(123).await.is_empty());
+        assert!(storage.backlog(123).await.is_empty());
+        assert_eq!(storage.settings(123).await.region, "RU");
+        assert!(storage.jobs(123).await.is_empty());
+        // другой чат не задет
+        assert_eq!(storage.count(456).await, 1);
+        assert_eq!(storage.backlog(456).await.len(), 1);
+        assert_eq!(storage.settings(456).await.region, "US");
+        assert_eq!(storage.jobs(456).await.len(), 1);
+
+        drop(storage);
+        remove_temp_store(&path);
+    }
+
+    #[tokio::test]
+    async fn test_backlog_and_promote() {
+        let (storage, path) = setup_temp_storage().await;
+        let movie = |id: u64| StoredMovie {
+            id,
+            title: format!("Movie {}", id),
+            original_title: format!("Movie {}", id),
+            media_type: MediaKind::Movie,
+            poster_path: None,
+            release_date: None,
+            watched: false,
+            vote_average: None,
+            custom_title: None,
+        };
+        for id in 1..=30 {
+            assert!(storage.add_to_backlog(123, movie(id)).await.unwrap());
+        }
+        assert!(!storage.add_to_backlog(123, movie(5)).await.unwrap());
+        for id in 101..=109 {
+            storage.add_movie(123, movie(id)).await.unwrap();
+        }
+
+        assert!(matches!(
+            storage.promote(123, 0).await.unwrap(),
+            Promote::Moved(m) if m.id == 1
+        ));
+        assert!(matches!(
+            storage.promote(123, 0).await.unwrap(),
+            Promote::ShortlistFull
+        ));
+        assert!(matches!(
+            storage.promote(123, 99).await.unwrap(),
+            Promote::NoSuchItem
+        ));
+        assert_eq!(storage.get(123).await.len(), 10);
+        assert_eq!(storage.backlog(123).await.len(), 29);
+        // уже в шортлисте — в бэклог не берём
+        assert!(!storage.add_to_backlog(123, movie(101)).await.unwrap());
+
+        drop(storage);
+        let storage = Storage::new(path.clone()).await.unwrap();
+        assert_eq!(storage.backlog(123).await[0].id, 2);
+
+        // /reset чистит только шортлист, бэклог остаётся
+        storage.remove_chat(123).await.unwrap();
+        assert!(storage.get(123).await.is_empty());
+        assert_eq!(storage.backlog(123).await.len(), 29);
+
+        drop(storage);
+        remove_temp_store(&path);
     }
 }
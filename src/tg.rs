@@ -1,20 +1,71 @@
-use crate::storage::{Storage, StoredMovie};
+use crate::format::{self, Lang};
+use crate::images::{clear_image_cache, fetch_image};
+use crate::ratelimit::RateLimiter;
+use crate::regions;
+use crate::storage::{
+    ChatSettings, JobKind, Promote, ScheduledJob, SearchSort, Storage, StoredMovie, MAX_LIST_LEN,
+};
 use crate::tmdb;
-use crate::tmdb::{MultiNorm, TmdbClient};
+use crate::tmdb::{ImageSize, MultiNorm, TmdbClient, WatchProviders};
+use chrono::{DateTime, Local, NaiveDate, NaiveDateTime, NaiveTime, TimeZone};
+use futures::stream::{self, StreamExt};
 use once_cell::sync::Lazy;
+use std::collections::HashMap;
 
 use teloxide::types::Message;
 use teloxide::{
     dispatching::{Dispatcher, UpdateFilterExt},
     prelude::*,
+    requests::Output,
     types::{
-        CallbackQuery, ChatId, InlineKeyboardButton, InlineKeyboardMarkup, InputFile, InputMedia,
-        InputMediaPhoto, ParseMode,
+        CallbackQuery, Chat, ChatId, ChatMemberKind, ChatMemberUpdated, InlineKeyboardButton,
+        InlineKeyboardMarkup, InlineQuery, InlineQueryResult, InlineQueryResultArticle, InputFile,
+        InputMedia, InputMediaPhoto, InputMessageContent, InputMessageContentText, ParseMode,
+        PollType,
     },
     utils::command::BotCommands,
-    RequestError,
+    ApiError, RequestError,
 };
 use moka::future::Cache;
+/* ====== Ошибки обработчиков ======
+   Telegram, TMDb и хранилище — разными вариантами, чтобы в логах было видно,
+   что именно сломалось; наверху report() пишет в лог и отвечает пользователю */
+#[derive(Debug, thiserror::Error)]
+enum BotError {
+    #[error(transparent)]
+    Tmdb(#[from] tmdb::TmdbErr),
+    #[error("хранилище: {0:#}")]
+    Storage(#[from] anyhow::Error),
+    #[error("Telegram: {0}")]
+    Telegram(#[from] RequestError),
+}
+
+type BotResult = Result<(), BotError>;
+
+const STORAGE_ERROR_TEXT: &str = "Не получилось сохранить изменения 😕 Попробуй ещё раз.";
+
+async fn report<R>(bot: &R, chat: Option<ChatId>, res: BotResult) -> ResponseResult<()>
+where
+    R: Requester<Err = RequestError>,
+{
+    let Err(e) = res else {
+        return Ok(());
+    };
+    tracing::error!("handler failed: {}", e);
+    let text = match &e {
+        BotError::Tmdb(t) => t.user_msg(),
+        BotError::Storage(_) => STORAGE_ERROR_TEXT,
+        // до Telegram и так не достучались — отвечать некуда
+        BotError::Telegram(_) => return Ok(()),
+    };
+    if let Some(chat) = chat {
+        if let Err(err) = send_retry(bot.send_message(chat, text)).await {
+            tracing::warn!("error reply to {} failed: {}", chat, err);
+        }
+    }
+    Ok(())
+}
+
 /* ====== Хранилище состояния ======
    last_search: (чат, ID сообщения бота) -> результаты поиска */
 #[allow(clippy::type_complexity)]
@@ -26,22 +77,463 @@ static LAST_SEARCH: Lazy<Cache<(ChatId, i32), Vec<MultiNorm>>> =
             .build()
     });
 
+/* последний текстовый запрос чата — для /again */
+static LAST_QUERY: Lazy<Cache<ChatId, String>> = Lazy::new(|| {
+    Cache::builder()
+        .time_to_live(std::time::Duration::from_secs(24 * 60 * 60))
+        .max_capacity(10_000)
+        .build()
+});
+
+/* последние разные запросы чата, свежие первыми — для /history */
+static SEARCH_HISTORY: Lazy<Cache<ChatId, Vec<String>>> = Lazy::new(|| {
+    Cache::builder()
+        .time_to_live(std::time::Duration::from_secs(7 * 24 * 60 * 60))
+        .max_capacity(10_000)
+        .build()
+});
+
+/* запросы, показанные кнопками в сообщении /history: (чат, сообщение) -> запросы;
+   кнопка несёт только номер — запрос целиком в callback_data (64 байта) не влезает */
+static HISTORY_SHOWN: Lazy<Cache<(ChatId, i32), Vec<String>>> = Lazy::new(|| {
+    Cache::builder()
+        .time_to_live(std::time::Duration::from_secs(24 * 60 * 60))
+        .max_capacity(10_000)
+        .build()
+});
+
+/// Сколько запросов помнит /history.
+const HISTORY_MAX: usize = 10;
+
+/* inline-поиск: запрос -> результаты, недолго (чтобы не дёргать TMDb на каждую букву) */
+static INLINE_SEARCH: Lazy<Cache<String, Vec<MultiNorm>>> = Lazy::new(|| {
+    Cache::builder()
+        .time_to_live(std::time::Duration::from_secs(10 * 60))
+        .max_capacity(1_000)
+        .build()
+});
+
+/* куда /backup кладёт полные копии хранилища (BACKUP_DIR, по умолчанию ./backups) */
+static BACKUP_DIR: Lazy<std::path::PathBuf> = Lazy::new(|| {
+    std::env::var("BACKUP_DIR")
+        .unwrap_or_else(|_| "backups".to_string())
+        .into()
+});
+
+/* сериал, чью карточку открывали последним, — для /episodes */
+static SELECTED_SHOW: Lazy<Cache<ChatId, (u64, String)>> = Lazy::new(|| {
+    Cache::builder()
+        .time_to_live(std::time::Duration::from_secs(24 * 60 * 60))
+        .max_capacity(10_000)
+        .build()
+});
+
+/* жанры TMDb по языку: (фильмы, сериалы); меняются редко — держим неделю */
+#[allow(clippy::type_complexity)]
+static GENRES: Lazy<Cache<String, (Vec<String>, Vec<String>)>> = Lazy::new(|| {
+    Cache::builder()
+        .time_to_live(std::time::Duration::from_secs(7 * 24 * 60 * 60))
+        .max_capacity(100)
+        .build()
+});
+
+/* администратор бота (ADMIN_USER_ID): служебные команды вроде /clearcache */
+static ADMIN_USER_ID: Lazy<Option<UserId>> = Lazy::new(|| {
+    std::env::var("ADMIN_USER_ID")
+        .ok()
+        .and_then(|v| v.trim().parse::<u64>().ok())
+        .map(UserId)
+});
+
+/* куда /feedback пересылает отзывы (ADMIN_CHAT_ID), иначе — в личку ADMIN_USER_ID */
+static ADMIN_CHAT_ID: Lazy<Option<ChatId>> = Lazy::new(|| {
+    std::env::var("ADMIN_CHAT_ID")
+        .ok()
+        .and_then(|v| v.trim().parse::<i64>().ok())
+        .map(ChatId)
+        .or_else(|| ADMIN_USER_ID.map(ChatId::from))
+});
+
+/* STARTER_LIST=550,13,tv/1399: чем предложить заполнить пустой список на /vote
+   (демо, онбординг); без префикса — фильм */
+static STARTER_LIST: Lazy<Vec<(u64, tmdb::MediaKind)>> = Lazy::new(|| {
+    std::env::var("STARTER_LIST")
+        .map(|v| parse_starter_list(&v))
+        .unwrap_or_default()
+});
+
+fn parse_starter_list(raw: &str) -> Vec<(u64, tmdb::MediaKind)> {
+    let mut out = Vec::new();
+    for item in raw.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+        let (kind, id) = match item.split_once('/') {
+            Some(("tv", id)) => (tmdb::MediaKind::Tv, id),
+            Some(("movie", id)) => (tmdb::MediaKind::Movie, id),
+            Some(_) => (tmdb::MediaKind::Movie, ""),
+            None => (tmdb::MediaKind::Movie, item),
+        };
+        match id.trim().parse::<u64>() {
+            Ok(id) if !out.contains(&(id, kind)) => out.push((id, kind)),
+            Ok(_) => {}
+            Err(_) => tracing::warn!("STARTER_LIST: {:?} ignored", item),
+        }
+    }
+    out.truncate(MAX_LIST_LEN);
+    out
+}
+
+/* ADMIN_ONLY_MUTATIONS=1: в группах менять список и запускать опросы могут только админы */
+static ADMIN_ONLY_MUTATIONS: Lazy<bool> = Lazy::new(|| {
+    std::env::var("ADMIN_ONLY_MUTATIONS")
+        .map(|v| matches!(v.trim(), "1" | "true" | "yes"))
+        .unwrap_or(false)
+});
+
+const ADMIN_ONLY_TEXT: &str =
+    "Только админы могут менять список и запускать голосование в этой группе.";
+
+/* перенос списка: одноразовый код -> чат-источник */
+static MOVE_CODES: Lazy<Cache<String, ChatId>> = Lazy::new(|| {
+    Cache::builder()
+        .time_to_live(std::time::Duration::from_secs(10 * 60))
+        .max_capacity(1_000)
+        .build()
+});
+
+/* ====== Антиспам ======
+   /vote и /quiz — раз в VOTE_COOLDOWN_SECS (30 с), поиск — до трёх запросов
+   за SEARCH_COOLDOWN_SECS (10 с); опросы по /schedule лимит не трогает */
+static VOTE_LIMIT: Lazy<RateLimiter> =
+    Lazy::new(|| RateLimiter::new(crate::config::env_secs("VOTE_COOLDOWN_SECS", 30), 1));
+static SEARCH_LIMIT: Lazy<RateLimiter> =
+    Lazy::new(|| RateLimiter::new(crate::config::env_secs("SEARCH_COOLDOWN_SECS", 10), 3));
+
+/* /feedback — не больше трёх отзывов в час от одного человека (ключ — id пользователя) */
+static FEEDBACK_LIMIT: Lazy<RateLimiter> =
+    Lazy::new(|| RateLimiter::new(std::time::Duration::from_secs(60 * 60), 3));
+
+const COOLDOWN_TEXT: &str = "Подожди немного ⏳";
+
+fn cooldown_for(cmd: &Command) -> Option<&'static RateLimiter> {
+    match cmd {
+        Command::Vote(arg) if !arg.trim().eq_ignore_ascii_case("preview") => Some(&VOTE_LIMIT),
+        Command::Quiz | Command::Episodes(_) => Some(&VOTE_LIMIT),
+        Command::Find(_) | Command::Again(_) | Command::Where(_) => Some(&SEARCH_LIMIT),
+        _ => None,
+    }
+}
+
+/* ====== Длина описаний ======
+   SEARCH_OVERVIEW_LEN — в результатах поиска и inline, DETAIL_OVERVIEW_LEN — в карточке
+   и голосовании; читаются из ENV один раз */
+struct OverviewLimits {
+    search: usize,
+    detail: usize,
+}
+
+static OVERVIEW_LIMITS: Lazy<OverviewLimits> = Lazy::new(|| OverviewLimits {
+    search: overview_len_from_env("SEARCH_OVERVIEW_LEN", 600),
+    detail: overview_len_from_env("DETAIL_OVERVIEW_LEN", 2000),
+});
+
+// сообщение в Telegram — до 4096 символов, часть занимают заголовок и ссылки
+const OVERVIEW_LEN_RANGE: std::ops::RangeInclusive<usize> = 50..=3500;
+
+fn overview_len_from_env(var: &str, default: usize) -> usize {
+    parse_overview_len(var, std::env::var(var).ok().as_deref(), default)
+}
+
+fn parse_overview_len(var: &str, raw: Option<&str>, default: usize) -> usize {
+    let Some(raw) = raw else {
+        return default;
+    };
+    match raw.trim().parse::<usize>() {
+        Ok(n) if OVERVIEW_LEN_RANGE.contains(&n) => n,
+        _ => {
+            tracing::warn!(
+                "{}={} ignored: expected a number in {}..={}, using {}",
+                var,
+                raw,
+                OVERVIEW_LEN_RANGE.start(),
+                OVERVIEW_LEN_RANGE.end(),
+                default
+            );
+            default
+        }
+    }
+}
+
+/* ====== Атрибуция TMDb ======
+   ATTRIBUTION_TEXT — своя формулировка (TMDb всё равно упоминаем),
+   ATTRIBUTION_INLINE=1 — в подписи к постерам вместо отдельного сообщения */
+struct Attribution {
+    text: String,
+    inline: bool,
+}
+
+const DEFAULT_ATTRIBUTION: &str = "Данные и изображения: © TMDB";
+
+static ATTRIBUTION: Lazy<Attribution> = Lazy::new(|| {
+    parse_attribution(
+        std::env::var("ATTRIBUTION_TEXT").ok().as_deref(),
+        std::env::var("ATTRIBUTION_INLINE").ok().as_deref(),
+    )
+});
+
+fn parse_attribution(text: Option<&str>, inline: Option<&str>) -> Attribution {
+    let text = match text.map(str::trim).filter(|t| !t.is_empty()) {
+        None => DEFAULT_ATTRIBUTION.to_string(),
+        Some(t) if t.to_lowercase().contains("tmdb") => t.to_string(),
+        Some(t) => format!("{} · © TMDB", t),
+    };
+    let inline = inline.is_some_and(|v| matches!(v.trim(), "1" | "true" | "yes"));
+    Attribution { text, inline }
+}
+
 /* ====== Команды ====== */
 #[derive(BotCommands, Clone)]
 #[command(rename_rule = "lowercase", description = "Команды:")]
 enum Command {
+    /// старт; с payload вида add_movie_<id> / add_tv_<id> — добавить по ссылке
+    #[command(hide)]
+    Start(String),
     /// сброс списка
     #[command(description = "сбросить список")]
     Reset,
     /// показать список (до 10 фильмов)
     #[command(description = "показать список")]
     List,
-    /// составить голосование (опрос + постеры + описания + трейлеры)
-    #[command(description = "составить голосование")]
-    Vote,
-    /// помощь
-    #[command(description = "помощь")]
-    Help,
+    /// сколько фильмов в списке — без самого списка
+    #[command(description = "сколько фильмов в списке")]
+    Count,
+    /// последние запросы чата кнопками — повторить поиск одним нажатием
+    #[command(description = "последние поиски")]
+    History,
+    /// список с видом и id TMDb — проверить, что именно сохранено
+    #[command(description = "id TMDb фильмов списка: «Название — movie/550»")]
+    Ids,
+    /// бэклог «на потом»: /backlog add <название>, /backlog list
+    #[command(description = "бэклог: /backlog add Название | /backlog list")]
+    Backlog(String),
+    /// опрос по сериям сезона выбранного сериала: /episodes 2, /episodes 2 11 — с 11-й серии
+    #[command(description = "опрос по сериям: /episodes СЕЗОН (сериал — из карточки или списка)")]
+    Episodes(String),
+    /// перенести N-й фильм бэклога в список для голосования
+    #[command(description = "из бэклога в список: /promote N")]
+    Promote(String),
+    /// составить голосование (опрос + постеры + описания + трейлеры);
+    /// /vote preview — только показать варианты, без опроса
+    #[command(description = "составить голосование (/vote preview — предпросмотр)")]
+    Vote(String),
+    /// свой текст вопроса в опросе; /setquestion reset — вернуть стандартный
+    #[command(
+        description = "свой вопрос опроса: /setquestion Пятничное кино? | /setquestion reset"
+    )]
+    SetQuestion(String),
+    /// викторина: «правильный» ответ — фильм с лучшим рейтингом TMDb
+    #[command(description = "викторина по рейтингу")]
+    Quiz,
+    /// отметить N-й фильм списка просмотренным (или снять отметку)
+    #[command(description = "отметить просмотренным: /watched N")]
+    Watched(String),
+    /// убрать из списка все просмотренные разом
+    #[command(description = "убрать все просмотренные из списка")]
+    ClearWatched,
+    /// убрать N-й фильм из списка
+    #[command(description = "убрать из списка: /remove N")]
+    Remove(String),
+    /// своё название для N-го фильма; без названия — вернуть из TMDb
+    #[command(description = "переименовать: /rename N Название")]
+    Rename(String),
+    /// только постер N-го фильма списка, крупнее, без текста
+    #[command(description = "постер фильма из списка: /poster N")]
+    Poster(String),
+    /// регион для «где смотреть» и возрастного рейтинга
+    #[command(description = "регион (US, RU, …): /setregion US")]
+    SetRegion(String),
+    /// перенести список в другой чат: /move — получить код, /move КОД — забрать список
+    #[command(description = "перенести список: /move, затем /move КОД в другом чате")]
+    Move(String),
+    /// ответом на сообщение с выгруженным списком (JSON) — добавить его фильмы к своему
+    #[command(description = "слить списки: /merge ответом на список в JSON")]
+    Merge,
+    /// список по рейтингу TMDb; сам список не пересортировывается
+    #[command(description = "список по рейтингу TMDb, без пересортировки")]
+    Top,
+    /// таблица: название, год, рейтинг, длительность
+    #[command(description = "сравнить фильмы списка")]
+    Compare,
+    /// случайный популярный фильм, которого ещё нет в списке
+    #[command(description = "случайный популярный фильм")]
+    Surprise,
+    /// упорядочить список: /sort title — по названию, /sort rating — по рейтингу TMDb
+    #[command(description = "сортировка списка: /sort title | /sort rating")]
+    Sort(String),
+    /// запланировать голосование: /schedule 20:00, /schedule 2025-01-31 19:30, /schedule cancel
+    #[command(description = "голосование по времени: /schedule 20:00 | /schedule cancel")]
+    Schedule(String),
+    /// напоминание: /remind 18:00 текст, /remind list, /remind cancel ID
+    #[command(description = "напоминание: /remind 18:00 Смотрим сегодня! | list | cancel ID")]
+    Remind(String),
+    /// язык чата (ISO 639-1) — для выбора трейлеров
+    #[command(description = "язык трейлеров (ru, en, fr, …): /lang fr")]
+    Lang(String),
+    /// взрослый контент в поиске; включение — после подтверждения
+    #[command(description = "взрослый контент в поиске: /adult on | off")]
+    Adult(String),
+    /// список чата — себе в личку (из группы)
+    #[command(description = "прислать список себе в личку")]
+    Digest,
+    /// порог голосов TMDb для поиска (0 — без фильтра)
+    #[command(description = "скрывать малоизвестное в поиске: /minvotes 10")]
+    MinVotes(String),
+    /// показывать оригинальное название рядом с русским
+    #[command(description = "оригинальные названия в списке и опросе: /original on | off")]
+    Original(String),
+    /// присылать список после каждого добавления из поиска
+    #[command(description = "список после каждого добавления: /autolist on | off")]
+    AutoList(String),
+    /// закреплять опрос /vote
+    #[command(description = "закреплять опрос /vote: /autopin on | off")]
+    AutoPin(String),
+    /// порядок выдачи поиска: по популярности (как в TMDb), по дате или по рейтингу
+    #[command(description = "порядок поиска: /searchsort date | popularity | rating")]
+    SearchSort(String),
+    /// трейлеры в /vote с превью видео, по сообщению на трейлер
+    #[command(description = "трейлеры с превью видео в /vote: /richtrailers on | off")]
+    RichTrailers(String),
+    /// голосование и карточки без постеров
+    #[command(description = "не присылать постеры (экономия трафика)")]
+    NoPosters,
+    /// вернуть постеры
+    #[command(description = "снова присылать постеры")]
+    Posters,
+    /// текущие настройки чата одним сообщением
+    #[command(description = "настройки чата")]
+    Settings,
+    /// повторить последний поиск; /again 2 — вторая страница выдачи
+    #[command(description = "повторить поиск: /again | /again 2")]
+    Again(String),
+    /// найти и сразу показать карточку лучшего совпадения
+    #[command(description = "найти фильм: /find Начало")]
+    Find(String),
+    /// где посмотреть лучшее совпадение, ничего не добавляя в список
+    #[command(description = "где смотреть: /where Оппенгеймер")]
+    Where(String),
+    /// жанры TMDb для фильмов и сериалов на языке чата
+    #[command(description = "жанры TMDb")]
+    Genres,
+    /// отзыв или баг-репорт администратору бота (ADMIN_CHAT_ID)
+    #[command(description = "написать разработчику: /feedback текст")]
+    Feedback(String),
+    /// сбросить кэши TMDb и постеров (только ADMIN_USER_ID)
+    #[command(hide)]
+    ClearCache,
+    /// полная копия хранилища в BACKUP_DIR (только ADMIN_USER_ID)
+    #[command(hide)]
+    Backup,
+    /// помощь; /help vote — подробно про одну команду
+    #[command(description = "помощь: /help | /help vote")]
+    Help(String),
+}
+
+/* ====== Подробная справка ======
+   /help <команда> — длинное описание с примерами; ключ — имя команды без «/».
+   «remove» — не команда, но про удаление часто спрашивают */
+static COMMAND_HELP: Lazy<HashMap<&'static str, &'static str>> = Lazy::new(|| {
+    HashMap::from([
+        (
+            "vote",
+            "/vote — опрос по текущему списку: постеры, описания и трейлеры.\n\
+             /vote preview — только показать варианты, опрос не создаётся.\n\
+             Анонимность и множественный выбор задаются при запуске бота.\n\
+             Свой вопрос — /setquestion, без постеров — /noposters.",
+        ),
+        (
+            "remove",
+            "/remove N — убрать N-й фильм из списка (номер — как в /list).\n\
+             То же кнопкой: /list и 🗑 в строке с нужным номером.\n\
+             Кнопка ✅/⬜ рядом — отметка «просмотрено», она фильм не удаляет.\n\
+             Все просмотренные разом — /clearwatched, весь список — /reset.",
+        ),
+        (
+            "backlog",
+            "/backlog add Название — отложить фильм «на потом».\n\
+             /backlog list — показать бэклог с номерами.\n\
+             /promote N — перенести N-й фильм бэклога в список для голосования.",
+        ),
+        (
+            "watched",
+            "/watched N — отметить N-й фильм из /list просмотренным; \
+             повторная команда снимает отметку.\n\
+             /clearwatched — убрать из списка все просмотренные разом.",
+        ),
+        (
+            "rename",
+            "/rename N Название — своё название для N-го фильма из /list.\n\
+             /rename N — вернуть название из TMDb.",
+        ),
+        (
+            "schedule",
+            "/schedule 20:00 — голосование сегодня (или завтра, если время прошло).\n\
+             /schedule 2025-01-31 19:30 — в конкретный день.\n\
+             /schedule cancel — отменить. Часовой пояс — из TZ.",
+        ),
+        (
+            "remind",
+            "/remind 18:00 Смотрим сегодня! — напоминание с текстом.\n\
+             /remind list — все напоминания чата с ID.\n\
+             /remind cancel ID — отменить одно.",
+        ),
+        (
+            "move",
+            "/move — получить одноразовый код.\n\
+             /move КОД — в другом чате забрать по нему список.",
+        ),
+        (
+            "sort",
+            "/sort title — по названию, /sort rating — по рейтингу TMDb. \
+             Порядок сохраняется и используется в опросе.",
+        ),
+        (
+            "merge",
+            "/merge — ответь этой командой на сообщение с выгруженным списком \
+             (JSON-массив фильмов, как в файле данных).\n\
+             Фильмы, которых ещё нет, добавятся в конец, пока в списке есть место; \
+             текущий список не заменяется. Заменить целиком — /move.",
+        ),
+        (
+            "again",
+            "/again — повторить последний поиск в этом чате.\n\
+             /again 2 — вторая страница той же выдачи.",
+        ),
+        (
+            "setquestion",
+            "/setquestion Пятничное кино? — свой вопрос для /vote.\n\
+             /setquestion reset — вернуть «Что смотрим?».",
+        ),
+        (
+            "episodes",
+            "/episodes 2 — опрос по сериям второго сезона (до десяти вариантов).\n\
+             /episodes 2 11 — начать с 11-й серии.\n\
+             Сериал — тот, чью карточку открывали последним, иначе последний сериал в списке.",
+        ),
+    ])
+});
+
+/// Текст для /help: без аргумента — все команды, с именем — подробная справка.
+fn help_text(arg: &str) -> String {
+    let name = arg.trim().trim_start_matches('/').to_lowercase();
+    if name.is_empty() {
+        return Command::descriptions().to_string();
+    }
+    match COMMAND_HELP.get(name.as_str()) {
+        Some(text) => (*text).to_string(),
+        None => format!(
+            "Подробной справки по «{}» нет.\n\n{}",
+            name,
+            Command::descriptions()
+        ),
+    }
 }
 
 pub async fn run(bot: Bot, tmdb: TmdbClient, storage: Storage, anonymous: bool, multiple: bool) {
@@ -55,7 +547,18 @@ pub async fn run(bot: Bot, tmdb: TmdbClient, storage: Storage, anonymous: bool,
                         let tmdb = tmdb.clone();
                         let storage = storage.clone();
                         async move {
-                            on_command(bot, msg, cmd, &tmdb, &storage, anonymous, multiple).await
+                            let chat = msg.chat.id;
+                            let res = on_command(
+                                bot.clone(),
+                                msg,
+                                cmd,
+                                &tmdb,
+                                &storage,
+                                anonymous,
+                                multiple,
+                            )
+                            .await;
+                            report(&bot, Some(chat), res).await
                         }
                     }
                 }))
@@ -65,20 +568,67 @@ pub async fn run(bot: Bot, tmdb: TmdbClient, storage: Storage, anonymous: bool,
                     dptree::endpoint(move |bot: Bot, msg: Message| {
                         let tmdb = tmdb.clone();
                         let storage = storage.clone();
-                        async move { on_search_text(bot, msg, &tmdb, &storage).await }
+                        async move {
+                            let chat = msg.chat.id;
+                            let res = on_search_text(bot.clone(), msg, &tmdb, &storage).await;
+                            report(&bot, Some(chat), res).await
+                        }
                     })
                 }),
         )
+        .branch(Update::filter_edited_message().endpoint({
+            let tmdb = tmdb.clone();
+            let storage = storage.clone();
+            move |bot: Bot, msg: Message| {
+                let tmdb = tmdb.clone();
+                let storage = storage.clone();
+                async move {
+                    let chat = msg.chat.id;
+                    let res = on_edited_search_text(bot.clone(), msg, &tmdb, &storage).await;
+                    report(&bot, Some(chat), res).await
+                }
+            }
+        }))
+        .branch(Update::filter_my_chat_member().endpoint({
+            let storage = storage.clone();
+            move |bot: Bot, upd: ChatMemberUpdated| {
+                let storage = storage.clone();
+                async move {
+                    let chat = upd.chat.id;
+                    let res = on_my_chat_member(bot.clone(), upd, &storage).await;
+                    report(&bot, Some(chat), res).await
+                }
+            }
+        }))
+        .branch(Update::filter_inline_query().endpoint({
+            let tmdb = tmdb.clone();
+            move |bot: Bot, q: InlineQuery| {
+                let tmdb = tmdb.clone();
+                async move { on_inline_query(bot, q, &tmdb).await }
+            }
+        }))
         .branch(Update::filter_callback_query().endpoint({
             let tmdb = tmdb.clone();
             let storage = storage.clone();
             move |bot: Bot, q: CallbackQuery| {
                 let tmdb = tmdb.clone();
                 let storage = storage.clone();
-                async move { on_callback(bot, q, &tmdb, &storage).await }
+                async move {
+                    let chat = q.message.as_ref().map(|m| m.chat().id);
+                    let res = on_callback(bot.clone(), q, &tmdb, &storage).await;
+                    report(&bot, chat, res).await
+                }
             }
         }));
 
+    tokio::spawn(run_scheduler(
+        bot.clone(),
+        tmdb.clone(),
+        storage.clone(),
+        anonymous,
+        multiple,
+    ));
+
     Dispatcher::builder(bot, msg_handler)
         .enable_ctrlc_handler()
         .build()
@@ -86,988 +636,6921 @@ pub async fn run(bot: Bot, tmdb: TmdbClient, storage: Storage, anonymous: bool,
         .await;
 }
 
-/* ====== Команды ====== */
-async fn on_command<R>(
-    bot: R,
-    msg: Message,
-    cmd: Command,
-    tmdb: &TmdbClient,
-    storage: &Storage,
-    anonymous: bool,
-    multiple: bool,
-) -> ResponseResult<()>
+/* ====== Бота добавили в группу / убрали из неё ======
+   добавили — здороваемся и показываем команды; выгнали или вышел —
+   забываем список, бэклог, настройки и задания этого чата */
+#[derive(Debug, PartialEq, Eq)]
+enum Membership {
+    Joined,
+    Gone,
+}
+
+fn membership_change(old: &ChatMemberKind, new: &ChatMemberKind) -> Option<Membership> {
+    match (old.is_present(), new.is_present()) {
+        (false, true) => Some(Membership::Joined),
+        (true, false) => Some(Membership::Gone),
+        _ => None,
+    }
+}
+
+const WELCOME_TEXT: &str = "Привет! Я помогаю киноклубу выбрать, что смотреть: \
+    пришлите название — найду фильм и добавлю в список, а /vote устроит голосование.";
+
+async fn on_my_chat_member<R>(bot: R, upd: ChatMemberUpdated, storage: &Storage) -> BotResult
 where
     R: Requester<Err = RequestError>,
 {
-    if !msg.chat.is_private() {
+    // личку и каналы не трогаем: заблокировавший бота может вернуться к своему списку
+    if !(upd.chat.is_group() || upd.chat.is_supergroup()) {
         return Ok(());
     }
-
-    match cmd {
-        Command::Help => {
-            bot.send_message(msg.chat.id, Command::descriptions().to_string())
-                .await?;
+    let change = membership_change(&upd.old_chat_member.kind, &upd.new_chat_member.kind);
+    match change {
+        Some(Membership::Joined) => {
+            let text = format!("{}\n\n{}", WELCOME_TEXT, Command::descriptions());
+            send_long(&bot, upd.chat.id, &text, None).await?;
         }
-        Command::Reset => {
-            storage
-                .remove_chat(msg.chat.id.0)
-                .await
-                .map_err(to_req_err)?;
-            bot.send_message(msg.chat.id, "Список очищен.").await?;
-        }
-        Command::List => send_list_view(&bot, msg.chat.id, storage).await?,
-        Command::Vote => {
-            run_vote_flow(&bot, msg.chat.id, tmdb, storage, anonymous, multiple).await?
+        Some(Membership::Gone) => {
+            tracing::info!("removed from chat {}, forgetting its data", upd.chat.id);
+            storage.forget_chat(upd.chat.id.0).await?;
         }
+        None => {}
     }
     Ok(())
 }
 
-/* ====== Поиск по тексту ====== */
-async fn on_search_text<R>(
-    bot: R,
-    msg: Message,
+/* ====== Планировщик ======
+   задания лежат в сторе (переживают рестарт); раз в 20 с забираем наступившие */
+async fn run_scheduler(
+    bot: Bot,
+    tmdb: TmdbClient,
+    storage: Storage,
+    anonymous: bool,
+    multiple: bool,
+) {
+    let mut tick = tokio::time::interval(std::time::Duration::from_secs(20));
+    loop {
+        tick.tick().await;
+        let due = match storage.take_due_jobs(chrono::Utc::now().timestamp()).await {
+            Ok(due) => due,
+            Err(e) => {
+                tracing::warn!("scheduler: reading jobs failed: {}", e);
+                continue;
+            }
+        };
+        for job in due {
+            let id = job.id;
+            if let Err(e) = run_job(&bot, &tmdb, &storage, job, anonymous, multiple).await {
+                tracing::warn!("scheduler: job {} failed: {}", id, e);
+            }
+        }
+    }
+}
+
+async fn run_job<R>(
+    bot: &R,
     tmdb: &TmdbClient,
-    _storage: &Storage,
-) -> ResponseResult<()>
+    storage: &Storage,
+    job: ScheduledJob,
+    anonymous: bool,
+    multiple: bool,
+) -> BotResult
 where
     R: Requester<Err = RequestError>,
 {
-    if !msg.chat.is_private() {
-        return Ok(());
+    let chat = ChatId(job.chat_id);
+    match job.kind {
+        JobKind::Vote => {
+            let kind = PollKind::Regular;
+            run_vote_flow(bot, chat, tmdb, storage, anonymous, multiple, kind).await
+        }
+        JobKind::Remind(text) => {
+            send_retry(bot.send_message(chat, format!("⏰ {}", text))).await?;
+            Ok(())
+        }
     }
+}
 
-    let Some(query) = message_text_any(&msg) else {
-        return Ok(());
-    };
-    let query = query.trim();
-    if query.is_empty() {
-        return Ok(());
+// «20:00» — ближайшие 20:00 (сегодня или завтра); «2025-01-31 19:30» / «31.01.2025 19:30» — точно,
+// но только в будущем. Время — по часовому поясу сервера (TZ).
+fn parse_when<Tz: TimeZone>(arg: &str, now: &DateTime<Tz>) -> Option<DateTime<Tz>> {
+    let arg = arg.trim();
+    let tz = now.timezone();
+    if let Ok(t) = NaiveTime::parse_from_str(arg, "%H:%M") {
+        let today = now.date_naive().and_time(t);
+        let at = tz.from_local_datetime(&today).earliest()?;
+        if at > *now {
+            return Some(at);
+        }
+        let tomorrow = today + chrono::Duration::days(1);
+        return tz.from_local_datetime(&tomorrow).earliest();
     }
+    let naive = NaiveDateTime::parse_from_str(arg, "%Y-%m-%d %H:%M")
+        .or_else(|_| NaiveDateTime::parse_from_str(arg, "%d.%m.%Y %H:%M"))
+        .ok()?;
+    let at = tz.from_local_datetime(&naive).earliest()?;
+    (at > *now).then_some(at)
+}
 
-    // Ищем до 10
-    let results = match tmdb.search_movies_ru(query, 10).await {
-        Ok(v) => v,
-        Err(e) => {
-            bot.send_message(msg.chat.id, e.user_msg()).await?;
-            return Ok(());
+// время в начале строки (одно слово «18:00» или два «2025-01-31 18:00»), дальше — текст
+fn split_when<'a, Tz: TimeZone>(
+    arg: &'a str,
+    now: &DateTime<Tz>,
+) -> Option<(DateTime<Tz>, &'a str)> {
+    let words: Vec<&str> = arg.split_whitespace().collect();
+    for n in [2, 1] {
+        if words.len() <= n {
+            continue;
+        }
+        if let Some(at) = parse_when(&words[..n].join(" "), now) {
+            let mut rest = arg.trim_start();
+            for w in &words[..n] {
+                rest = rest[w.len()..].trim_start();
+            }
+            return Some((at, rest));
         }
-    };
-
-    if results.is_empty() {
-        bot.send_message(msg.chat.id, "Ничего не нашёл 😕").await?;
-        return Ok(());
     }
+    None
+}
 
-
-    // Сообщение с названиями + краткими описаниями
-    let mut blocks = Vec::new();
-    for m in &results {
-        blocks.push(make_block(m, 600)); // описания укоротим
+fn format_job_time(at: i64) -> String {
+    match Local.timestamp_opt(at, 0).single() {
+        Some(t) => t.format("%d.%m.%Y %H:%M").to_string(),
+        None => at.to_string(),
     }
-    let text = join_blocks(blocks, 3500); // запас до 4096
-    bot.send_message(msg.chat.id, text)
-        .parse_mode(ParseMode::Html)
-        .await?;
-
-    // Кнопки "➕ <Название (год)>"
-    let kb = keyboard_add_results(&results);
-    let sent_msg = bot.send_message(msg.chat.id, "Выбери фильм, чтобы добавить в список:")
-        .reply_markup(kb)
-        .await?;
-
-    LAST_SEARCH
-        .insert((msg.chat.id, sent_msg.id.0), results)
-        .await;
-
-    Ok(())
 }
 
-/* ====== Callback-кнопки ======
-   add:<id>   — добавить найденный фильм в список
-   del:<id>   — удалить из списка
-   show:<id>  — показать постер+описание из TMDb
-*/
-async fn on_callback<R>(
+/* ====== Команды ====== */
+async fn on_command<R>(
     bot: R,
-    q: CallbackQuery,
+    msg: Message,
+    cmd: Command,
     tmdb: &TmdbClient,
     storage: &Storage,
-) -> ResponseResult<()>
+    anonymous: bool,
+    multiple: bool,
+) -> BotResult
 where
     R: Requester<Err = RequestError>,
 {
-    let Some(data) = q.data.clone() else {
-        return Ok(());
-    };
-    let chat_id = q.message.as_ref().map(|m| m.chat().id).unwrap_or(ChatId(0));
-    let mut parts = data.splitn(3, ':');
-    let cmd = parts.next().unwrap_or("");
-    let id_str = parts.next().unwrap_or("");
-    let media_type_str = parts.next().unwrap_or("");
-    let Ok(id) = id_str.parse::<u64>() else {
+    if is_mutation(&cmd) && !message_may_mutate(&bot, &msg).await {
+        bot.send_message(msg.chat.id, ADMIN_ONLY_TEXT).await?;
         return Ok(());
-    };
+    }
 
-    let media_type = if media_type_str == "tv" {
-        tmdb::MediaKind::Tv
-    } else if media_type_str == "person" {
-        tmdb::MediaKind::Person
-    } else {
-        tmdb::MediaKind::Movie
-    };
+    if let Some(limiter) = cooldown_for(&cmd) {
+        if !limiter.check(msg.chat.id.0) {
+            bot.send_message(msg.chat.id, COOLDOWN_TEXT).await?;
+            return Ok(());
+        }
+    }
 
     match cmd {
-        "add" => {
-            let message_id = q.message.as_ref().map(|m| m.id().0).unwrap_or(0);
-            let mut movie_opt = LAST_SEARCH
-                .get(&(chat_id, message_id))
-                .await
-                .and_then(|v| v.iter().find(|m| m.id == id).cloned());
-
-            if movie_opt.is_none() {
-                if let Ok(Some(m)) = tmdb.movie_details_ru(id, media_type).await {
-                    movie_opt = Some(m);
+        Command::Start(payload) => {
+            let Some((id, kind)) = parse_start_payload(&payload) else {
+                send_long(
+                    &bot,
+                    msg.chat.id,
+                    &Command::descriptions().to_string(),
+                    None,
+                )
+                .await?;
+                return Ok(());
+            };
+            let m = match tmdb.movie_details_ru(id, kind).await {
+                Ok(Some(m)) => m,
+                Ok(None) => {
+                    bot.send_message(msg.chat.id, "Фильм по ссылке не найден.")
+                        .await?;
+                    return Ok(());
                 }
-            }
-
-            if let Some(m) = movie_opt {
-                let added = storage
-                    .add_movie(
-                        chat_id.0,
-                        StoredMovie {
-                            id: m.id,
-                            title: m.title,
-                            original_title: m.original_title,
-                            poster_path: m.image_path.clone(),
-                            release_date: m.release_date.clone(),
-                            media_type: m.media_type,
-                        },
-                    )
-                    .await
-                    .map_err(to_req_err)?;
-                if added {
-                    answer_cb(&bot, &q, "Добавлено").await?;
-                    send_list_view(&bot, chat_id, storage).await?;
-                } else {
-                    // либо уже есть, либо переполнено
-                    // уточним причину:
-                    let current = storage.get(chat_id.0).await;
-                    if current.len() >= 10 {
-                        answer_cb(&bot, &q, "В списке уже 10 фильмов").await?;
-                    } else {
-                        answer_cb(&bot, &q, "Уже в списке").await?;
-                    }
+                Err(e) => {
+                    bot.send_message(msg.chat.id, e.user_msg()).await?;
+                    return Ok(());
                 }
+            };
+            let title = one_line_title(&m);
+            let added = storage.add_movie(msg.chat.id.0, m.into()).await?;
+            if added {
+                bot.send_message(msg.chat.id, format!("Добавил «{}».", title))
+                    .await?;
+                send_list_after_add(&bot, msg.chat.id, storage).await?;
             } else {
-                answer_cb(&bot, &q, "Не нашёл фильм в последнем поиске").await?;
+                bot.send_message(
+                    msg.chat.id,
+                    format!("«{}» уже в списке, или список заполнен.", title),
+                )
+                .await?;
             }
         }
-        "del" => {
-            let removed = storage
-                .delete_movie(chat_id.0, id, media_type)
-                .await
-                .map_err(to_req_err)?;
-            if removed {
-                answer_cb(&bot, &q, "Удалено").await?;
-                send_list_view(&bot, chat_id, storage).await?;
-            } else {
-                answer_cb(&bot, &q, "Не найдено в списке").await?;
-            }
+        Command::Help(arg) => {
+            send_long(&bot, msg.chat.id, &help_text(&arg), None).await?;
         }
-        "show" => match tmdb.movie_details_ru(id, media_type).await {
-            Ok(Some(m)) => {
-                let text = make_block(&m, 2000);
-                bot.send_message(chat_id, text)
-                    .parse_mode(ParseMode::Html)
+        Command::Reset => {
+            storage.remove_chat(msg.chat.id.0).await?;
+            bot.send_message(msg.chat.id, "Список очищен, бэклог не тронут.")
+                .await?;
+        }
+        Command::List => send_list_view(&bot, msg.chat.id, storage).await?,
+        Command::Count => {
+            let n = storage.count(msg.chat.id.0).await;
+            bot.send_message(msg.chat.id, format!("В списке {} из {}", n, MAX_LIST_LEN))
+                .await?;
+        }
+        Command::History => {
+            let history = SEARCH_HISTORY.get(&msg.chat.id).await.unwrap_or_default();
+            if history.is_empty() {
+                bot.send_message(msg.chat.id, "История поиска пуста.")
                     .await?;
-                if let Some(p) = &m.image_path {
-                    let url = format!("https://image.tmdb.org/t/p/w500{}", p);
-                    if let Ok(bytes) = fetch_image(&url).await {
-                        bot.send_photo(
-                            chat_id,
-                            InputFile::memory(bytes).file_name(format!("poster_{}.jpg", m.id)),
-                        )
-                        .await?;
-                    }
-                }
-                answer_cb(&bot, &q, "Показал").await?;
-            }
-            Ok(None) => {
-                answer_cb(&bot, &q, "Фильм не найден").await?;
                 return Ok(());
             }
-            Err(e) => {
-                answer_cb(&bot, &q, e.user_msg()).await?;
-                return Ok(());
+            let rows = history
+                .iter()
+                .enumerate()
+                .map(|(i, q)| {
+                    vec![InlineKeyboardButton::callback(
+                        format!("🔎 {}", clip_plain(q, 60)),
+                        format!("hist:{}", i),
+                    )]
+                })
+                .collect::<Vec<_>>();
+            let sent = bot
+                .send_message(msg.chat.id, "Недавние поиски — нажми, чтобы повторить:")
+                .reply_markup(InlineKeyboardMarkup::new(rows))
+                .await?;
+            HISTORY_SHOWN
+                .insert((msg.chat.id, sent.id.0), history)
+                .await;
+        }
+        Command::Ids => {
+            let list = storage.get(msg.chat.id.0).await;
+            if list.is_empty() {
+                bot.send_message(msg.chat.id, "Список пуст.").await?;
+            } else {
+                send_long(&bot, msg.chat.id, &ids_text(&list), None).await?;
+            }
+        }
+        Command::SetRegion(arg) => {
+            let Some(region) = regions::normalize(&arg) else {
+                let current = storage.settings(msg.chat.id.0).await.region;
+                bot.send_message(
+                    msg.chat.id,
+                    format!(
+                        "Нужен двухбуквенный код страны, например: /setregion US\nСейчас: {}",
+                        current
+                    ),
+                )
+                .await?;
+                return Ok(());
+            };
+            storage
+                .update_settings(msg.chat.id.0, |s| s.region = region.clone())
+                .await?;
+            bot.send_message(msg.chat.id, format!("Регион: {}", region))
+                .await?;
+        }
+        Command::Sort(arg) => {
+            let by = match arg.trim().to_lowercase().as_str() {
+                "title" => SortBy::Title,
+                "rating" => SortBy::Rating,
+                _ => {
+                    bot.send_message(msg.chat.id, "Как сортировать: /sort title или /sort rating")
+                        .await?;
+                    return Ok(());
+                }
+            };
+            let mut ratings = HashMap::new();
+            if by == SortBy::Rating {
+                // заодно запоминаем рейтинги, которых не было при добавлении
+                let list = storage.get(msg.chat.id.0).await;
+                for (m, r) in list.iter().zip(list_ratings(tmdb, &list).await) {
+                    if let Some(r) = r {
+                        ratings.insert((m.id, m.media_type.as_str()), r);
+                    }
+                }
+            }
+            // сортируем уже текущий список: за время запросов его могли поменять
+            storage
+                .reorder(msg.chat.id.0, &ratings, |list| sort_list(list, by))
+                .await?;
+            send_list_view(&bot, msg.chat.id, storage).await?;
+        }
+        Command::Top => {
+            let mut list = storage.get(msg.chat.id.0).await;
+            if list.is_empty() {
+                bot.send_message(msg.chat.id, "Список пуст.").await?;
+                return Ok(());
+            }
+            // сортируем копию — порядок в хранилище не трогаем
+            let ratings = list_ratings(tmdb, &list).await;
+            for (m, r) in list.iter_mut().zip(ratings) {
+                m.vote_average = r;
+            }
+            sort_list(&mut list, SortBy::Rating);
+            bot.send_message(msg.chat.id, top_text(&list))
+                .parse_mode(ParseMode::Html)
+                .await?;
+        }
+        Command::Schedule(arg) => {
+            let arg = arg.trim();
+            let chat = msg.chat.id.0;
+            if arg.is_empty() {
+                let votes: Vec<String> = storage
+                    .jobs(chat)
+                    .await
+                    .iter()
+                    .filter(|j| j.kind == JobKind::Vote)
+                    .map(|j| format_job_time(j.at))
+                    .collect();
+                let text = if votes.is_empty() {
+                    "Ничего не запланировано. Пример: /schedule 20:00".to_string()
+                } else {
+                    format!("Голосование запланировано: {}", votes.join(", "))
+                };
+                bot.send_message(msg.chat.id, text).await?;
+                return Ok(());
+            }
+            if arg.eq_ignore_ascii_case("cancel") {
+                let n = storage
+                    .cancel_jobs(chat, |j| j.kind == JobKind::Vote)
+                    .await?;
+                let text = if n > 0 {
+                    "Запланированное голосование отменено."
+                } else {
+                    "Нечего отменять."
+                };
+                bot.send_message(msg.chat.id, text).await?;
+                return Ok(());
+            }
+            let Some(at) = parse_when(arg, &Local::now()) else {
+                bot.send_message(
+                    msg.chat.id,
+                    "Не понял время. Примеры: /schedule 20:00, /schedule 2025-01-31 19:30",
+                )
+                .await?;
+                return Ok(());
+            };
+            // одно голосование на чат: новое время заменяет старое
+            storage
+                .cancel_jobs(chat, |j| j.kind == JobKind::Vote)
+                .await?;
+            storage.add_job(chat, at.timestamp(), JobKind::Vote).await?;
+            bot.send_message(
+                msg.chat.id,
+                format!("Голосование — {}.", format_job_time(at.timestamp())),
+            )
+            .await?;
+        }
+        Command::Remind(arg) => {
+            let arg = arg.trim();
+            let chat = msg.chat.id.0;
+            if arg.eq_ignore_ascii_case("list") {
+                let lines: Vec<String> = storage
+                    .jobs(chat)
+                    .await
+                    .iter()
+                    .filter_map(|j| match &j.kind {
+                        JobKind::Remind(text) => {
+                            Some(format!("#{} — {}: {}", j.id, format_job_time(j.at), text))
+                        }
+                        _ => None,
+                    })
+                    .collect();
+                let text = if lines.is_empty() {
+                    "Напоминаний нет.".to_string()
+                } else {
+                    format!("Напоминания:\n{}", lines.join("\n"))
+                };
+                bot.send_message(msg.chat.id, text).await?;
+                return Ok(());
+            }
+            if let Some(id) = arg
+                .strip_prefix("cancel")
+                .map(|rest| rest.trim().trim_start_matches('#'))
+            {
+                let Ok(id) = id.parse::<u64>() else {
+                    bot.send_message(msg.chat.id, "Укажи номер из /remind list: /remind cancel 3")
+                        .await?;
+                    return Ok(());
+                };
+                let n = storage
+                    .cancel_jobs(chat, |j| j.id == id && matches!(j.kind, JobKind::Remind(_)))
+                    .await?;
+                let text = if n > 0 {
+                    "Напоминание отменено."
+                } else {
+                    "Такого напоминания нет."
+                };
+                bot.send_message(msg.chat.id, text).await?;
+                return Ok(());
+            }
+            let Some((at, text)) = split_when(arg, &Local::now()) else {
+                bot.send_message(
+                    msg.chat.id,
+                    "Пример: /remind 18:00 Смотрим сегодня!\nСписок: /remind list",
+                )
+                .await?;
+                return Ok(());
+            };
+            let id = storage
+                .add_job(chat, at.timestamp(), JobKind::Remind(text.to_string()))
+                .await?;
+            bot.send_message(
+                msg.chat.id,
+                format!("Напомню {} (#{}).", format_job_time(at.timestamp()), id),
+            )
+            .await?;
+        }
+        Command::Lang(arg) => {
+            let lang = arg.trim().to_lowercase();
+            if lang.len() != 2 || !lang.chars().all(|c| c.is_ascii_lowercase()) {
+                let current = storage.settings(msg.chat.id.0).await.language;
+                bot.send_message(
+                    msg.chat.id,
+                    format!(
+                        "Нужен двухбуквенный код языка, например: /lang fr\nСейчас: {}",
+                        current
+                    ),
+                )
+                .await?;
+                return Ok(());
+            }
+            storage
+                .update_settings(msg.chat.id.0, |s| s.language = lang.clone())
+                .await?;
+            bot.send_message(msg.chat.id, format!("Язык трейлеров: {}", lang))
+                .await?;
+        }
+        Command::Adult(arg) => match arg.trim().to_lowercase().as_str() {
+            "on" => {
+                let kb = InlineKeyboardMarkup::new(vec![vec![
+                    InlineKeyboardButton::callback("🔞 Да, включить".to_string(), "adult:1"),
+                    InlineKeyboardButton::callback("Отмена".to_string(), "nop:0"),
+                ]]);
+                bot.send_message(
+                    msg.chat.id,
+                    "Показывать в поиске фильмы для взрослых (18+)? Это касается всего чата.",
+                )
+                .reply_markup(kb)
+                .await?;
+            }
+            "off" => {
+                storage
+                    .update_settings(msg.chat.id.0, |s| s.include_adult = false)
+                    .await?;
+                bot.send_message(msg.chat.id, "Взрослый контент в поиске выключен.")
+                    .await?;
+            }
+            _ => {
+                let on = storage.settings(msg.chat.id.0).await.include_adult;
+                bot.send_message(
+                    msg.chat.id,
+                    format!(
+                        "Взрослый контент в поиске: {}\nИзменить: /adult on | /adult off",
+                        if on {
+                            "включён"
+                        } else {
+                            "выключен"
+                        }
+                    ),
+                )
+                .await?;
             }
         },
-        _ => {
-            answer_cb(&bot, &q, "Неизвестная команда").await?;
+        Command::Digest => {
+            let Some(user) = msg.from.as_ref() else {
+                return Ok(());
+            };
+            send_digest(&bot, &msg.chat, user, storage).await?;
+        }
+        Command::MinVotes(arg) => {
+            let Ok(n) = arg.trim().parse::<u64>() else {
+                let current = storage.settings(msg.chat.id.0).await.min_votes;
+                bot.send_message(
+                    msg.chat.id,
+                    format!(
+                        "Укажи число голосов TMDb, например: /minvotes 50 (0 — без фильтра)\nСейчас: {}",
+                        current
+                    ),
+                )
+                .await?;
+                return Ok(());
+            };
+            storage
+                .update_settings(msg.chat.id.0, |s| s.min_votes = n)
+                .await?;
+            bot.send_message(
+                msg.chat.id,
+                format!("В поиске прячу тайтлы меньше чем с {} голосами", n),
+            )
+            .await?;
+        }
+        Command::Original(arg) => {
+            let on = match arg.trim().to_lowercase().as_str() {
+                "on" => true,
+                "off" => false,
+                _ => {
+                    let on = storage.settings(msg.chat.id.0).await.show_original_title;
+                    bot.send_message(
+                        msg.chat.id,
+                        format!(
+                            "Оригинальные названия: {}\nИзменить: /original on | /original off",
+                            if on {
+                                "показываю"
+                            } else {
+                                "не показываю"
+                            }
+                        ),
+                    )
+                    .await?;
+                    return Ok(());
+                }
+            };
+            storage
+                .update_settings(msg.chat.id.0, |s| s.show_original_title = on)
+                .await?;
+            let text = if on {
+                "Теперь в списке и опросе есть оригинальные названия."
+            } else {
+                "Оригинальные названия больше не показываю."
+            };
+            bot.send_message(msg.chat.id, text).await?;
+        }
+        Command::AutoList(arg) => {
+            let on =
+                match arg.trim().to_lowercase().as_str() {
+                    "on" => true,
+                    "off" => false,
+                    _ => {
+                        let on = storage.settings(msg.chat.id.0).await.auto_list;
+                        bot.send_message(
+                            msg.chat.id,
+                            format!(
+                            "Список после добавления: {}\nИзменить: /autolist on | /autolist off",
+                            if on { "присылаю" } else { "не присылаю" }
+                        ),
+                        )
+                        .await?;
+                        return Ok(());
+                    }
+                };
+            storage
+                .update_settings(msg.chat.id.0, |s| s.auto_list = on)
+                .await?;
+            let text = if on {
+                "Снова присылаю список после каждого добавления."
+            } else {
+                "Список после добавления больше не присылаю — посмотреть: /list"
+            };
+            bot.send_message(msg.chat.id, text).await?;
+        }
+        Command::AutoPin(arg) => {
+            let on = match arg.trim().to_lowercase().as_str() {
+                "on" => true,
+                "off" => false,
+                _ => {
+                    let on = storage.settings(msg.chat.id.0).await.auto_pin;
+                    bot.send_message(
+                        msg.chat.id,
+                        format!(
+                            "Закреплять опрос: {}\nИзменить: /autopin on | /autopin off",
+                            if on { "да" } else { "нет" }
+                        ),
+                    )
+                    .await?;
+                    return Ok(());
+                }
+            };
+            storage
+                .update_settings(msg.chat.id.0, |s| s.auto_pin = on)
+                .await?;
+            let text = if on {
+                "Буду закреплять опрос /vote (нужно право закреплять сообщения)."
+            } else {
+                "Опрос /vote больше не закрепляю."
+            };
+            bot.send_message(msg.chat.id, text).await?;
+        }
+        Command::SearchSort(arg) => {
+            let Some(sort) = SearchSort::parse(&arg) else {
+                let current = storage.settings(msg.chat.id.0).await.search_sort;
+                bot.send_message(
+                    msg.chat.id,
+                    format!(
+                        "Порядок поиска: {}\nИзменить: /searchsort date | popularity | rating",
+                        current.as_str()
+                    ),
+                )
+                .await?;
+                return Ok(());
+            };
+            storage
+                .update_settings(msg.chat.id.0, |s| s.search_sort = sort)
+                .await?;
+            let text = match sort {
+                SearchSort::Popularity => "Поиск снова в порядке TMDb — сначала популярное.",
+                SearchSort::Date => "В поиске теперь сначала новые.",
+                SearchSort::Rating => "В поиске теперь сначала с лучшим рейтингом.",
+            };
+            bot.send_message(msg.chat.id, text).await?;
+        }
+        Command::RichTrailers(arg) => {
+            let on =
+                match arg.trim().to_lowercase().as_str() {
+                    "on" => true,
+                    "off" => false,
+                    _ => {
+                        let on = storage.settings(msg.chat.id.0).await.rich_trailers;
+                        bot.send_message(
+                            msg.chat.id,
+                            format!(
+                            "Трейлеры в /vote: {}\nИзменить: /richtrailers on | /richtrailers off",
+                            if on { "с превью видео" } else { "списком ссылок" }
+                        ),
+                        )
+                        .await?;
+                        return Ok(());
+                    }
+                };
+            storage
+                .update_settings(msg.chat.id.0, |s| s.rich_trailers = on)
+                .await?;
+            let text = if on {
+                "Трейлеры в /vote пришлю отдельными сообщениями с превью видео."
+            } else {
+                "Трейлеры в /vote снова одним списком ссылок."
+            };
+            bot.send_message(msg.chat.id, text).await?;
+        }
+        Command::NoPosters | Command::Posters => {
+            let on = matches!(cmd, Command::Posters);
+            storage
+                .update_settings(msg.chat.id.0, |s| s.posters = on)
+                .await?;
+            let text = if on {
+                "Постеры снова включены."
+            } else {
+                "Постеры выключены: в /vote и карточках будет только текст. Вернуть: /posters"
+            };
+            bot.send_message(msg.chat.id, text).await?;
+        }
+        Command::Settings => {
+            let chat = msg.chat.id.0;
+            let text = settings_text(
+                &storage.settings(chat).await,
+                PollFlags {
+                    anonymous,
+                    multiple,
+                    admin_only: *ADMIN_ONLY_MUTATIONS,
+                },
+                storage.count(chat).await,
+                storage.backlog(chat).await.len(),
+            );
+            bot.send_message(msg.chat.id, text)
+                .parse_mode(ParseMode::Html)
+                .await?;
+        }
+        Command::ClearCache => {
+            if !is_bot_admin(msg.from.as_ref(), *ADMIN_USER_ID) {
+                bot.send_message(msg.chat.id, "Команда только для администратора бота.")
+                    .await?;
+                return Ok(());
+            }
+            INLINE_SEARCH.run_pending_tasks().await;
+            let search = INLINE_SEARCH.entry_count();
+            INLINE_SEARCH.invalidate_all();
+            GENRES.run_pending_tasks().await;
+            let genres = GENRES.entry_count();
+            GENRES.invalidate_all();
+            let images = clear_image_cache().await;
+            bot.send_message(
+                msg.chat.id,
+                format!(
+                    "Кэши очищены: поиск — {}, жанры — {}, постеры — {}.",
+                    search, genres, images
+                ),
+            )
+            .await?;
+        }
+        Command::Feedback(arg) => {
+            let text = arg.trim();
+            let Some(admin_chat) = *ADMIN_CHAT_ID else {
+                bot.send_message(msg.chat.id, "Отзывы в этом боте не принимаются.")
+                    .await?;
+                return Ok(());
+            };
+            if text.is_empty() {
+                bot.send_message(msg.chat.id, "Напиши отзыв после команды: /feedback текст")
+                    .await?;
+                return Ok(());
+            }
+            let sender = msg.from.as_ref().map_or(msg.chat.id.0, |u| u.id.0 as i64);
+            if !FEEDBACK_LIMIT.check(sender) {
+                bot.send_message(msg.chat.id, COOLDOWN_TEXT).await?;
+                return Ok(());
+            }
+            let report = feedback_report(msg.chat.id, msg.chat.title(), msg.from.as_ref(), text);
+            send_long(&bot, admin_chat, &report, None).await?;
+            bot.send_message(msg.chat.id, "Спасибо за отзыв!").await?;
+        }
+        Command::Backup => {
+            if !is_bot_admin(msg.from.as_ref(), *ADMIN_USER_ID) {
+                bot.send_message(msg.chat.id, "Команда только для администратора бота.")
+                    .await?;
+                return Ok(());
+            }
+            let path = storage.backup(&BACKUP_DIR).await?;
+            bot.send_message(msg.chat.id, format!("Копия сохранена: {}", path.display()))
+                .await?;
+        }
+        Command::Compare => send_compare(&bot, msg.chat.id, tmdb, storage).await?,
+        Command::Genres => {
+            let lang = storage.settings(msg.chat.id.0).await.language;
+            match genre_lists(tmdb, &lang).await {
+                Ok((movie, tv)) => {
+                    bot.send_message(msg.chat.id, genres_text(&movie, &tv))
+                        .parse_mode(ParseMode::Html)
+                        .await?;
+                }
+                Err(e) => {
+                    bot.send_message(msg.chat.id, e.user_msg()).await?;
+                }
+            }
+        }
+        Command::Surprise => {
+            let list = storage.get(msg.chat.id.0).await;
+            // страница может целиком совпасть со списком — пробуем ещё пару раз
+            let mut picked = None;
+            for _ in 0..3 {
+                let page = fastrand::u32(1..=SURPRISE_PAGES);
+                match tmdb.discover_popular(page).await {
+                    Ok(found) => {
+                        picked = pick_surprise(found, &list);
+                        if picked.is_some() {
+                            break;
+                        }
+                    }
+                    Err(e) => {
+                        bot.send_message(msg.chat.id, e.user_msg()).await?;
+                        return Ok(());
+                    }
+                }
+            }
+            match picked {
+                Some(m) => send_top_hit_card(&bot, msg.chat.id, tmdb, m).await?,
+                None => {
+                    bot.send_message(msg.chat.id, "Не нашёл ничего нового, попробуй ещё раз.")
+                        .await?;
+                }
+            }
+        }
+        Command::Move(arg) => {
+            let code = arg.trim();
+            if code.is_empty() {
+                if storage.get(msg.chat.id.0).await.is_empty() {
+                    bot.send_message(msg.chat.id, "Список пуст — переносить нечего.")
+                        .await?;
+                    return Ok(());
+                }
+                let code = issue_move_code(msg.chat.id).await;
+                bot.send_message(
+                    msg.chat.id,
+                    format!(
+                        "Код переноса: {}\nОтправь в другом чате: /move {}\nКод одноразовый и действует 10 минут.",
+                        code, code
+                    ),
+                )
+                .await?;
+                return Ok(());
+            }
+            let Some(source) = MOVE_CODES.remove(&code.to_uppercase()).await else {
+                bot.send_message(msg.chat.id, "Код не найден или истёк.")
+                    .await?;
+                return Ok(());
+            };
+            if source == msg.chat.id {
+                bot.send_message(msg.chat.id, "Это тот же чат — список уже здесь.")
+                    .await?;
+                return Ok(());
+            }
+            let list = storage.get(source.0).await;
+            storage.put(msg.chat.id.0, list).await?;
+            bot.send_message(msg.chat.id, "Список перенесён.").await?;
+            send_list_view(&bot, msg.chat.id, storage).await?;
+        }
+        Command::Merge => {
+            let text = msg.reply_to_message().and_then(message_text_any);
+            let Some(movies) = text.as_deref().and_then(parse_exported_list) else {
+                bot.send_message(
+                    msg.chat.id,
+                    "Ответь командой /merge на сообщение со списком в JSON.",
+                )
+                .await?;
+                return Ok(());
+            };
+            let (added, skipped) = storage.merge_movies(msg.chat.id.0, movies).await?;
+            bot.send_message(
+                msg.chat.id,
+                format!("Добавлено: {}, пропущено: {}", added, skipped),
+            )
+            .await?;
+            if added > 0 {
+                send_list_after_add(&bot, msg.chat.id, storage).await?;
+            }
+        }
+        Command::Again(arg) => {
+            let Some(query) = LAST_QUERY.get(&msg.chat.id).await else {
+                bot.send_message(msg.chat.id, "Нечего повторять — сначала найди что-нибудь.")
+                    .await?;
+                return Ok(());
+            };
+            let arg = arg.trim();
+            let page = if arg.is_empty() {
+                1
+            } else {
+                match arg.parse::<u32>() {
+                    Ok(p) if p >= 1 => p,
+                    _ => {
+                        bot.send_message(msg.chat.id, "Номер страницы: /again 2")
+                            .await?;
+                        return Ok(());
+                    }
+                }
+            };
+            let settings = storage.settings(msg.chat.id.0).await;
+            match tmdb
+                .search_movies_page(&query, 10, settings.search_filter(), page)
+                .await
+            {
+                Ok(results) if results.is_empty() => {
+                    bot.send_message(msg.chat.id, "Ничего не нашёл 😕").await?;
+                }
+                Ok(mut results) => {
+                    sort_search_results(&mut results, settings.search_sort);
+                    send_search_results(&bot, msg.chat.id, results, None).await?
+                }
+                Err(e) => {
+                    bot.send_message(msg.chat.id, e.user_msg()).await?;
+                }
+            }
+        }
+        Command::Find(query) => {
+            let query = query.trim();
+            if query.is_empty() {
+                bot.send_message(msg.chat.id, "Напиши название: /find Начало")
+                    .await?;
+                return Ok(());
+            }
+            remember_query(msg.chat.id, query).await;
+            let settings = storage.settings(msg.chat.id.0).await;
+            let mut results = match tmdb
+                .search_movies_ru(query, 10, settings.search_filter())
+                .await
+            {
+                Ok(v) => v,
+                Err(e) => {
+                    bot.send_message(msg.chat.id, e.user_msg()).await?;
+                    return Ok(());
+                }
+            };
+            if results.is_empty() {
+                bot.send_message(msg.chat.id, "Ничего не нашёл 😕").await?;
+            } else {
+                sort_search_results(&mut results, settings.search_sort);
+                if is_clear_top_hit(&results) {
+                    send_top_hit_card(&bot, msg.chat.id, tmdb, results[0].clone()).await?;
+                } else {
+                    send_search_results(&bot, msg.chat.id, results, None).await?;
+                }
+            }
+        }
+        Command::Where(query) => {
+            let query = query.trim();
+            if query.is_empty() {
+                bot.send_message(msg.chat.id, "Напиши название: /where Оппенгеймер")
+                    .await?;
+                return Ok(());
+            }
+            let settings = storage.settings(msg.chat.id.0).await;
+            let results = match tmdb
+                .search_movies_ru(query, 10, settings.search_filter())
+                .await
+            {
+                Ok(v) => v,
+                Err(e) => {
+                    bot.send_message(msg.chat.id, e.user_msg()).await?;
+                    return Ok(());
+                }
+            };
+            let Some(top) = results
+                .into_iter()
+                .find(|m| !matches!(m.media_type, tmdb::MediaKind::Person))
+            else {
+                bot.send_message(msg.chat.id, "Ничего не нашёл 😕").await?;
+                return Ok(());
+            };
+            let providers = match tmdb.watch_providers(&top, &settings.region).await {
+                Ok(p) => p,
+                Err(e) => {
+                    bot.send_message(msg.chat.id, e.user_msg()).await?;
+                    return Ok(());
+                }
+            };
+            bot.send_message(
+                msg.chat.id,
+                where_text(&top, &settings.region, providers.as_ref()),
+            )
+            .parse_mode(ParseMode::Html)
+            .await?;
+        }
+        Command::Watched(arg) => {
+            let list = storage.get(msg.chat.id.0).await;
+            let picked = arg
+                .trim()
+                .parse::<usize>()
+                .ok()
+                .and_then(|n| n.checked_sub(1))
+                .and_then(|i| list.get(i));
+            let Some(m) = picked else {
+                bot.send_message(
+                    msg.chat.id,
+                    "Укажи номер фильма из /list, например: /watched 2",
+                )
+                .await?;
+                return Ok(());
+            };
+            storage
+                .toggle_watched(msg.chat.id.0, m.id, m.media_type)
+                .await?;
+            send_list_view(&bot, msg.chat.id, storage).await?;
+        }
+        Command::Remove(arg) => {
+            let list = storage.get(msg.chat.id.0).await;
+            let picked = arg
+                .trim()
+                .parse::<usize>()
+                .ok()
+                .and_then(|n| n.checked_sub(1))
+                .and_then(|i| list.get(i));
+            let Some(m) = picked else {
+                bot.send_message(
+                    msg.chat.id,
+                    "Укажи номер фильма из /list, например: /remove 2",
+                )
+                .await?;
+                return Ok(());
+            };
+            storage
+                .delete_movie(msg.chat.id.0, m.id, m.media_type)
+                .await?;
+            bot.send_message(
+                msg.chat.id,
+                format!("Убрал «{}».", one_line_title_stored(m)),
+            )
+            .await?;
+            send_list_view(&bot, msg.chat.id, storage).await?;
+        }
+        Command::ClearWatched => {
+            let removed = storage.retain(msg.chat.id.0, |m| !m.watched).await?;
+            if removed == 0 {
+                bot.send_message(msg.chat.id, "Просмотренных в списке нет.")
+                    .await?;
+                return Ok(());
+            }
+            bot.send_message(msg.chat.id, format!("Убрал просмотренные: {}", removed))
+                .await?;
+            send_list_view(&bot, msg.chat.id, storage).await?;
+        }
+        Command::Backlog(arg) => {
+            let arg = arg.trim();
+            let (sub, rest) = arg.split_once(' ').unwrap_or((arg, ""));
+            match sub.to_lowercase().as_str() {
+                "" | "list" => send_backlog_view(&bot, msg.chat.id, storage).await?,
+                "add" if !rest.trim().is_empty() => {
+                    let filter = storage.settings(msg.chat.id.0).await.search_filter();
+                    let found = match tmdb.search_movies_ru(rest.trim(), 10, filter).await {
+                        Ok(v) => v
+                            .into_iter()
+                            .find(|m| !matches!(m.media_type, tmdb::MediaKind::Person)),
+                        Err(e) => {
+                            bot.send_message(msg.chat.id, e.user_msg()).await?;
+                            return Ok(());
+                        }
+                    };
+                    let Some(m) = found else {
+                        bot.send_message(msg.chat.id, "Ничего не нашёл 😕").await?;
+                        return Ok(());
+                    };
+                    let title = one_line_title(&m);
+                    let added = storage
+                        .add_to_backlog(msg.chat.id.0, StoredMovie::from(m))
+                        .await?;
+                    let text = if added {
+                        format!("В бэклоге: {}", title)
+                    } else {
+                        format!("{} уже в списке или бэклоге (или бэклог полон)", title)
+                    };
+                    bot.send_message(msg.chat.id, text).await?;
+                }
+                _ => {
+                    bot.send_message(
+                        msg.chat.id,
+                        "Использование: /backlog add Название | /backlog list",
+                    )
+                    .await?;
+                }
+            }
+        }
+        Command::Promote(arg) => {
+            let Some(idx) = arg
+                .trim()
+                .parse::<usize>()
+                .ok()
+                .and_then(|n| n.checked_sub(1))
+            else {
+                bot.send_message(
+                    msg.chat.id,
+                    "Укажи номер из /backlog list, например: /promote 3",
+                )
+                .await?;
+                return Ok(());
+            };
+            match storage.promote(msg.chat.id.0, idx).await? {
+                Promote::Moved(m) => {
+                    let text = format!("Перенёс в список: {}", one_line_title_stored(&m));
+                    bot.send_message(msg.chat.id, text).await?;
+                    send_list_view(&bot, msg.chat.id, storage).await?;
+                }
+                Promote::NoSuchItem => {
+                    bot.send_message(msg.chat.id, "В бэклоге нет такого номера.")
+                        .await?;
+                }
+                Promote::ShortlistFull => {
+                    bot.send_message(
+                        msg.chat.id,
+                        "Список уже полон (10). Удали что-нибудь и повтори.",
+                    )
+                    .await?;
+                }
+            }
+        }
+        Command::Rename(arg) => {
+            let arg = arg.trim();
+            let (num, title) = arg.split_once(char::is_whitespace).unwrap_or((arg, ""));
+            let title = title.trim();
+            let Some(idx) = num.parse::<usize>().ok().and_then(|n| n.checked_sub(1)) else {
+                bot.send_message(
+                    msg.chat.id,
+                    "Укажи номер из /list и название, например: /rename 2 Тот самый фильм",
+                )
+                .await?;
+                return Ok(());
+            };
+            let custom = (!title.is_empty()).then(|| clip(title, 100));
+            let renamed = storage.rename_movie(msg.chat.id.0, idx, custom).await?;
+            if renamed {
+                send_list_view(&bot, msg.chat.id, storage).await?;
+            } else {
+                bot.send_message(msg.chat.id, "В списке нет такого номера.")
+                    .await?;
+            }
+        }
+        Command::Poster(arg) => {
+            let list = storage.get(msg.chat.id.0).await;
+            let picked = arg
+                .trim()
+                .parse::<usize>()
+                .ok()
+                .and_then(|n| n.checked_sub(1))
+                .and_then(|i| list.get(i));
+            let Some(m) = picked else {
+                bot.send_message(
+                    msg.chat.id,
+                    "Укажи номер фильма из /list, например: /poster 2",
+                )
+                .await?;
+                return Ok(());
+            };
+            send_poster(&bot, msg.chat.id, tmdb, m).await?;
+        }
+        Command::SetQuestion(arg) => {
+            let arg = arg.trim();
+            if arg.is_empty() {
+                let current = poll_question(&storage.settings(msg.chat.id.0).await);
+                bot.send_message(
+                    msg.chat.id,
+                    format!(
+                        "Вопрос опроса: {}\nИзменить: /setquestion Пятничное кино?\nВернуть стандартный: /setquestion reset",
+                        current
+                    ),
+                )
+                .await?;
+                return Ok(());
+            }
+            let question = if arg.eq_ignore_ascii_case("reset") {
+                None
+            } else {
+                Some(clip_plain(arg, POLL_QUESTION_LIMIT))
+            };
+            storage
+                .update_settings(msg.chat.id.0, |s| s.vote_question = question)
+                .await?;
+            let current = poll_question(&storage.settings(msg.chat.id.0).await);
+            bot.send_message(msg.chat.id, format!("Теперь опрос спросит: {}", current))
+                .await?;
+        }
+        Command::Vote(arg) if arg.trim().eq_ignore_ascii_case("preview") => {
+            let list = vote_candidates(storage.get(msg.chat.id.0).await);
+            let settings = storage.settings(msg.chat.id.0).await;
+            bot.send_message(msg.chat.id, vote_preview_text(&list, &settings))
+                .parse_mode(ParseMode::Html)
+                .await?;
+        }
+        Command::Episodes(arg) => {
+            let Some((season, start)) = parse_episodes_arg(&arg) else {
+                bot.send_message(
+                    msg.chat.id,
+                    "Укажи сезон: /episodes 2 (или /episodes 2 11 — с 11-й серии)",
+                )
+                .await?;
+                return Ok(());
+            };
+            let Some((show_id, show_title)) = selected_show(msg.chat.id, storage).await else {
+                bot.send_message(
+                    msg.chat.id,
+                    "Сначала открой карточку сериала (🎬 в /list) или добавь сериал в список.",
+                )
+                .await?;
+                return Ok(());
+            };
+            let season = match tmdb.season(show_id, season).await {
+                Ok(s) => s,
+                Err(e) => {
+                    bot.send_message(msg.chat.id, e.user_msg()).await?;
+                    return Ok(());
+                }
+            };
+            let options = episode_poll_options(&season.episodes, start);
+            if options.len() < 2 {
+                bot.send_message(msg.chat.id, "Для опроса нужно хотя бы две серии.")
+                    .await?;
+                return Ok(());
+            }
+            let question = clip_plain(
+                &format!("Какую серию смотрим? {} — {}", show_title, season.name),
+                POLL_QUESTION_LIMIT,
+            );
+            let poll = bot
+                .send_poll(
+                    msg.chat.id,
+                    question,
+                    options
+                        .into_iter()
+                        .map(teloxide::types::InputPollOption::new),
+                )
+                .is_anonymous(anonymous)
+                .allows_multiple_answers(multiple);
+            if let Err(e) = send_retry(poll).await {
+                if !is_poll_forbidden(&e) {
+                    return Err(e.into());
+                }
+                bot.send_message(
+                    msg.chat.id,
+                    "У меня нет прав на создание опросов в этом чате",
+                )
+                .await?;
+            }
+        }
+        Command::Vote(_) => {
+            let kind = PollKind::Regular;
+            run_vote_flow(&bot, msg.chat.id, tmdb, storage, anonymous, multiple, kind).await?
+        }
+        Command::Quiz => {
+            let kind = PollKind::Quiz;
+            run_vote_flow(&bot, msg.chat.id, tmdb, storage, anonymous, multiple, kind).await?
+        }
+    }
+    Ok(())
+}
+
+// первые страницы popularity.desc — то, что большинство слышало
+const SURPRISE_PAGES: u32 = 20;
+
+fn pick_surprise(found: Vec<MultiNorm>, list: &[StoredMovie]) -> Option<MultiNorm> {
+    let fresh: Vec<MultiNorm> = found
+        .into_iter()
+        .filter(|m| {
+            !list
+                .iter()
+                .any(|sm| sm.id == m.id && sm.media_type == m.media_type)
+        })
+        .collect();
+    if fresh.is_empty() {
+        return None;
+    }
+    let i = fastrand::usize(..fresh.len());
+    fresh.into_iter().nth(i)
+}
+
+// команды, меняющие список или запускающие опрос
+fn is_mutation(cmd: &Command) -> bool {
+    match cmd {
+        Command::Reset | Command::ClearWatched | Command::Quiz | Command::Sort(_) => true,
+        Command::Vote(arg) => !arg.trim().eq_ignore_ascii_case("preview"),
+        Command::Move(arg) => !arg.trim().is_empty(), // импорт по коду
+        Command::Schedule(arg) | Command::Episodes(arg) => !arg.trim().is_empty(),
+        Command::Backlog(arg) => arg.trim().to_lowercase().starts_with("add"),
+        Command::Promote(_) | Command::Rename(_) | Command::Merge => true,
+        Command::Watched(arg)
+        | Command::Remove(arg)
+        | Command::Lang(arg)
+        | Command::SetRegion(arg) => !arg.trim().is_empty(),
+        Command::Start(payload) => !payload.trim().is_empty(), // диплинк «добавить фильм»
+        Command::NoPosters | Command::Posters => true,
+        Command::SetQuestion(arg) => !arg.trim().is_empty(),
+        Command::Adult(arg)
+        | Command::MinVotes(arg)
+        | Command::Original(arg)
+        | Command::AutoList(arg)
+        | Command::RichTrailers(arg)
+        | Command::SearchSort(arg)
+        | Command::AutoPin(arg) => !arg.trim().is_empty(),
+        _ => false,
+    }
+}
+
+/// В личке — всегда; в группе при ADMIN_ONLY_MUTATIONS — только админ или создатель.
+async fn may_mutate<R>(
+    bot: &R,
+    chat: &teloxide::types::Chat,
+    user: Option<&teloxide::types::User>,
+    admin_only: bool,
+) -> bool
+where
+    R: Requester<Err = RequestError>,
+{
+    if chat.is_private() || !admin_only {
+        return true;
+    }
+    let Some(user) = user else {
+        return false;
+    };
+    match bot.get_chat_member(chat.id, user.id).await {
+        Ok(member) => member.is_privileged(),
+        Err(e) => {
+            tracing::warn!("getChatMember in {} failed: {}", chat.id, e);
+            false
+        }
+    }
+}
+
+// то же для сообщения: анонимный админ пишет от имени самой группы
+async fn message_may_mutate<R>(bot: &R, msg: &Message) -> bool
+where
+    R: Requester<Err = RequestError>,
+{
+    let anon_admin = msg
+        .sender_chat
+        .as_ref()
+        .is_some_and(|c| c.id == msg.chat.id);
+    anon_admin || may_mutate(bot, &msg.chat, msg.from.as_ref(), *ADMIN_ONLY_MUTATIONS).await
+}
+
+// кнопки, которые меняют список или настройки чата, — под тем же замком, что и команды
+fn is_mutating_callback(cmd: &str) -> bool {
+    matches!(
+        cmd,
+        "add" | "addforce" | "watched" | "del" | "adult" | "starter"
+    )
+}
+
+async fn callback_may_mutate<R>(bot: &R, q: &CallbackQuery, admin_only: bool) -> bool
+where
+    R: Requester<Err = RequestError>,
+{
+    let cmd = q.data.as_deref().and_then(|d| d.split(':').next());
+    if !cmd.is_some_and(is_mutating_callback) {
+        return true;
+    }
+    match q.message.as_ref() {
+        Some(m) => may_mutate(bot, m.chat(), Some(&q.from), admin_only).await,
+        None => false,
+    }
+}
+
+fn is_bot_admin(from: Option<&teloxide::types::User>, admin: Option<UserId>) -> bool {
+    matches!((from, admin), (Some(u), Some(a)) if u.id == a)
+}
+
+// «📝 Отзыв из чата -100… «Киноклуб» от @nick (id 42):» + текст; без разметки
+fn feedback_report(
+    chat: ChatId,
+    chat_title: Option<&str>,
+    from: Option<&teloxide::types::User>,
+    text: &str,
+) -> String {
+    let place = match chat_title {
+        Some(t) => format!("{} «{}»", chat, t),
+        None => chat.to_string(),
+    };
+    let author = match from {
+        Some(u) => match &u.username {
+            Some(name) => format!("@{} (id {})", name, u.id),
+            None => format!("{} (id {})", u.full_name(), u.id),
+        },
+        None => "неизвестного".to_string(),
+    };
+    format!("📝 Отзыв из чата {} от {}:\n{}", place, author, text)
+}
+
+// код без похожих символов (0/O, 1/I), чтобы его можно было переписать руками
+async fn issue_move_code(source: ChatId) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+    let code: String = (0..6)
+        .map(|_| ALPHABET[fastrand::usize(..ALPHABET.len())] as char)
+        .collect();
+    MOVE_CODES.insert(code.clone(), source).await;
+    code
+}
+
+// deep link: t.me/<bot>?start=add_movie_550 / add_tv_1399
+fn parse_start_payload(payload: &str) -> Option<(u64, tmdb::MediaKind)> {
+    let rest = payload.trim().strip_prefix("add_")?;
+    let (kind, id) = rest.split_once('_')?;
+    let kind = match kind {
+        "movie" => tmdb::MediaKind::Movie,
+        "tv" => tmdb::MediaKind::Tv,
+        _ => return None,
+    };
+    Some((id.parse().ok()?, kind))
+}
+
+/* ====== Поиск по тексту ====== */
+async fn on_search_text<R>(bot: R, msg: Message, tmdb: &TmdbClient, storage: &Storage) -> BotResult
+where
+    R: Requester<Err = RequestError>,
+{
+    let Some(query) = message_text_any(&msg) else {
+        return Ok(());
+    };
+    let query = query.trim();
+    if query.is_empty() {
+        return Ok(());
+    }
+
+    // ответ числом на сообщение с результатами — добавить N-й вариант
+    if let (Ok(n), Some(reply)) = (query.parse::<usize>(), msg.reply_to_message()) {
+        if let Some(results) = LAST_SEARCH.get(&(msg.chat.id, reply.id.0)).await {
+            let Some(m) = n.checked_sub(1).and_then(|i| results.get(i)).cloned() else {
+                bot.send_message(
+                    msg.chat.id,
+                    format!("Выбери номер от 1 до {}.", results.len()),
+                )
+                .await?;
+                return Ok(());
+            };
+            if !message_may_mutate(&bot, &msg).await {
+                bot.send_message(msg.chat.id, ADMIN_ONLY_TEXT).await?;
+                return Ok(());
+            }
+            let added = storage.add_movie(msg.chat.id.0, m.into()).await?;
+            if added {
+                send_list_after_add(&bot, msg.chat.id, storage).await?;
+            } else if storage.count(msg.chat.id.0).await >= MAX_LIST_LEN {
+                bot.send_message(msg.chat.id, "В списке уже 10 фильмов")
+                    .await?;
+            } else {
+                bot.send_message(msg.chat.id, "Уже в списке").await?;
+            }
+            return Ok(());
+        }
+    }
+
+    if !SEARCH_LIMIT.check(msg.chat.id.0) {
+        bot.send_message(msg.chat.id, COOLDOWN_TEXT).await?;
+        return Ok(());
+    }
+
+    // несколько строк — список из заметок: каждая строка отдельным поиском
+    let lines: Vec<&str> = query
+        .lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty())
+        .collect();
+    if lines.len() > 1 {
+        if !message_may_mutate(&bot, &msg).await {
+            bot.send_message(msg.chat.id, ADMIN_ONLY_TEXT).await?;
+            return Ok(());
+        }
+        return bulk_import(&bot, msg.chat.id, tmdb, storage, &lines).await;
+    }
+
+    search_and_show(&bot, &msg.chat, tmdb, storage, query).await
+}
+
+// поиск по запросу (до 10) и выдача в чат; запрос запоминается для /again и /history
+async fn search_and_show<R>(
+    bot: &R,
+    chat: &Chat,
+    tmdb: &TmdbClient,
+    storage: &Storage,
+    query: &str,
+) -> BotResult
+where
+    R: Requester<Err = RequestError>,
+{
+    remember_query(chat.id, query).await;
+    let settings = storage.settings(chat.id.0).await;
+    let mut hits = match tmdb
+        .search_with_person(query, 10, settings.search_filter(), 1)
+        .await
+    {
+        Ok(v) => v,
+        Err(e) => {
+            bot.send_message(chat.id, e.user_msg()).await?;
+            return Ok(());
+        }
+    };
+
+    if hits.items.is_empty() {
+        match hits.person.as_ref() {
+            // фильмов нет, но нашёлся человек — хотя бы его фильмография
+            Some(p) => {
+                bot.send_message(chat.id, "Фильмов не нашёл 😕")
+                    .reply_markup(keyboard_add_results(&[], Some(p)))
+                    .await?;
+            }
+            None => {
+                bot.send_message(chat.id, "Ничего не нашёл 😕").await?;
+            }
+        }
+        return Ok(());
+    }
+
+    sort_search_results(&mut hits.items, settings.search_sort);
+    // в личке явного лидера показываем подробной карточкой; в группе — всегда
+    // компактный список с кнопками, чтобы не засорять общий чат постерами
+    let person = hits
+        .person
+        .filter(|_| hits.items.len() <= PERSON_HINT_MAX_HITS);
+    match hits.items.first() {
+        Some(top) if chat.is_private() && is_clear_top_hit(&hits.items) => {
+            send_top_hit_card(bot, chat.id, tmdb, top.clone()).await?
+        }
+        _ => send_search_results(bot, chat.id, hits.items, person.as_ref()).await?,
+    }
+    Ok(())
+}
+
+async fn remember_query(chat: ChatId, query: &str) {
+    LAST_QUERY.insert(chat, query.to_string()).await;
+    let history = SEARCH_HISTORY.get(&chat).await.unwrap_or_default();
+    SEARCH_HISTORY
+        .insert(chat, push_history(history, query))
+        .await;
+}
+
+// свежий запрос — в начало; повтор (без учёта регистра) переезжает наверх,
+// всё дальше HISTORY_MAX отрезаем
+fn push_history(mut history: Vec<String>, query: &str) -> Vec<String> {
+    let query = query.trim();
+    history.retain(|q| q.to_lowercase() != query.to_lowercase());
+    history.insert(0, query.to_string());
+    history.truncate(HISTORY_MAX);
+    history
+}
+
+/* порядок выдачи по /searchsort; сортировка устойчивая — при равенстве
+   остаётся порядок TMDb, без даты/рейтинга — в конец */
+fn sort_search_results(results: &mut [MultiNorm], by: SearchSort) {
+    match by {
+        SearchSort::Popularity => {}
+        SearchSort::Date => results.sort_by(|a, b| {
+            let date = |m: &MultiNorm| m.release_date.clone().filter(|d| !d.trim().is_empty());
+            date(b).cmp(&date(a))
+        }),
+        SearchSort::Rating => results.sort_by(|a, b| {
+            let a = a.vote_average.unwrap_or(f64::NEG_INFINITY);
+            let b = b.vote_average.unwrap_or(f64::NEG_INFINITY);
+            b.total_cmp(&a)
+        }),
+    }
+}
+
+/// Фильмов в выдаче не больше — значит, искали скорее человека: предложим его фильмографию.
+const PERSON_HINT_MAX_HITS: usize = 3;
+
+/* текст, по которому уже искали для (чат, сообщение) — чтобы повторная правка
+   без изменений (или эхо того же апдейта) не запускала поиск ещё раз */
+static EDITED_QUERIES: Lazy<Cache<(ChatId, i32), String>> = Lazy::new(|| {
+    Cache::builder()
+        .time_to_live(std::time::Duration::from_secs(24 * 60 * 60))
+        .max_capacity(10_000)
+        .build()
+});
+
+// исправили опечатку в запросе — ищем заново; правки команд, ответов номером,
+// многострочных списков и сообщений ботов не трогаем
+async fn on_edited_search_text<R>(
+    bot: R,
+    msg: Message,
+    tmdb: &TmdbClient,
+    storage: &Storage,
+) -> BotResult
+where
+    R: Requester<Err = RequestError>,
+{
+    if msg.from.as_ref().is_some_and(|u| u.is_bot) || msg.reply_to_message().is_some() {
+        return Ok(());
+    }
+    let Some(text) = msg.text().map(str::trim) else {
+        return Ok(());
+    };
+    if text.is_empty() || text.starts_with('/') || text.lines().count() > 1 {
+        return Ok(());
+    }
+    let key = (msg.chat.id, msg.id.0);
+    if EDITED_QUERIES.get(&key).await.as_deref() == Some(text) {
+        return Ok(());
+    }
+    EDITED_QUERIES.insert(key, text.to_string()).await;
+    on_search_text(bot, msg, tmdb, storage).await
+}
+
+// нумерованные описания + кнопки «➕», результаты — в LAST_SEARCH
+async fn send_search_results<R>(
+    bot: &R,
+    chat: ChatId,
+    results: Vec<MultiNorm>,
+    person: Option<&MultiNorm>,
+) -> ResponseResult<()>
+where
+    R: Requester<Err = RequestError>,
+{
+    // Сообщение с названиями + краткими описаниями
+    let mut blocks = Vec::new();
+    for (i, m) in results.iter().enumerate() {
+        // номер — чтобы можно было ответить на сообщение цифрой
+        let block = make_block(m, OVERVIEW_LIMITS.search, Lang::default()); // описания укоротим
+        blocks.push(format!("{}. {}", i + 1, block));
+    }
+    let text = join_blocks(blocks, 3500); // запас до 4096
+    let text_msg = bot
+        .send_message(chat, text)
+        .parse_mode(ParseMode::Html)
+        .await?;
+
+    // Кнопки "➕ <Название (год)>"
+    let kb = keyboard_add_results(&results, person);
+    let sent_msg = bot
+        .send_message(chat, "Выбери фильм, чтобы добавить в список:")
+        .reply_markup(kb)
+        .await?;
+
+    // ответить номером можно на любое из двух сообщений
+    LAST_SEARCH
+        .insert((chat, text_msg.id.0), results.clone())
+        .await;
+    LAST_SEARCH.insert((chat, sent_msg.id.0), results).await;
+
+    Ok(())
+}
+
+/// Больше строк за раз не ищем — TMDb и чат не заспамим.
+const BULK_IMPORT_MAX_LINES: usize = 30;
+
+// лучший результат по каждой строке — в список; в конце сводка по строкам
+async fn bulk_import<R>(
+    bot: &R,
+    chat: ChatId,
+    tmdb: &TmdbClient,
+    storage: &Storage,
+    lines: &[&str],
+) -> BotResult
+where
+    R: Requester<Err = RequestError>,
+{
+    let filter = storage.settings(chat.0).await.search_filter();
+    let mut added = 0;
+    let mut report = Vec::new();
+    for line in lines.iter().take(BULK_IMPORT_MAX_LINES) {
+        let line_html = html_escape(&clip(line, 60));
+        let top = match tmdb.search_movies_ru(line, 1, filter).await {
+            Ok(v) => v.into_iter().next(),
+            Err(e) => {
+                report.push(format!("⚠️ {} — {}", line_html, e.user_msg()));
+                continue;
+            }
+        };
+        let Some(m) = top else {
+            report.push(format!("⚠️ {} — не нашёл", line_html));
+            continue;
+        };
+        let title = html_escape(&one_line_title(&m));
+        if storage.add_movie(chat.0, m.into()).await? {
+            added += 1;
+            report.push(format!("✅ {}", title));
+        } else if storage.count(chat.0).await >= MAX_LIST_LEN {
+            report.push(format!("⚠️ {} — в списке уже 10 фильмов", title));
+        } else {
+            report.push(format!("⚠️ {} — уже в списке", title));
+        }
+    }
+    if lines.len() > BULK_IMPORT_MAX_LINES {
+        report.push(format!(
+            "…ещё {} строк пропущено (не больше {} за раз)",
+            lines.len() - BULK_IMPORT_MAX_LINES,
+            BULK_IMPORT_MAX_LINES
+        ));
+    }
+    let text = format!(
+        "<b>Импорт: добавлено {} из {}</b>\n{}",
+        added,
+        lines.len(),
+        report.join("\n")
+    );
+    send_long(bot, chat, &text, Some(ParseMode::Html)).await?;
+    if added > 0 {
+        send_list_after_add(bot, chat, storage).await?;
+    }
+    Ok(())
+}
+
+// «явный» лидер поиска: достаточно голосов и заметный отрыв от второго
+fn is_clear_top_hit(results: &[MultiNorm]) -> bool {
+    const MIN_VOTES: u64 = 100;
+    let Some(top) = results.first() else {
+        return false;
+    };
+    let top_votes = top.vote_count.unwrap_or(0);
+    if top_votes < MIN_VOTES {
+        return false;
+    }
+    match results.get(1) {
+        Some(second) => top_votes >= second.vote_count.unwrap_or(0) * 2,
+        None => true,
+    }
+}
+
+// карточка одного фильма (постер + описание) с кнопкой «➕ Добавить»
+async fn send_top_hit_card<R>(
+    bot: &R,
+    chat: ChatId,
+    tmdb: &TmdbClient,
+    m: MultiNorm,
+) -> ResponseResult<()>
+where
+    R: Requester<Err = RequestError>,
+{
+    let kb = InlineKeyboardMarkup::new(vec![vec![InlineKeyboardButton::callback(
+        "➕ Добавить".to_string(),
+        format!("add:{}:{}", m.id, m.media_type.as_str()),
+    )]]);
+    let poster = match m.poster_path() {
+        Some(p) => fetch_image(&tmdb.image_url(p)).await.ok(),
+        None => None,
+    };
+    let sent = match poster {
+        Some(bytes) => {
+            send_retry(
+                bot.send_photo(
+                    chat,
+                    InputFile::memory(bytes).file_name(format!("poster_{}.jpg", m.id)),
+                )
+                // подпись к фото — до 1024 символов
+                .caption(make_block(
+                    &m,
+                    OVERVIEW_LIMITS.detail.min(700),
+                    Lang::default(),
+                ))
+                .parse_mode(ParseMode::Html)
+                .reply_markup(kb),
+            )
+            .await?
+        }
+        None => {
+            send_retry(
+                bot.send_message(
+                    chat,
+                    make_block(&m, OVERVIEW_LIMITS.detail, Lang::default()),
+                )
+                .parse_mode(ParseMode::Html)
+                .reply_markup(kb),
+            )
+            .await?
+        }
+    };
+    // чтобы «➕» не ходил в TMDb повторно
+    LAST_SEARCH.insert((chat, sent.id.0), vec![m]).await;
+    Ok(())
+}
+
+// один постер без подписи; нет постера или не скачался — говорим текстом
+async fn send_poster<R>(
+    bot: &R,
+    chat: ChatId,
+    tmdb: &TmdbClient,
+    m: &StoredMovie,
+) -> ResponseResult<()>
+where
+    R: Requester<Err = RequestError>,
+{
+    let Some(p) = &m.poster_path else {
+        send_retry(bot.send_message(
+            chat,
+            format!("У «{}» нет постера в TMDb.", m.display_title()),
+        ))
+        .await?;
+        return Ok(());
+    };
+    match fetch_image(&tmdb.image_url_sized(p, ImageSize::W780)).await {
+        Ok(bytes) => {
+            send_retry(bot.send_photo(
+                chat,
+                InputFile::memory(bytes).file_name(format!("poster_{}.jpg", m.id)),
+            ))
+            .await?;
+        }
+        Err(e) => {
+            tracing::warn!("poster for {} failed: {}", m.id, e);
+            send_retry(bot.send_message(chat, "Не удалось загрузить постер 😕")).await?;
+        }
+    }
+    Ok(())
+}
+
+/* ====== Inline-режим: @bot <название> в любом чате ====== */
+async fn on_inline_query<R>(bot: R, q: InlineQuery, tmdb: &TmdbClient) -> ResponseResult<()>
+where
+    R: Requester<Err = RequestError>,
+{
+    let query = q.query.trim().to_lowercase();
+    if query.is_empty() {
+        return Ok(());
+    }
+
+    let results = match INLINE_SEARCH.get(&query).await {
+        Some(v) => v,
+        // inline не привязан к чату — настройки по умолчанию, без взрослого контента
+        None => match tmdb
+            .search_movies_ru(&query, 10, ChatSettings::default().search_filter())
+            .await
+        {
+            Ok(v) => {
+                INLINE_SEARCH.insert(query.clone(), v.clone()).await;
+                v
+            }
+            Err(e) => {
+                tracing::warn!("inline search {:?} failed: {}", query, e);
+                Vec::new()
+            }
+        },
+    };
+
+    let articles: Vec<InlineQueryResult> = results
+        .iter()
+        .map(|m| InlineQueryResult::Article(inline_article(m, tmdb)))
+        .collect();
+    bot.answer_inline_query(q.id, articles)
+        .cache_time(300)
+        .await?;
+    Ok(())
+}
+
+fn inline_article(m: &MultiNorm, tmdb: &TmdbClient) -> InlineQueryResultArticle {
+    let content = InputMessageContent::Text(
+        InputMessageContentText::new(make_block(m, OVERVIEW_LIMITS.search, Lang::default()))
+            .parse_mode(ParseMode::Html),
+    );
+    let mut article = InlineQueryResultArticle::new(
+        format!("{}:{}", m.media_type.as_str(), m.id),
+        one_line_title(m),
+        content,
+    );
+    if !m.overview.trim().is_empty() {
+        article = article.description(clip(&m.overview, 200));
+    }
+    if let Some(url) = m
+        .image_path
+        .as_ref()
+        .and_then(|p| tmdb.image_url(p).parse().ok())
+    {
+        article = article.thumbnail_url(url);
+    }
+    article
+}
+
+/* ====== Callback-кнопки ======
+   add:<id>   — добавить найденный фильм в список
+   del:<id>   — удалить из списка
+   show:<id>  — показать постер+описание из TMDb
+   gallery:<id> — альтернативные постеры (до 10)
+   watched:<id> — переключить отметку «просмотрено»
+   person:<id> — фильмография персоны из поиска (кнопки «➕» как в выдаче)
+   */
+async fn on_callback<R>(bot: R, q: CallbackQuery, tmdb: &TmdbClient, storage: &Storage) -> BotResult
+where
+    R: Requester<Err = RequestError>,
+{
+    let Some(data) = q.data.clone() else {
+        return Ok(());
+    };
+    let chat_id = q.message.as_ref().map(|m| m.chat().id).unwrap_or(ChatId(0));
+    let mut parts = data.splitn(3, ':');
+    let cmd = parts.next().unwrap_or("");
+    let id_str = parts.next().unwrap_or("");
+    let media_type_str = parts.next().unwrap_or("");
+    let Ok(id) = id_str.parse::<u64>() else {
+        return Ok(());
+    };
+
+    let media_type = if media_type_str == "tv" {
+        tmdb::MediaKind::Tv
+    } else if media_type_str == "person" {
+        tmdb::MediaKind::Person
+    } else {
+        tmdb::MediaKind::Movie
+    };
+
+    if !callback_may_mutate(&bot, &q, *ADMIN_ONLY_MUTATIONS).await {
+        answer_cb(&bot, &q, ADMIN_ONLY_TEXT).await?;
+        return Ok(());
+    }
+
+    match cmd {
+        "add" | "addforce" => {
+            let message_id = q.message.as_ref().map(|m| m.id().0).unwrap_or(0);
+            // id у фильма и сериала могут совпасть; в старых кнопках типа нет — только id
+            let mut movie_opt = LAST_SEARCH.get(&(chat_id, message_id)).await.and_then(|v| {
+                v.iter()
+                    .find(|m| {
+                        m.id == id && (media_type_str.is_empty() || m.media_type == media_type)
+                    })
+                    .cloned()
+            });
+
+            if movie_opt.is_none() {
+                if let Ok(Some(m)) = tmdb.movie_details_ru(id, media_type).await {
+                    movie_opt = Some(m);
+                }
+            }
+
+            if let Some(m) = movie_opt {
+                if cmd == "add" {
+                    let current = storage.get(chat_id.0).await;
+                    if let Some(dup) = cross_media_duplicate(&current, &m) {
+                        let text = format!(
+                            "«{}» уже есть как {}, добавить и {}?",
+                            one_line_title_stored(dup),
+                            media_kind_name(dup.media_type),
+                            media_kind_name(m.media_type)
+                        );
+                        let kb = InlineKeyboardMarkup::new(vec![vec![
+                            InlineKeyboardButton::callback(
+                                "➕ Да, добавить".to_string(),
+                                format!("addforce:{}:{}", m.id, m.media_type.as_str()),
+                            ),
+                            InlineKeyboardButton::callback("Нет".to_string(), "nop:0".to_string()),
+                        ]]);
+                        let sent =
+                            send_retry(bot.send_message(chat_id, text).reply_markup(kb)).await?;
+                        // «Да» возьмёт фильм отсюда, без похода в TMDb
+                        LAST_SEARCH.insert((chat_id, sent.id.0), vec![m]).await;
+                        answer_cb(&bot, &q, "Уже есть в другом формате").await?;
+                        return Ok(());
+                    }
+                }
+                let added = storage.add_movie(chat_id.0, m.into()).await?;
+                if added {
+                    answer_cb(&bot, &q, "Добавлено").await?;
+                    send_list_after_add(&bot, chat_id, storage).await?;
+                } else {
+                    // либо уже есть, либо переполнено
+                    // уточним причину:
+                    let current = storage.get(chat_id.0).await;
+                    if current.len() >= 10 {
+                        answer_cb(&bot, &q, "В списке уже 10 фильмов").await?;
+                    } else {
+                        answer_cb(&bot, &q, "Уже в списке").await?;
+                    }
+                }
+            } else {
+                answer_cb(&bot, &q, "Не нашёл фильм в последнем поиске").await?;
+            }
+        }
+        "person" => {
+            let (name, films) = match tmdb.person_filmography(id).await {
+                Ok(v) => v,
+                Err(e) => {
+                    answer_cb(&bot, &q, e.user_msg()).await?;
+                    return Ok(());
+                }
+            };
+            if films.is_empty() {
+                answer_cb(&bot, &q, "Фильмов не нашёл").await?;
+                return Ok(());
+            }
+            answer_cb(&bot, &q, "Показал").await?;
+            send_retry(bot.send_message(chat_id, format!("🎭 Фильмы: {}", name))).await?;
+            send_search_results(&bot, chat_id, films, None).await?;
+        }
+        "adult" => {
+            storage
+                .update_settings(chat_id.0, |s| s.include_adult = true)
+                .await?;
+            answer_cb(&bot, &q, "Готово").await?;
+            send_retry(bot.send_message(
+                chat_id,
+                "🔞 Взрослый контент в поиске включён. Выключить: /adult off",
+            ))
+            .await?;
+        }
+        "starter" => {
+            if storage.count(chat_id.0).await > 0 {
+                answer_cb(&bot, &q, "Список уже не пуст").await?;
+                return Ok(());
+            }
+            let mut movies = Vec::new();
+            for &(id, kind) in STARTER_LIST.iter() {
+                match tmdb.movie_details_ru(id, kind).await {
+                    Ok(Some(m)) => movies.push(StoredMovie::from(m)),
+                    Ok(None) => tracing::warn!("STARTER_LIST: {} {} not found", kind.as_str(), id),
+                    Err(e) => {
+                        tracing::warn!("STARTER_LIST: {} {} failed: {}", kind.as_str(), id, e)
+                    }
+                }
+            }
+            if movies.is_empty() {
+                answer_cb(&bot, &q, "Не удалось загрузить примерный список").await?;
+                return Ok(());
+            }
+            // пока грузили, в список могли добавить или нажать кнопку ещё раз
+            if !storage.put_if_empty(chat_id.0, movies).await? {
+                answer_cb(&bot, &q, "Список уже не пуст").await?;
+                return Ok(());
+            }
+            answer_cb(&bot, &q, "Готово").await?;
+            send_list_view(&bot, chat_id, storage).await?;
+        }
+        "hist" => {
+            let Some(msg) = q.message.as_ref() else {
+                return Ok(());
+            };
+            let query = HISTORY_SHOWN
+                .get(&(chat_id, msg.id().0))
+                .await
+                .and_then(|shown| shown.get(id as usize).cloned());
+            let Some(query) = query else {
+                answer_cb(&bot, &q, "История устарела — открой /history заново").await?;
+                return Ok(());
+            };
+            if !SEARCH_LIMIT.check(chat_id.0) {
+                answer_cb(&bot, &q, COOLDOWN_TEXT).await?;
+                return Ok(());
+            }
+            answer_cb(&bot, &q, &format!("Ищу «{}»", clip_plain(&query, 150))).await?;
+            search_and_show(&bot, msg.chat(), tmdb, storage, &query).await?;
+        }
+        "del" => {
+            let removed = storage.delete_movie(chat_id.0, id, media_type).await?;
+            if removed {
+                answer_cb(&bot, &q, "Удалено").await?;
+                send_list_view(&bot, chat_id, storage).await?;
+            } else {
+                answer_cb(&bot, &q, "Не найдено в списке").await?;
+            }
+        }
+        "show" => {
+            // детали, рейтинг, провайдеры, IMDb и трейлер — одним запросом к TMDb
+            let settings = storage.settings(chat_id.0).await;
+            let region = settings.region;
+            let full = match tmdb
+                .movie_details_full(id, media_type, &region, &settings.language)
+                .await
+            {
+                Ok(Some(full)) => full,
+                Ok(None) => {
+                    answer_cb(&bot, &q, "Фильм не найден").await?;
+                    return Ok(());
+                }
+                Err(e) => {
+                    answer_cb(&bot, &q, e.user_msg()).await?;
+                    return Ok(());
+                }
+            };
+            let m = &full.item;
+            if m.media_type == tmdb::MediaKind::Tv {
+                SELECTED_SHOW.insert(chat_id, (m.id, m.title.clone())).await;
+            }
+            let mut text = make_block(
+                m,
+                OVERVIEW_LIMITS.detail,
+                Lang::from_code(&settings.language),
+            );
+            if !full.cast.is_empty() {
+                text.push_str(&format!(
+                    "\n\n👥 В ролях: {}",
+                    html_escape(&full.cast.join(", "))
+                ));
+            }
+            text.push_str("\n\n");
+            text.push_str(&region_block(
+                &region,
+                full.certification.as_deref(),
+                full.providers.as_ref(),
+            ));
+            let kb = keyboard_show_card(m, full.imdb_id.as_deref(), full.trailer.as_deref());
+            send_retry(
+                bot.send_message(chat_id, text)
+                    .parse_mode(ParseMode::Html)
+                    .reply_markup(kb),
+            )
+            .await?;
+            if let Some(p) = m.poster_path().filter(|_| settings.posters) {
+                let url = tmdb.image_url(p);
+                if let Ok(bytes) = fetch_image(&url).await {
+                    send_retry(bot.send_photo(
+                        chat_id,
+                        InputFile::memory(bytes).file_name(format!("poster_{}.jpg", m.id)),
+                    ))
+                    .await?;
+                }
+            }
+            answer_cb(&bot, &q, "Показал").await?;
+        }
+        "watched" => {
+            let toggled = storage.toggle_watched(chat_id.0, id, media_type).await?;
+            match toggled {
+                Some(true) => {
+                    answer_cb(&bot, &q, "Отмечено как просмотренное").await?;
+                    send_list_view(&bot, chat_id, storage).await?;
+                }
+                Some(false) => {
+                    answer_cb(&bot, &q, "Отметка снята").await?;
+                    send_list_view(&bot, chat_id, storage).await?;
+                }
+                None => answer_cb(&bot, &q, "Не найдено в списке").await?,
+            }
+        }
+        "gallery" => match tmdb.movie_details_ru(id, media_type).await {
+            Ok(Some(m)) => match tmdb.images(&m).await {
+                Ok(paths) if !paths.is_empty() => {
+                    answer_cb(&bot, &q, "Загружаю постеры…").await?;
+                    send_gallery(&bot, chat_id, tmdb, &paths).await?;
+                }
+                Ok(_) => answer_cb(&bot, &q, "Других постеров нет").await?,
+                Err(e) => answer_cb(&bot, &q, e.user_msg()).await?,
+            },
+            Ok(None) => answer_cb(&bot, &q, "Фильм не найден").await?,
+            Err(e) => answer_cb(&bot, &q, e.user_msg()).await?,
+        },
+        "tags" => match tmdb.movie_details_ru(id, media_type).await {
+            Ok(Some(m)) => match tmdb.keywords(&m).await {
+                Ok(tags) if !tags.is_empty() => {
+                    send_retry(
+                        bot.send_message(chat_id, tags_text(&m, &tags))
+                            .parse_mode(ParseMode::Html),
+                    )
+                    .await?;
+                    answer_cb(&bot, &q, "Показал").await?;
+                }
+                Ok(_) => answer_cb(&bot, &q, "Тегов нет").await?,
+                Err(e) => answer_cb(&bot, &q, e.user_msg()).await?,
+            },
+            Ok(None) => answer_cb(&bot, &q, "Фильм не найден").await?,
+            Err(e) => answer_cb(&bot, &q, e.user_msg()).await?,
+        },
+        "nop" => answer_cb(&bot, &q, "Ок").await?,
+        _ => {
+            answer_cb(&bot, &q, "Неизвестная команда").await?;
+        }
+    }
+    Ok(())
+}
+
+// тот же тайтл (название или оригинальное + год) под другим media_type: фильм vs сериал
+fn cross_media_duplicate<'a>(list: &'a [StoredMovie], m: &MultiNorm) -> Option<&'a StoredMovie> {
+    let m_year = format::year_of(&m.release_date);
+    let m_titles = [
+        normalize_title(&m.title),
+        normalize_title(&m.original_title),
+    ];
+    list.iter().find(|sm| {
+        if sm.media_type == m.media_type {
+            return false;
+        }
+        let same_title = [
+            normalize_title(&sm.title),
+            normalize_title(&sm.original_title),
+        ]
+        .iter()
+        .any(|t| !t.is_empty() && m_titles.contains(t));
+        let sm_year = format::year_of(&sm.release_date);
+        let same_year = match (sm_year, m_year) {
+            (Some(a), Some(b)) => a == b,
+            _ => true,
+        };
+        same_title && same_year
+    })
+}
+
+// «Шерлок: Холмс!» ~ «шерлок холмс»: регистр, пунктуация и пробелы не важны
+fn normalize_title(s: &str) -> String {
+    s.chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(char::to_lowercase)
+        .map(|c| if c == 'ё' { 'е' } else { c })
+        .collect()
+}
+
+fn media_kind_name(kind: tmdb::MediaKind) -> &'static str {
+    match kind {
+        tmdb::MediaKind::Movie => "фильм",
+        tmdb::MediaKind::Tv => "сериал",
+        tmdb::MediaKind::Person => "персона",
+    }
+}
+
+// после добавления — список, если чат не выключил это через /autolist
+async fn send_list_after_add<R>(bot: &R, chat: ChatId, storage: &Storage) -> ResponseResult<()>
+where
+    R: Requester<Err = RequestError>,
+{
+    if storage.settings(chat.0).await.auto_list {
+        send_list_view(bot, chat, storage).await?;
+    }
+    Ok(())
+}
+
+/* ====== /list: показать список с кнопками ====== */
+async fn send_list_view<R>(bot: &R, chat: ChatId, storage: &Storage) -> ResponseResult<()>
+where
+    R: Requester<Err = RequestError>,
+{
+    let list = storage.get(chat.0).await;
+    if list.is_empty() {
+        send_retry(bot.send_message(chat, "Список пуст. Пришли название — добавлю варианты."))
+            .await?;
+        return Ok(());
+    }
+    let with_original = storage.settings(chat.0).await.show_original_title;
+    let mut lines = Vec::new();
+    for (i, m) in list.iter().enumerate() {
+        let title = html_escape(&list_title(m, with_original));
+        if m.watched {
+            lines.push(format!("{}. <s>{}</s> ✅", i + 1, title));
+        } else {
+            lines.push(format!("{}. {}", i + 1, title));
+        }
+    }
+    let txt = format!("<b>В списке ({}/10):</b>\n{}", list.len(), lines.join("\n"));
+    let kb = keyboard_list_two_columns_stored(&list);
+    send_retry(
+        bot.send_message(chat, txt)
+            .parse_mode(ParseMode::Html)
+            .reply_markup(kb),
+    )
+    .await?;
+    Ok(())
+}
+
+// «1. Бойцовский клуб (1999) — movie/550»: различить одноимённые фильмы
+fn ids_text(list: &[StoredMovie]) -> String {
+    list.iter()
+        .enumerate()
+        .map(|(i, m)| {
+            format!(
+                "{}. {} — {}/{}",
+                i + 1,
+                one_line_title_stored(m),
+                m.media_type.as_str(),
+                m.id
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+async fn send_backlog_view<R>(bot: &R, chat: ChatId, storage: &Storage) -> ResponseResult<()>
+where
+    R: Requester<Err = RequestError>,
+{
+    let backlog = storage.backlog(chat.0).await;
+    if backlog.is_empty() {
+        send_retry(bot.send_message(chat, "Бэклог пуст. Добавить: /backlog add Название")).await?;
+        return Ok(());
+    }
+    let lines: Vec<String> = backlog
+        .iter()
+        .enumerate()
+        .map(|(i, m)| format!("{}. {}", i + 1, html_escape(&one_line_title_stored(m))))
+        .collect();
+    let txt = format!(
+        "<b>Бэклог ({}):</b>\n{}\n\nВ список для голосования: /promote N",
+        backlog.len(),
+        lines.join("\n")
+    );
+    send_long(bot, chat, &txt, Some(ParseMode::Html)).await
+}
+
+/* ====== /digest: список в личку ======
+   писать первым бот не может — если пользователь не нажимал /start,
+   Telegram отказывает, и мы подсказываем в группе */
+async fn send_digest<R>(
+    bot: &R,
+    chat: &teloxide::types::Chat,
+    user: &teloxide::types::User,
+    storage: &Storage,
+) -> ResponseResult<()>
+where
+    R: Requester<Err = RequestError>,
+{
+    let list = storage.get(chat.id.0).await;
+    let text = digest_text(chat.title().unwrap_or("этого чата"), &list);
+    // в личке «🗑»/«✅» били бы по списку лички, поэтому только карточки
+    let kb = InlineKeyboardMarkup::new(
+        list.iter()
+            .map(|m| {
+                vec![InlineKeyboardButton::callback(
+                    format!("🎬 {}", one_line_title_stored(m)),
+                    format!("show:{}:{}", m.id, m.media_type.as_str()),
+                )]
+            })
+            .collect::<Vec<_>>(),
+    );
+    let dm = ChatId(user.id.0 as i64);
+    match send_retry(
+        bot.send_message(dm, text)
+            .parse_mode(ParseMode::Html)
+            .reply_markup(kb),
+    )
+    .await
+    {
+        Ok(_) if chat.is_private() => {}
+        Ok(_) => {
+            send_retry(bot.send_message(chat.id, "Отправил список в личку ✉️")).await?;
+        }
+        Err(e) if is_dm_closed(&e) => {
+            send_retry(bot.send_message(
+                chat.id,
+                "Не могу написать первым: открой личку с ботом, нажми /start и повтори /digest.",
+            ))
+            .await?;
+        }
+        Err(e) => return Err(e),
+    }
+    Ok(())
+}
+
+fn digest_text(chat_title: &str, list: &[StoredMovie]) -> String {
+    if list.is_empty() {
+        return format!("Список «{}» пуст.", html_escape(chat_title));
+    }
+    let lines: Vec<String> = list
+        .iter()
+        .enumerate()
+        .map(|(i, m)| {
+            let rating = m
+                .vote_average
+                .filter(|v| *v > 0.0)
+                .map(|v| format!(" ★{:.1}", v))
+                .unwrap_or_default();
+            let mark = if m.watched { " ✅" } else { "" };
+            format!(
+                "{}. {}{}{}",
+                i + 1,
+                html_escape(&one_line_title_stored(m)),
+                rating,
+                mark
+            )
+        })
+        .collect();
+    format!(
+        "<b>Список «{}» ({}/{}):</b>\n{}",
+        html_escape(chat_title),
+        list.len(),
+        MAX_LIST_LEN,
+        lines.join("\n")
+    )
+}
+
+// пользователь не начинал диалог с ботом или заблокировал его
+fn is_dm_closed(e: &RequestError) -> bool {
+    match e {
+        RequestError::Api(ApiError::CantInitiateConversation | ApiError::BotBlocked) => true,
+        RequestError::Api(ApiError::Unknown(d)) => d.contains("can't initiate conversation"),
+        _ => false,
+    }
+}
+
+/* ====== Сравнение ======
+   моноширинная таблица; детали (рейтинг, длительность) берём из TMDb,
+   при ошибке — то, что сохранено в списке */
+async fn send_compare<R>(
+    bot: &R,
+    chat: ChatId,
+    tmdb: &TmdbClient,
+    storage: &Storage,
+) -> ResponseResult<()>
+where
+    R: Requester<Err = RequestError>,
+{
+    let list = storage.get(chat.0).await;
+    if list.is_empty() {
+        send_retry(bot.send_message(chat, "Список пуст — сравнивать нечего.")).await?;
+        return Ok(());
+    }
+    let mut rows = Vec::new();
+    for sm in &list {
+        let details = match tmdb.movie_details_ru(sm.id, sm.media_type).await {
+            Ok(d) => d,
+            Err(e) => {
+                tracing::warn!("details for {} failed: {}", sm.id, e);
+                None
+            }
+        };
+        rows.push(match details {
+            Some(m) => CompareRow {
+                title: m.title,
+                year: m.release_date,
+                rating: m.vote_average,
+                runtime: m.runtime,
+            },
+            None => CompareRow {
+                title: sm.title.clone(),
+                year: sm.release_date.clone(),
+                rating: sm.vote_average,
+                runtime: None,
+            },
+        });
+    }
+    for part in compare_tables(&rows, 4000) {
+        send_retry(bot.send_message(chat, part).parse_mode(ParseMode::Html)).await?;
+    }
+    Ok(())
+}
+
+struct CompareRow {
+    title: String,
+    year: Option<String>,
+    rating: Option<f64>,
+    runtime: Option<u32>,
+}
+
+const COMPARE_TITLE_WIDTH: usize = 20;
+
+// строки таблицы режем по сообщениям целиком, каждое — в своём <pre>
+fn compare_tables(rows: &[CompareRow], limit: usize) -> Vec<String> {
+    let pad =
+        |s: &str, w: usize| format!("{}{}", s, " ".repeat(w.saturating_sub(s.chars().count())));
+    let header = format!(
+        "{}{}{}{}{}",
+        pad("#", 3),
+        pad("Название", COMPARE_TITLE_WIDTH + 2),
+        pad("Год", 6),
+        pad("★", 5),
+        "Мин"
+    );
+    let mut lines = Vec::new();
+    for (i, r) in rows.iter().enumerate() {
+        let title = clip(&r.title, COMPARE_TITLE_WIDTH);
+        let year = format::year_of(&r.year).unwrap_or("—");
+        let rating = r
+            .rating
+            .filter(|v| *v > 0.0)
+            .map(|v| format!("{:.1}", v))
+            .unwrap_or_else(|| "—".to_string());
+        let runtime = r
+            .runtime
+            .filter(|v| *v > 0)
+            .map(|v| v.to_string())
+            .unwrap_or_else(|| "—".to_string());
+        lines.push(format!(
+            "{}{}{}{}{}",
+            pad(&(i + 1).to_string(), 3),
+            pad(&title, COMPARE_TITLE_WIDTH + 2),
+            pad(year, 6),
+            pad(&rating, 5),
+            runtime
+        ));
+    }
+
+    let wrap = |body: &str| format!("<pre>{}</pre>", html_escape(body));
+    let mut parts = Vec::new();
+    let mut cur = header.clone();
+    for line in lines {
+        if wrap(&format!("{}\n{}", cur, line)).chars().count() > limit {
+            parts.push(wrap(&cur));
+            cur = header.clone();
+        }
+        cur.push('\n');
+        cur.push_str(&line);
+    }
+    parts.push(wrap(&cur));
+    parts
+}
+
+// глобальные флаги опроса (задаются при запуске, общие для всех чатов)
+struct PollFlags {
+    anonymous: bool,
+    multiple: bool,
+    admin_only: bool,
+}
+
+fn settings_text(
+    s: &ChatSettings,
+    flags: PollFlags,
+    list_len: usize,
+    backlog_len: usize,
+) -> String {
+    let yes_no = |v: bool| if v { "да" } else { "нет" };
+    [
+        "<b>Настройки чата</b>".to_string(),
+        format!("Язык трейлеров: {} (/lang)", html_escape(&s.language)),
+        format!("Регион: {} (/setregion)", html_escape(&s.region)),
+        format!(
+            "Опрос: анонимный — {}, несколько ответов — {}",
+            yes_no(flags.anonymous),
+            yes_no(flags.multiple)
+        ),
+        "Режим: обычный опрос (/vote) или викторина (/quiz)".to_string(),
+        format!(
+            "Список: {}/{}, бэклог: {}",
+            list_len, MAX_LIST_LEN, backlog_len
+        ),
+        format!(
+            "Взрослый контент в поиске: {} (/adult)",
+            yes_no(s.include_adult)
+        ),
+        format!("Мин. голосов TMDb в поиске: {} (/minvotes)", s.min_votes),
+        format!(
+            "Вопрос опроса: {} (/setquestion)",
+            html_escape(&poll_question(s))
+        ),
+        format!("Постеры: {} (/posters, /noposters)", yes_no(s.posters)),
+        format!(
+            "Список после добавления: {} (/autolist)",
+            yes_no(s.auto_list)
+        ),
+        format!(
+            "Трейлеры с превью: {} (/richtrailers)",
+            yes_no(s.rich_trailers)
+        ),
+        format!("Порядок поиска: {} (/searchsort)", s.search_sort.as_str()),
+        format!("Закреплять опрос: {} (/autopin)", yes_no(s.auto_pin)),
+        format!(
+            "Оригинальные названия: {} (/original)",
+            yes_no(s.show_original_title)
+        ),
+        format!(
+            "Менять список в группе: {}",
+            if flags.admin_only {
+                "только админы"
+            } else {
+                "все"
+            }
+        ),
+    ]
+    .join("\n")
+}
+
+const POLL_QUESTION: &str = "Что смотрим?";
+
+/* ====== Лимиты опроса ======
+   вопрос — до 300 символов, вариант — до 100; текст без разметки,
+   поэтому режем просто по символам, оставляя место под «…» */
+const POLL_QUESTION_LIMIT: usize = 300;
+const POLL_OPTION_LIMIT: usize = 100;
+
+/* одинаковые варианты Telegram не принимает: дубли различаем типом
+   («фильм»/«сериал»), а если и так совпали — номером в списке */
+fn poll_option_labels(list: &[StoredMovie], with_original: bool) -> Vec<String> {
+    let titles: Vec<String> = list.iter().map(|m| list_title(m, with_original)).collect();
+    let label = |title: &str, suffix: &str| {
+        let room = POLL_OPTION_LIMIT.saturating_sub(suffix.chars().count());
+        clip_plain(title, room) + suffix
+    };
+    let dup = |labels: &[String], i: usize| labels.iter().filter(|l| **l == labels[i]).count() > 1;
+
+    let mut suffixes = vec![String::new(); list.len()];
+    let labels: Vec<String> = titles.iter().map(|t| label(t, "")).collect();
+    for (i, m) in list.iter().enumerate() {
+        if dup(&labels, i) {
+            suffixes[i] = format!(" · {}", media_kind_name(m.media_type));
+        }
+    }
+    let labels: Vec<String> = titles
+        .iter()
+        .zip(&suffixes)
+        .map(|(t, s)| label(t, s))
+        .collect();
+    for (i, suffix) in suffixes.iter_mut().enumerate() {
+        if dup(&labels, i) {
+            suffix.push_str(&format!(" #{}", i + 1));
+        }
+    }
+    titles
+        .iter()
+        .zip(&suffixes)
+        .map(|(t, s)| label(t, s))
+        .collect()
+}
+
+fn poll_question(s: &ChatSettings) -> String {
+    let q = s.vote_question.as_deref().unwrap_or(POLL_QUESTION);
+    clip_plain(q, POLL_QUESTION_LIMIT)
+}
+
+fn clip_plain(s: &str, max: usize) -> String {
+    if s.chars().count() <= max {
+        s.to_string()
+    } else {
+        s.chars().take(max.saturating_sub(1)).collect::<String>() + "…"
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum PollKind {
+    Regular,
+    /// correct_option — фильм с максимальным vote_average
+    Quiz,
+}
+
+/* ====== Незаконченный /vote ======
+   если голосование упало на середине (опрос ушёл, альбом — нет), повторный /vote
+   по тому же списку не шлёт опрос второй раз, а продолжает с постеров.
+   Держим в памяти и недолго: после рестарта или через час начинаем заново */
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+enum VoteStage {
+    PollSent,
+    PostersSent,
+}
+
+#[derive(Clone)]
+struct VoteProgress {
+    // список и вид опроса, для которых шёл /vote; поменялись — начинаем с нуля
+    key: (Vec<(u64, tmdb::MediaKind)>, PollKind),
+    stage: VoteStage,
+}
+
+static VOTE_PROGRESS: Lazy<Cache<ChatId, VoteProgress>> = Lazy::new(|| {
+    Cache::builder()
+        .time_to_live(std::time::Duration::from_secs(60 * 60))
+        .max_capacity(10_000)
+        .build()
+});
+
+fn vote_key(list: &[StoredMovie], kind: PollKind) -> (Vec<(u64, tmdb::MediaKind)>, PollKind) {
+    (list.iter().map(|m| (m.id, m.media_type)).collect(), kind)
+}
+
+// докуда дошёл прошлый /vote по тому же списку (None — начинать сначала)
+fn resumed_stage(
+    progress: Option<VoteProgress>,
+    key: &(Vec<(u64, tmdb::MediaKind)>, PollKind),
+) -> Option<VoteStage> {
+    progress.filter(|p| p.key == *key).map(|p| p.stage)
+}
+
+/* пауза между сообщениями /vote (VOTE_PACING_MS, по умолчанию 300 мс):
+   опрос, альбом и пачка описаний подряд упираются во flood-лимит чата,
+   а 429 посреди серии — это полупоказанное голосование */
+static VOTE_PACING: Lazy<std::time::Duration> =
+    Lazy::new(|| crate::config::env_millis("VOTE_PACING_MS", 300, 5000));
+
+async fn run_vote_flow<R>(
+    bot: &R,
+    chat: ChatId,
+    tmdb: &TmdbClient,
+    storage: &Storage,
+    anonymous: bool,
+    multiple_ans: bool,
+    kind: PollKind,
+) -> BotResult
+where
+    R: Requester<Err = RequestError>,
+{
+    let list = vote_candidates(storage.get(chat.0).await);
+    if list.is_empty() && !STARTER_LIST.is_empty() {
+        let kb = InlineKeyboardMarkup::new(vec![vec![InlineKeyboardButton::callback(
+            "📋 Заполнить примерным списком".to_string(),
+            "starter:0".to_string(),
+        )]]);
+        send_retry(
+            bot.send_message(chat, "Список пуст. Заполнить примерным списком?")
+                .reply_markup(kb),
+        )
+        .await?;
+        return Ok(());
+    }
+    if list.len() < 2 {
+        send_retry(bot.send_message(
+            chat,
+            "Нужно минимум 2 фильма в списке. Добавь и повтори /vote.",
+        ))
+        .await?;
+        return Ok(());
+    }
+    let key = vote_key(&list, kind);
+    let done = resumed_stage(VOTE_PROGRESS.get(&chat).await, &key);
+    let mark = |stage| {
+        VOTE_PROGRESS.insert(
+            chat,
+            VoteProgress {
+                key: key.clone(),
+                stage,
+            },
+        )
+    };
+    let settings = storage.settings(chat.0).await;
+    if done.is_none() {
+        let sent = send_vote_poll(
+            bot,
+            chat,
+            tmdb,
+            &list,
+            &settings,
+            anonymous,
+            multiple_ans,
+            kind,
+        )
+        .await?;
+        if !sent {
+            return Ok(());
+        }
+        mark(VoteStage::PollSent).await;
+        tokio::time::sleep(*VOTE_PACING).await;
+    } else {
+        tracing::info!("vote in {} resumed after {:?}", chat, done);
+    }
+
+    // альбом постеров (короткий общий caption); без постеров атрибуция — сообщением
+    let inline_attribution = ATTRIBUTION.inline && settings.posters;
+    if settings.posters && done < Some(VoteStage::PostersSent) {
+        let caption = if inline_attribution {
+            format!("<b>Постеры</b>\n<i>{}</i>", html_escape(&ATTRIBUTION.text))
+        } else {
+            "<b>Постеры</b>".to_string()
+        };
+        send_album_from_stored(bot, chat, tmdb, &list, Some(&caption)).await?;
+    }
+    mark(VoteStage::PostersSent).await;
+
+    // описания + трейлеры (тянем детали по id); альбом — уже пачка сообщений, даём чату остыть
+    let lang = settings.language.as_str();
+    let (blocks, trailers) =
+        vote_details(tmdb, &list, &settings.region, lang, VOTE_ITEM_TIMEOUT).await?;
+    let pause = *VOTE_PACING;
+    tokio::time::sleep(pause).await;
+    send_long_paced(
+        bot,
+        chat,
+        &blocks.join("\n\n"),
+        Some(ParseMode::Html),
+        pause,
+    )
+    .await?;
+    if settings.rich_trailers {
+        for (title, url) in &trailers {
+            tokio::time::sleep(pause).await;
+            send_retry(
+                bot.send_message(chat, format!("▶️ {}", html_link(url, title)))
+                    .parse_mode(ParseMode::Html)
+                    .link_preview_options(trailer_preview(url)),
+            )
+            .await?;
+        }
+    } else if !trailers.is_empty() {
+        let lines: Vec<String> = trailers
+            .iter()
+            .map(|(title, url)| format!("• {}", html_link(url, title)))
+            .collect();
+        let text = format!("<b>Трейлеры</b>\n{}", lines.join("\n"));
+        tokio::time::sleep(pause).await;
+        send_long_paced(bot, chat, &text, Some(ParseMode::Html), pause).await?;
+    }
+    if !inline_attribution {
+        tokio::time::sleep(pause).await;
+        send_retry(bot.send_message(chat, ATTRIBUTION.text.as_str())).await?;
+    }
+    VOTE_PROGRESS.invalidate(&chat).await;
+    Ok(())
+}
+
+// опрос (или викторина); нет прав на опросы — говорим об этом и идём дальше.
+// false — викторина не получилась, дальше /vote не продолжаем
+#[allow(clippy::too_many_arguments)]
+async fn send_vote_poll<R>(
+    bot: &R,
+    chat: ChatId,
+    tmdb: &TmdbClient,
+    list: &[StoredMovie],
+    settings: &ChatSettings,
+    anonymous: bool,
+    multiple_ans: bool,
+    kind: PollKind,
+) -> Result<bool, BotError>
+where
+    R: Requester<Err = RequestError>,
+{
+    let with_original = settings.show_original_title;
+    let options: Vec<teloxide::types::InputPollOption> = poll_option_labels(list, with_original)
+        .into_iter()
+        .map(teloxide::types::InputPollOption::new)
+        .collect();
+    let mut poll = bot
+        .send_poll(chat, poll_question(settings), options)
+        .is_anonymous(anonymous);
+    match kind {
+        PollKind::Regular => poll = poll.allows_multiple_answers(multiple_ans),
+        PollKind::Quiz => {
+            let ratings = list_ratings(tmdb, list).await;
+            let Some(best) = top_rated_index(&ratings) else {
+                send_retry(bot.send_message(
+                    chat,
+                    "Не нашёл рейтингов TMDb для фильмов из списка — викторина не получится.",
+                ))
+                .await?;
+                return Ok(false);
+            };
+            poll = poll
+                .type_(PollType::Quiz)
+                .correct_option_id(best as u8)
+                .explanation(format!(
+                    "Лучший рейтинг TMDb: {} — {:.1}",
+                    one_line_title_stored(&list[best]),
+                    ratings[best].unwrap_or_default()
+                ));
+        }
+    }
+    // без права на опросы всё равно покажем постеры и описания
+    match send_retry(poll).await {
+        Ok(sent) if settings.auto_pin => pin_poll(bot, chat, sent.id).await?,
+        Ok(_) => {}
+        Err(e) => {
+            if !is_poll_forbidden(&e) {
+                return Err(e.into());
+            }
+            tracing::warn!("poll in {} forbidden: {}", chat, e);
+            send_retry(bot.send_message(chat, "У меня нет прав на создание опросов в этом чате"))
+                .await?;
+        }
+    }
+    Ok(true)
+}
+
+// закрепить опрос (auto_pin); без права закреплять — говорим об этом, голосование идёт дальше
+async fn pin_poll<R>(bot: &R, chat: ChatId, poll: teloxide::types::MessageId) -> ResponseResult<()>
+where
+    R: Requester<Err = RequestError>,
+{
+    let Err(e) = send_retry(bot.pin_chat_message(chat, poll).disable_notification(true)).await
+    else {
+        return Ok(());
+    };
+    tracing::warn!("pinning poll in {} failed: {}", chat, e);
+    if matches!(
+        e,
+        RequestError::Api(
+            ApiError::NotEnoughRightsToPinMessage | ApiError::NotEnoughRightsToManagePins
+        )
+    ) {
+        send_retry(bot.send_message(
+            chat,
+            "Не могу закрепить опрос: дай мне право закреплять сообщения или выключи /autopin off",
+        ))
+        .await?;
+    }
+    Ok(())
+}
+
+// жанры фильмов и сериалов из кэша или TMDb (оба списка — параллельно)
+async fn genre_lists(
+    tmdb: &TmdbClient,
+    lang: &str,
+) -> Result<(Vec<String>, Vec<String>), tmdb::TmdbErr> {
+    if let Some(cached) = GENRES.get(lang).await {
+        return Ok(cached);
+    }
+    let (movie, tv) = futures::try_join!(
+        tmdb.genres(tmdb::MediaKind::Movie, lang),
+        tmdb.genres(tmdb::MediaKind::Tv, lang)
+    )?;
+    GENRES
+        .insert(lang.to_string(), (movie.clone(), tv.clone()))
+        .await;
+    Ok((movie, tv))
+}
+
+fn genres_text(movie: &[String], tv: &[String]) -> String {
+    let line = |names: &[String]| {
+        if names.is_empty() {
+            "—".to_string()
+        } else {
+            html_escape(&names.join(", "))
+        }
+    };
+    format!(
+        "<b>Жанры фильмов</b>\n{}\n\n<b>Жанры сериалов</b>\n{}",
+        line(movie),
+        line(tv)
+    )
+}
+
+// «2» или «2 11»: сезон и номер серии, с которой начинать опрос (с 1)
+fn parse_episodes_arg(arg: &str) -> Option<(u32, u32)> {
+    let mut parts = arg.split_whitespace();
+    let season = parts.next()?.parse().ok()?;
+    let start = match parts.next() {
+        Some(n) => n.parse().ok().filter(|n| *n >= 1)?,
+        None => 1,
+    };
+    parts.next().is_none().then_some((season, start))
+}
+
+// сериал для /episodes: открытый последним в карточке, иначе последний сериал из списка
+async fn selected_show(chat: ChatId, storage: &Storage) -> Option<(u64, String)> {
+    if let Some(show) = SELECTED_SHOW.get(&chat).await {
+        return Some(show);
+    }
+    storage
+        .get(chat.0)
+        .await
+        .into_iter()
+        .rev()
+        .find(|m| m.media_type == tmdb::MediaKind::Tv)
+        .map(|m| (m.id, m.display_title().to_string()))
+}
+
+/// Больше вариантов в опросе Telegram не принимает.
+const POLL_MAX_OPTIONS: usize = 10;
+
+// варианты опроса: «3. Название» для серий начиная со `start`, не больше POLL_MAX_OPTIONS
+fn episode_poll_options(episodes: &[tmdb::Episode], start: u32) -> Vec<String> {
+    episodes
+        .iter()
+        .filter(|e| e.episode_number >= start)
+        .take(POLL_MAX_OPTIONS)
+        .map(|e| {
+            let label = if e.name.trim().is_empty() {
+                format!("Серия {}", e.episode_number)
+            } else {
+                format!("{}. {}", e.episode_number, e.name.trim())
+            };
+            clip_plain(&label, POLL_OPTION_LIMIT)
+        })
+        .collect()
+}
+
+/// Сколько ждём детали и трейлер одного фильма в /vote, прежде чем его пропустить.
+const VOTE_ITEM_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(8);
+
+/// Сколько фильмов /vote тянет из TMDb одновременно (не упираться в rate limit).
+const VOTE_FETCH_CONCURRENCY: usize = 4;
+
+type VoteItem = Result<Option<(MultiNorm, Option<String>)>, tmdb::TmdbErr>;
+
+// детали + трейлер одного фильма; Err(Elapsed) — не уложились в per_item
+async fn vote_item<'a>(
+    tmdb: &TmdbClient,
+    i: usize,
+    sm: &'a StoredMovie,
+    region: &str,
+    lang: &str,
+    per_item: std::time::Duration,
+) -> (
+    usize,
+    &'a StoredMovie,
+    Result<VoteItem, tokio::time::error::Elapsed>,
+) {
+    // детали и трейлер — одним запросом, как для карточки
+    let item = async {
+        let full = tmdb
+            .movie_details_full(sm.id, sm.media_type, region, lang)
+            .await?;
+        Ok(full.map(|f| (f.item, f.trailer)))
+    };
+    (i, sm, tokio::time::timeout(per_item, item).await)
+}
+
+// крупное превью ролика под ссылкой (по ссылке Telegram сам достаёт обложку YouTube)
+fn trailer_preview(url: &str) -> teloxide::types::LinkPreviewOptions {
+    teloxide::types::LinkPreviewOptions {
+        is_disabled: false,
+        url: Some(url.to_string()),
+        prefer_small_media: false,
+        prefer_large_media: true,
+        show_above_text: false,
+    }
+}
+
+/* ====== Детали для /vote ======
+   описания и строки трейлеров тянем параллельно (до VOTE_FETCH_CONCURRENCY),
+   собираем в порядке списка; зависший TMDb по одному фильму не держит весь /vote.
+   Трейлеры — пары (название, ссылка) */
+async fn vote_details(
+    tmdb: &TmdbClient,
+    list: &[StoredMovie],
+    region: &str,
+    lang: &str,
+    per_item: std::time::Duration,
+) -> Result<(Vec<String>, Vec<(String, String)>), tmdb::TmdbErr> {
+    let items: Vec<_> = list
+        .iter()
+        .enumerate()
+        .filter(|(_, sm)| !matches!(sm.media_type, tmdb::MediaKind::Person))
+        .map(|(i, sm)| vote_item(tmdb, i, sm, region, lang, per_item))
+        .collect();
+    let mut fetched: Vec<_> = stream::iter(items)
+        .buffer_unordered(VOTE_FETCH_CONCURRENCY)
+        .collect()
+        .await;
+    fetched.sort_by_key(|(i, _, _)| *i);
+
+    let mut blocks = Vec::new();
+    let mut trailers = Vec::new();
+    for (_, sm, res) in fetched {
+        match res {
+            Ok(Ok(Some((m, trailer)))) => {
+                if let Some(t) = trailer {
+                    trailers.push((m.title.clone(), t));
+                }
+                blocks.push(make_block(
+                    &m,
+                    OVERVIEW_LIMITS.detail.min(1200),
+                    Lang::from_code(lang),
+                ));
+            }
+            Ok(Ok(None)) => {}
+            Ok(Err(e)) => return Err(e),
+            Err(_) => {
+                tracing::warn!("details for {} timed out", sm.id);
+                blocks.push(format!(
+                    "<b>{}</b>\n<i>детали недоступны</i>",
+                    html_escape(&one_line_title_stored(sm))
+                ));
+            }
+        }
+    }
+    Ok((blocks, trailers))
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum SortBy {
+    Title,
+    Rating,
+}
+
+// сортировка устойчивая: при равенстве сохраняется порядок добавления
+fn sort_list(list: &mut [StoredMovie], by: SortBy) {
+    match by {
+        SortBy::Title => list.sort_by_key(|m| m.display_title().to_lowercase()),
+        SortBy::Rating => list.sort_by(|a, b| {
+            // без рейтинга — в конец
+            let a = a.vote_average.unwrap_or(f64::NEG_INFINITY);
+            let b = b.vote_average.unwrap_or(f64::NEG_INFINITY);
+            b.total_cmp(&a)
+        }),
+    }
+}
+
+fn top_text(sorted: &[StoredMovie]) -> String {
+    let lines: Vec<String> = sorted
+        .iter()
+        .enumerate()
+        .map(|(i, m)| {
+            let rating = match m.vote_average.filter(|v| *v > 0.0) {
+                Some(v) => format!("⭐ {:.1}", v),
+                None => "нет оценок".to_string(),
+            };
+            format!(
+                "{}. {} — {}",
+                i + 1,
+                html_escape(&one_line_title_stored(m)),
+                rating
+            )
+        })
+        .collect();
+    format!("<b>Лучшие в списке:</b>\n{}", lines.join("\n"))
+}
+
+// то же, что уйдёт в опрос, но без send_poll и альбома
+fn vote_preview_text(list: &[StoredMovie], settings: &ChatSettings) -> String {
+    if list.len() < 2 {
+        return "Нужно минимум 2 фильма в списке — опрос не получится.".to_string();
+    }
+    let options: Vec<String> = poll_option_labels(list, settings.show_original_title)
+        .iter()
+        .enumerate()
+        .map(|(i, label)| format!("{}. {}", i + 1, html_escape(label)))
+        .collect();
+    format!(
+        "<b>Предпросмотр опроса</b>\n{}\n\n{}\n\nВсё верно? Тогда /vote",
+        html_escape(&poll_question(settings)),
+        options.join("\n")
+    )
+}
+
+// просмотренные в опрос не берём, если только весь список не просмотрен
+fn vote_candidates(all: Vec<StoredMovie>) -> Vec<StoredMovie> {
+    if all.iter().all(|m| m.watched) {
+        all
+    } else {
+        all.into_iter().filter(|m| !m.watched).collect()
+    }
+}
+
+// рейтинги для опроса: сохранённые, а для старых записей — из деталей TMDb
+async fn list_ratings(tmdb: &TmdbClient, list: &[StoredMovie]) -> Vec<Option<f64>> {
+    let mut out = Vec::with_capacity(list.len());
+    for sm in list {
+        let rating = match sm.vote_average {
+            Some(v) => Some(v),
+            None => match tmdb.movie_details_ru(sm.id, sm.media_type).await {
+                Ok(Some(m)) => m.vote_average,
+                Ok(None) => None,
+                Err(e) => {
+                    tracing::warn!("rating for {} failed: {}", sm.id, e);
+                    None
+                }
+            },
+        };
+        out.push(rating);
+    }
+    out
+}
+
+// индекс фильма с лучшим рейтингом (0 у TMDb — «нет оценок»)
+fn top_rated_index(ratings: &[Option<f64>]) -> Option<usize> {
+    ratings
+        .iter()
+        .enumerate()
+        .filter_map(|(i, r)| r.filter(|v| *v > 0.0).map(|v| (i, v)))
+        .fold(None, |best: Option<(usize, f64)>, (i, v)| match best {
+            Some((_, b)) if b >= v => best,
+            _ => Some((i, v)),
+        })
+        .map(|(i, _)| i)
+}
+
+/* ====== Кнопки ====== */
+
+fn keyboard_add_results(results: &[MultiNorm], person: Option<&MultiNorm>) -> InlineKeyboardMarkup {
+    // по 1 в строке
+    let mut rows = Vec::new();
+    let mut row = Vec::new();
+    for (m, label) in results.iter().zip(add_button_labels(results)) {
+        let btn = InlineKeyboardButton::callback(
+            format!("➕ {}", label),
+            format!("add:{}:{}", m.id, m.media_type.as_str()),
+        );
+        row.push(btn);
+        rows.push(row);
+        row = Vec::new();
+    }
+    if !row.is_empty() {
+        rows.push(row);
+    }
+    // фильмы — основная выдача, персона — последней строкой
+    if let Some(p) = person {
+        rows.push(vec![InlineKeyboardButton::callback(
+            format!("🎭 Фильмы {}", p.title),
+            format!("person:{}:person", p.id),
+        )]);
+    }
+    InlineKeyboardMarkup::new(rows)
+}
+
+// одинаковые «Название (год)» (ремейки, нет даты) различаем типом и id TMDb
+fn add_button_labels(results: &[MultiNorm]) -> Vec<String> {
+    let labels: Vec<String> = results.iter().map(one_line_title).collect();
+    labels
+        .iter()
+        .zip(results)
+        .map(|(label, m)| {
+            if labels.iter().filter(|l| *l == label).count() > 1 {
+                format!("{} · {} #{}", label, media_kind_name(m.media_type), m.id)
+            } else {
+                label.clone()
+            }
+        })
+        .collect()
+}
+
+fn keyboard_show_card(
+    m: &MultiNorm,
+    imdb_id: Option<&str>,
+    trailer: Option<&str>,
+) -> InlineKeyboardMarkup {
+    let mut links = Vec::new();
+    let tmdb_page = format!(
+        "https://www.themoviedb.org/{}/{}",
+        m.media_type.as_str(),
+        m.id
+    );
+    if let Ok(url) = reqwest::Url::parse(&tmdb_page) {
+        links.push(InlineKeyboardButton::url("TMDb".to_string(), url));
+    }
+    if let Some(url) = imdb_id
+        .and_then(|id| reqwest::Url::parse(&format!("https://www.imdb.com/title/{}/", id)).ok())
+    {
+        links.push(InlineKeyboardButton::url("IMDb".to_string(), url));
+    }
+    if let Some(url) = trailer.and_then(|t| reqwest::Url::parse(t).ok()) {
+        links.push(InlineKeyboardButton::url("▶️ Трейлер".to_string(), url));
+    }
+    InlineKeyboardMarkup::new(vec![
+        vec![
+            InlineKeyboardButton::callback(
+                "🖼 Постеры".to_string(),
+                format!("gallery:{}:{}", m.id, m.media_type.as_str()),
+            ),
+            InlineKeyboardButton::callback(
+                "🏷 Теги".to_string(),
+                format!("tags:{}:{}", m.id, m.media_type.as_str()),
+            ),
+        ],
+        links,
+    ])
+}
+
+/* ====== Вспомогательные ====== */
+
+const MAX_TAGS: usize = 10;
+
+fn tags_text(m: &MultiNorm, tags: &[String]) -> String {
+    let shown: Vec<String> = tags.iter().take(MAX_TAGS).map(|t| html_escape(t)).collect();
+    format!(
+        "🏷 <b>{}</b>: {}",
+        html_escape(&one_line_title(m)),
+        shown.join(", ")
+    )
+}
+
+fn one_line_title(m: &MultiNorm) -> String {
+    if let Some(y) = format::year_of(&m.release_date) {
+        format!("{} ({})", m.title, y)
+    } else {
+        m.title.clone()
+    }
+}
+
+// рейтинг и «где смотреть» для региона чата
+fn region_block(region: &str, cert: Option<&str>, providers: Option<&WatchProviders>) -> String {
+    let mut lines = Vec::new();
+    if let Some(c) = cert {
+        lines.push(format!("🔞 Рейтинг ({}): {}", region, html_escape(c)));
+    }
+    match providers {
+        Some(p) => {
+            for (label, names) in [
+                ("подписка", &p.flatrate),
+                ("аренда", &p.rent),
+                ("покупка", &p.buy),
+            ] {
+                if !names.is_empty() {
+                    lines.push(format!(
+                        "📺 {} ({}): {}",
+                        label,
+                        region,
+                        html_escape(&names.join(", "))
+                    ));
+                }
+            }
+            if let Some(link) = &p.link {
+                lines.push(html_link(link, "Все варианты просмотра"));
+            }
+        }
+        None => lines.push(format!("📺 Нет данных, где смотреть ({})", region)),
+    }
+    lines.join("\n")
+}
+
+// ответ /where: заголовок и только провайдеры, без рейтинга и описания
+fn where_text(m: &MultiNorm, region: &str, providers: Option<&WatchProviders>) -> String {
+    let head = format!("<b>{}</b>", html_escape(&one_line_title(m)));
+    match providers {
+        Some(p) => format!("{}\n{}", head, region_block(region, None, Some(p))),
+        None => format!("{}\n📺 нет данных о доступности ({})", head, region),
+    }
+}
+
+fn make_block(m: &MultiNorm, overview_limit: usize, lang: Lang) -> String {
+    let year = format::year_of(&m.release_date).unwrap_or("");
+    let title = html_escape(&m.title);
+    let body = if m.overview.trim().is_empty() {
+        "<i>нет описания</i>".to_string()
+    } else {
+        clip(&html_escape(&m.overview), overview_limit)
+    };
+
+    let mut head = if year.is_empty() {
+        format!("<b>{}</b>", title)
+    } else {
+        format!("<b>{}</b> ({})", title, year)
+    };
+    if let Some(t) = &m.tagline {
+        head.push_str(&format!("\n<i>{}</i>", html_escape(t)));
+    }
+    // хронометраж и сезоны есть только у деталей
+    let mut meta = Vec::new();
+    if let Some(badge) = coming_soon(m.release_date.as_deref(), Local::now().date_naive()) {
+        meta.push(badge);
+    }
+    if let Some(min) = m.runtime.filter(|v| *v > 0) {
+        meta.push(format!("⏱ {}", format::runtime(min, lang)));
+    }
+    if let Some(n) = m.seasons.filter(|v| *v > 0) {
+        meta.push(format::seasons(n, lang));
+    }
+    if meta.is_empty() {
+        format!("{}\n\n{}", head, body)
+    } else {
+        format!("{}\n{}\n\n{}", head, meta.join(" · "), body)
+    }
+}
+
+/* ====== Бейдж «скоро» ======
+   дата релиза позже сегодняшней — «🔜 скоро (12.2025)»;
+   кривые и неполные даты TMDb («2025», «») молча пропускаем */
+fn coming_soon(release_date: Option<&str>, today: NaiveDate) -> Option<String> {
+    let date = NaiveDate::parse_from_str(release_date?.trim(), "%Y-%m-%d").ok()?;
+    (date > today).then(|| format!("🔜 скоро ({})", date.format("%m.%Y")))
+}
+
+/* лимит Telegram считает видимый текст, а не разметку: теги не в счёт,
+   сущность (&amp;) — один символ. Иначе блоки с <b>/<a href> занимают
+   в бюджете вдвое больше, чем на экране */
+fn join_blocks(blocks: Vec<String>, limit_hint: usize) -> String {
+    // аккуратно собираем, не превышая limit_hint видимых символов
+    let mut out = String::new();
+    let mut len = 0;
+    for b in blocks {
+        let piece = if out.is_empty() {
+            b
+        } else {
+            format!("\n\n{}", b)
+        };
+        let piece_len = visible_len(&piece);
+        out.push_str(&piece);
+        len += piece_len;
+        if len > limit_hint {
+            // не влезло — всё равно добавили, верхний слой потом порежет split_html
+            break;
+        }
+    }
+    out
+}
+
+// длина HTML-текста так, как его покажет Telegram
+fn visible_len(html: &str) -> usize {
+    html_pieces(html, true)
+        .iter()
+        .filter(|p| matches!(p, Piece::Text(_)))
+        .count()
+}
+
+fn html_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+// для значений атрибутов (href="…"): ещё и кавычки, иначе заголовок с " ломает тег
+fn html_attr_escape(s: &str) -> String {
+    html_escape(s).replace('"', "&quot;").replace('\'', "&#39;")
+}
+
+fn html_link(url: &str, text: &str) -> String {
+    format!(
+        "<a href=\"{}\">{}</a>",
+        html_attr_escape(url),
+        html_escape(text)
+    )
+}
+
+fn clip(s: &str, max: usize) -> String {
+    if s.chars().count() <= max {
+        s.to_string()
+    } else {
+        s.chars().take(max).collect::<String>() + "…"
+    }
+}
+
+/* ====== Длинные сообщения ======
+   Telegram принимает до 4096 символов; режем по строкам, а внутри слишком длинной
+   строки — между тегами/сущностями, закрывая открытые теги и открывая их заново */
+const MESSAGE_LIMIT: usize = 4000;
+
+async fn send_long<R>(
+    bot: &R,
+    chat: ChatId,
+    text: &str,
+    parse_mode: Option<ParseMode>,
+) -> ResponseResult<()>
+where
+    R: Requester<Err = RequestError>,
+{
+    send_long_paced(bot, chat, text, parse_mode, std::time::Duration::ZERO).await
+}
+
+// то же, но с паузой `pause` перед каждой следующей частью (серия сообщений /vote)
+async fn send_long_paced<R>(
+    bot: &R,
+    chat: ChatId,
+    text: &str,
+    parse_mode: Option<ParseMode>,
+    pause: std::time::Duration,
+) -> ResponseResult<()>
+where
+    R: Requester<Err = RequestError>,
+{
+    let html = parse_mode == Some(ParseMode::Html);
+    for (i, part) in split_html(text, MESSAGE_LIMIT, html)
+        .into_iter()
+        .enumerate()
+    {
+        if i > 0 {
+            tokio::time::sleep(pause).await;
+        }
+        let mut req = bot.send_message(chat, part);
+        if let Some(mode) = parse_mode {
+            req = req.parse_mode(mode);
+        }
+        send_retry(req).await?;
+    }
+    Ok(())
+}
+
+enum Piece<'a> {
+    Open { name: &'a str, raw: &'a str },
+    Close(&'a str),
+    Text(&'a str), // один символ или HTML-сущность целиком
+}
+
+fn html_pieces(s: &str, html: bool) -> Vec<Piece<'_>> {
+    let mut out = Vec::new();
+    let mut i = 0;
+    while i < s.len() {
+        let rest = &s[i..];
+        let end = if html && rest.starts_with('<') {
+            rest.find('>').map(|e| e + 1)
+        } else if html && rest.starts_with('&') {
+            rest.find(';').filter(|e| *e <= 10).map(|e| e + 1)
+        } else {
+            None
+        };
+        let len = end.unwrap_or_else(|| rest.chars().next().map_or(1, char::len_utf8));
+        let raw = &rest[..len];
+        out.push(if end.is_some() && raw.starts_with("</") {
+            Piece::Close(raw[2..raw.len() - 1].trim())
+        } else if end.is_some() && raw.starts_with('<') {
+            let inner = &raw[1..raw.len() - 1];
+            let name = inner.split_whitespace().next().unwrap_or("");
+            Piece::Open { name, raw }
+        } else {
+            Piece::Text(raw)
+        });
+        i += len;
+    }
+    out
+}
+
+fn split_html(text: &str, max: usize, html: bool) -> Vec<String> {
+    if text.chars().count() <= max {
+        return vec![text.to_string()];
+    }
+    let closing = |stack: &[(&str, &str)]| -> String {
+        stack
+            .iter()
+            .rev()
+            .map(|(n, _)| format!("</{}>", n))
+            .collect()
+    };
+    let reopen = |stack: &[(&str, &str)]| -> String { stack.iter().map(|(_, raw)| *raw).collect() };
+
+    let mut out = Vec::new();
+    let mut stack: Vec<(&str, &str)> = Vec::new();
+    let mut cur = String::new();
+    // последний перевод строки в cur: байтовая позиция и открытые на тот момент теги
+    let mut last_nl: Option<(usize, Vec<(&str, &str)>)> = None;
+    for piece in html_pieces(text, html) {
+        let raw = match &piece {
+            Piece::Open { raw, .. } => *raw,
+            Piece::Close(_) | Piece::Text(_) => "",
+        };
+        let add = match &piece {
+            Piece::Open { raw, .. } => raw.to_string(),
+            Piece::Close(name) => format!("</{}>", name),
+            Piece::Text(t) => t.to_string(),
+        };
+        let budget = cur.chars().count() + add.chars().count() + closing(&stack).chars().count();
+        if budget > max && !cur.is_empty() {
+            match last_nl.take() {
+                Some((pos, at)) => {
+                    let tail = cur[pos + 1..].to_string();
+                    cur.truncate(pos);
+                    cur.push_str(&closing(&at));
+                    out.push(std::mem::take(&mut cur));
+                    cur = reopen(&at) + &tail;
+                }
+                None => {
+                    cur.push_str(&closing(&stack));
+                    out.push(std::mem::take(&mut cur));
+                    cur = reopen(&stack);
+                }
+            }
+        }
+        match piece {
+            Piece::Open { name, .. } => stack.push((name, raw)),
+            Piece::Close(name) => {
+                if let Some(pos) = stack.iter().rposition(|(n, _)| *n == name) {
+                    stack.remove(pos);
+                }
+            }
+            Piece::Text("\n") => last_nl = Some((cur.len(), stack.clone())),
+            Piece::Text(_) => {}
+        }
+        cur.push_str(&add);
+    }
+    if !cur.trim().is_empty() {
+        out.push(cur);
+    }
+    out
+}
+
+/* ====== Ретраи отправки в Telegram ======
+   429 — ждём retry_after, сеть/5xx — короткий бэкофф; до 3 повторов */
+async fn send_retry<Q>(req: Q) -> ResponseResult<Output<Q>>
+where
+    Q: Request<Err = RequestError>,
+{
+    let mut delays = [500u64, 1000, 2000].into_iter();
+    loop {
+        let err = match req.send_ref().await {
+            Ok(v) => return Ok(v),
+            Err(e) => e,
+        };
+        let Some(backoff) = delays.next() else {
+            return Err(err);
+        };
+        let wait = match &err {
+            RequestError::RetryAfter(s) => s.duration(),
+            e if is_transient(e) => std::time::Duration::from_millis(backoff),
+            _ => return Err(err),
+        };
+        tracing::warn!("telegram: {}, повтор через {:?}", err, wait);
+        tokio::time::sleep(wait).await;
+    }
+}
+
+// в группе запрещены опросы (права участников или бота)
+fn is_poll_forbidden(e: &RequestError) -> bool {
+    match e {
+        RequestError::Api(ApiError::NotEnoughRightsToPostMessages) => true,
+        RequestError::Api(ApiError::Unknown(d)) => {
+            let d = d.to_lowercase();
+            d.contains("not enough rights to send polls") || d.contains("chat_send_polls_forbidden")
+        }
+        _ => false,
+    }
+}
+
+fn is_transient(e: &RequestError) -> bool {
+    match e {
+        RequestError::Network(_) | RequestError::InvalidJson { .. } => true,
+        // 5xx Telegram отдаёт как ошибку API с описанием статуса
+        RequestError::Api(ApiError::Unknown(d)) => [
+            "Internal Server Error",
+            "Bad Gateway",
+            "Service Unavailable",
+            "Gateway Timeout",
+        ]
+        .iter()
+        .any(|s| d.contains(s)),
+        _ => false,
+    }
+}
+
+async fn answer_cb<R>(bot: &R, q: &CallbackQuery, text: &str) -> ResponseResult<()>
+where
+    R: Requester<Err = RequestError>,
+{
+    send_retry(
+        bot.answer_callback_query(q.id.clone())
+            .text(text)
+            .show_alert(false),
+    )
+    .await?;
+    Ok(())
+}
+
+// выгруженный список — JSON-массив фильмов в том же виде, что и в файле данных;
+// допускаем обёртку в ```…``` (так удобнее копировать в Telegram)
+fn parse_exported_list(text: &str) -> Option<Vec<StoredMovie>> {
+    let text = text.trim();
+    let text = text
+        .strip_prefix("```json")
+        .or_else(|| text.strip_prefix("```"))
+        .and_then(|t| t.strip_suffix("```"))
+        .unwrap_or(text);
+    let movies: Vec<StoredMovie> = serde_json::from_str(text.trim()).ok()?;
+    (!movies.is_empty()).then_some(movies)
+}
+
+fn message_text_any(msg: &Message) -> Option<String> {
+    if let Some(t) = msg.text() {
+        return Some(t.to_string());
+    }
+    if let Some(c) = msg.caption() {
+        return Some(c.to_string());
+    }
+    None
+}
+
+
+fn one_line_title_stored(m: &StoredMovie) -> String {
+    if let Some(y) = format::year_of(&m.release_date) {
+        format!("{} ({})", m.display_title(), y)
+    } else {
+        m.display_title().to_string()
+    }
+}
+
+// «Паразиты (Parasite, 2019)» — если включено show_original_title и названия различаются
+fn list_title(m: &StoredMovie, with_original: bool) -> String {
+    let original = m.original_title.trim();
+    if !with_original || original.is_empty() || original == m.display_title() {
+        return one_line_title_stored(m);
+    }
+    match format::year_of(&m.release_date) {
+        Some(y) => format!("{} ({}, {})", m.display_title(), original, y),
+        None => format!("{} ({})", m.display_title(), original),
+    }
+}
+
+fn keyboard_list_two_columns_stored(list: &[StoredMovie]) -> InlineKeyboardMarkup {
+    let mut rows = Vec::new();
+    for m in list {
+        let show = InlineKeyboardButton::callback(
+            format!("🎬 {}", one_line_title_stored(m)),
+            format!("show:{}:{}", m.id, m.media_type.as_str()),
+        );
+        let watched = InlineKeyboardButton::callback(
+            if m.watched { "✅" } else { "⬜" }.to_string(),
+            format!("watched:{}:{}", m.id, m.media_type.as_str()),
+        );
+        let del = InlineKeyboardButton::callback(
+            "🗑".to_string(),
+            format!("del:{}:{}", m.id, m.media_type.as_str()),
+        );
+        rows.push(vec![show, watched, del]);
+    }
+    InlineKeyboardMarkup::new(rows)
+}
+
+/// Сколько постеров альбома качаем одновременно (POSTER_CONCURRENCY, по умолчанию 4).
+static POSTER_CONCURRENCY: Lazy<usize> =
+    Lazy::new(|| crate::config::env_count("POSTER_CONCURRENCY", 4, 10));
+
+/// Больше постеров в альбом не кладём (ALBUM_MAX, 1..=10; больше 10 Telegram не примет).
+static ALBUM_MAX: Lazy<usize> =
+    Lazy::new(|| parse_album_max(std::env::var("ALBUM_MAX").ok().as_deref()));
+
+fn parse_album_max(raw: Option<&str>) -> usize {
+    const TELEGRAM_MAX: usize = 10;
+    let Some(raw) = raw else {
+        return TELEGRAM_MAX;
+    };
+    match raw.trim().parse::<usize>() {
+        Ok(n) if n >= 1 => n.min(TELEGRAM_MAX),
+        _ => {
+            tracing::warn!("ALBUM_MAX={} ignored: expected 1..=10", raw);
+            TELEGRAM_MAX
+        }
+    }
+}
+
+// общая подпись альбома + пометка, сколько фильмов остались без постера
+fn album_caption(common_caption_html: Option<&str>, skipped: usize) -> Option<String> {
+    if skipped == 0 {
+        return common_caption_html.map(str::to_string);
+    }
+    let note = format!(
+        "({} {} без постера)",
+        skipped,
+        format::plural_ru(skipped as u64, "фильм", "фильма", "фильмов")
+    );
+    Some(match common_caption_html {
+        Some(c) => format!("{}\n{}", c, note),
+        None => note,
+    })
+}
+
+// постер i-го фильма; не скачался — None и строчка в лог
+async fn album_poster(i: usize, m: &StoredMovie, url: String) -> Option<(usize, Vec<u8>)> {
+    match fetch_image(&url).await {
+        Ok(bytes) => Some((i, bytes)),
+        Err(e) => {
+            tracing::warn!("poster for {} ({}) failed: {}", m.title, url, e);
+            None
+        }
+    }
+}
+
+// отправка альбома из StoredMovie (постеры — по байтам)
+async fn send_album_from_stored<R>(
+    bot: &R,
+    chat_id: ChatId,
+    tmdb: &TmdbClient,
+    movies: &[StoredMovie],
+    common_caption_html: Option<&str>,
+) -> Result<(), teloxide::RequestError>
+where
+    R: Requester<Err = RequestError>,
+{
+    // качаем параллельно, собираем в порядке списка
+    let downloads: Vec<_> = movies
+        .iter()
+        .take(*ALBUM_MAX)
+        .enumerate()
+        .filter_map(|(i, m)| {
+            let p = m.poster_path.as_ref()?;
+            Some(album_poster(i, m, tmdb.image_url(p)))
+        })
+        .collect();
+    let mut posters: Vec<(usize, Vec<u8>)> = stream::iter(downloads)
+        .buffer_unordered(*POSTER_CONCURRENCY)
+        .filter_map(|p| async move { p })
+        .collect()
+        .await;
+    posters.sort_by_key(|(i, _)| *i);
+    let skipped = movies.len().min(*ALBUM_MAX) - posters.len();
+    let caption = album_caption(common_caption_html, skipped);
+
+    let mut media: Vec<InputMedia> = Vec::new();
+    for (i, bytes) in posters {
+        let file = InputFile::memory(bytes).file_name(format!("poster_{i}.jpg"));
+        // подпись — у того постера, который реально оказался первым
+        let mut photo = InputMediaPhoto::new(file).show_caption_above_media(true);
+        if media.is_empty() {
+            if let Some(c) = caption.as_deref() {
+                photo.caption = Some(clip(c, 1024));
+                photo.parse_mode = Some(ParseMode::Html);
+            }
+        }
+        media.push(InputMedia::Photo(photo));
+    }
+    if !media.is_empty() {
+        send_photos(bot, chat_id, media).await?;
+        return Ok(());
+    }
+
+    // ни один постер не скачался — хотя бы текстом
+    let lines: Vec<String> = movies
+        .iter()
+        .map(|m| format!("• {}", html_escape(&one_line_title_stored(m))))
+        .collect();
+    let text = match common_caption_html {
+        Some(c) => format!("{}\n{}", c, lines.join("\n")),
+        None => lines.join("\n"),
+    };
+    send_retry(bot.send_message(chat_id, text).parse_mode(ParseMode::Html)).await?;
+    Ok(())
+}
+
+// галерея альтернативных постеров (media group — максимум 10)
+async fn send_gallery<R>(
+    bot: &R,
+    chat_id: ChatId,
+    tmdb: &TmdbClient,
+    paths: &[String],
+) -> ResponseResult<()>
+where
+    R: Requester<Err = RequestError>,
+{
+    let mut media: Vec<InputMedia> = Vec::new();
+    for (i, p) in paths.iter().enumerate() {
+        if media.len() >= 10 {
+            break;
+        }
+        let url = tmdb.image_url(p);
+        match fetch_image(&url).await {
+            Ok(bytes) => {
+                let file = InputFile::memory(bytes).file_name(format!("gallery_{i}.jpg"));
+                media.push(InputMedia::Photo(InputMediaPhoto::new(file)));
+            }
+            Err(e) => tracing::warn!("gallery poster {} failed: {}", url, e),
+        }
+    }
+    if media.is_empty() {
+        send_retry(bot.send_message(chat_id, "Не удалось загрузить постеры.")).await?;
+        return Ok(());
+    }
+    send_photos(bot, chat_id, media).await
+}
+
+// media group требует минимум 2 элемента — одиночное фото шлём через send_photo
+async fn send_photos<R>(bot: &R, chat_id: ChatId, mut media: Vec<InputMedia>) -> ResponseResult<()>
+where
+    R: Requester<Err = RequestError>,
+{
+    if media.len() == 1 {
+        if let Some(InputMedia::Photo(p)) = media.pop() {
+            send_single_photo(bot, chat_id, p).await?;
+        }
+        return Ok(());
+    }
+    /* один битый постер валит весь альбом — тогда шлём по одному,
+       пропуская те, что Telegram не принял */
+    let err = match send_retry(bot.send_media_group(chat_id, media.clone())).await {
+        Ok(_) => return Ok(()),
+        Err(e) => e,
+    };
+    tracing::warn!("media group failed, sending photos one by one: {}", err);
+    let mut sent = 0;
+    for item in media {
+        let InputMedia::Photo(p) = item else {
+            continue;
+        };
+        match send_single_photo(bot, chat_id, p).await {
+            Ok(()) => sent += 1,
+            Err(e) => tracing::warn!("single poster failed: {}", e),
+        }
+    }
+    if sent == 0 {
+        return Err(err);
+    }
+    Ok(())
+}
+
+async fn send_single_photo<R>(bot: &R, chat_id: ChatId, p: InputMediaPhoto) -> ResponseResult<()>
+where
+    R: Requester<Err = RequestError>,
+{
+    let mut req = bot.send_photo(chat_id, p.media);
+    if let Some(c) = p.caption {
+        req = req.caption(c).show_caption_above_media(true);
+    }
+    if let Some(pm) = p.parse_mode {
+        req = req.parse_mode(pm);
+    }
+    send_retry(req).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{remove_temp_store, temp_store_path};
+    use crate::tmdb::MediaKind;
+    use wiremock::matchers::{method, path, path_regex};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    #[test]
+    fn test_one_line_title() {
+        let m = MultiNorm {
+            id: 1,
+            media_type: MediaKind::Movie,
+            title: "Inception".to_string(),
+            original_title: "Inception".to_string(),
+            overview: "".to_string(),
+            release_date: Some("2010-07-16".to_string()),
+            image_path: None,
+            vote_average: None,
+            vote_count: None,
+            runtime: None,
+            seasons: None,
+            tagline: None,
+            original_language: None,
+        };
+        assert_eq!(one_line_title(&m), "Inception (2010)");
+    }
+
+    #[test]
+    fn test_add_button_labels_disambiguate_same_titles() {
+        let item = |id: u64, kind: MediaKind, date: Option<&str>| MultiNorm {
+            id,
+            media_type: kind,
+            title: "The Lion King".to_string(),
+            original_title: "The Lion King".to_string(),
+            overview: "".to_string(),
+            release_date: date.map(str::to_string),
+            image_path: None,
+            vote_average: None,
+            vote_count: None,
+            runtime: None,
+            seasons: None,
+            tagline: None,
+            original_language: None,
+        };
+        let labels = add_button_labels(&[
+            item(8587, MediaKind::Movie, Some("1994-06-23")),
+            item(420818, MediaKind::Movie, Some("2019-07-12")),
+            item(1, MediaKind::Movie, None),
+            item(2, MediaKind::Tv, None),
+        ]);
+        assert_eq!(labels[0], "The Lion King (1994)");
+        assert_eq!(labels[1], "The Lion King (2019)");
+        assert_eq!(labels[2], "The Lion King · фильм #1");
+        assert_eq!(labels[3], "The Lion King · сериал #2");
+    }
+
+    #[test]
+    fn test_make_block() {
+        let m = MultiNorm {
+            id: 1,
+            media_type: MediaKind::Movie,
+            title: "Inception".to_string(),
+            original_title: "Inception".to_string(),
+            overview: "A thief who steals corporate secrets...".to_string(),
+            release_date: Some("2010-07-16".to_string()),
+            image_path: None,
+            vote_average: None,
+            vote_count: None,
+            runtime: None,
+            seasons: None,
+            tagline: None,
+            original_language: None,
+        };
+        let block = make_block(&m, 10, Lang::Ru);
+        assert!(block.contains("<b>Inception</b> (2010)"));
+        assert!(block.contains("A thief wh…"));
+
+        let tv = MultiNorm {
+            media_type: MediaKind::Tv,
+            runtime: Some(128),
+            seasons: Some(3),
+            tagline: None,
+            ..m.clone()
+        };
+        let block = make_block(&tv, 10, Lang::Ru);
+        assert!(block.starts_with("<b>Inception</b> (2010)\n⏱ 2 ч 8 мин · 3 сезона\n\n"));
+        assert!(make_block(&tv, 10, Lang::En).contains("⏱ 2 h 8 min · 3 seasons"));
+
+        let with_tagline = MultiNorm {
+            tagline: Some("Your mind is the scene of the crime".to_string()),
+            ..m
+        };
+        assert!(make_block(&with_tagline, 10, Lang::Ru)
+            .starts_with("<b>Inception</b> (2010)\n<i>Your mind is the scene of the crime</i>\n"));
+    }
+
+    #[test]
+    fn test_vote_candidates_skip_watched() {
+        let movie = |id: u64, watched: bool| StoredMovie {
+            id,
+            title: format!("Movie {}", id),
+            original_title: format!("Movie {}", id),
+            media_type: MediaKind::Movie,
+            poster_path: None,
+            release_date: None,
+            watched,
+            vote_average: None,
+            custom_title: None,
+        };
+        let picked = vote_candidates(vec![movie(1, false), movie(2, true), movie(3, false)]);
+        assert_eq!(picked.iter().map(|m| m.id).collect::<Vec<_>>(), vec![1, 3]);
+
+        let all_watched = vote_candidates(vec![movie(1, true), movie(2, true)]);
+        assert_eq!(all_watched.len(), 2);
+    }
+
+    #[test]
+    fn test_inline_article_card() {
+        let m = MultiNorm {
+            id: 27205,
+            media_type: MediaKind::Movie,
+            title: "Начало".to_string(),
+            original_title: "Inception".to_string(),
+            overview: "Сны во сне.".to_string(),
+            release_date: Some("2010-07-15".to_string()),
+            image_path: Some("/poster.jpg".to_string()),
+            vote_average: None,
+            vote_count: None,
+            runtime: None,
+            seasons: None,
+            tagline: None,
+            original_language: None,
+        };
+        let tmdb = TmdbClient::new_test("token".to_string(), "http://localhost".to_string());
+        let article = inline_article(&m, &tmdb);
+        assert_eq!(article.id, "movie:27205");
+        assert_eq!(article.title, "Начало (2010)");
+        assert_eq!(article.description.as_deref(), Some("Сны во сне."));
+        assert!(article.thumbnail_url.is_some());
+        match article.input_message_content {
+            InputMessageContent::Text(t) => assert!(t.message_text.contains("<b>Начало</b>")),
+            _ => panic!("expected text content"),
+        }
+    }
+
+    #[test]
+    fn test_parse_start_payload() {
+        assert_eq!(
+            parse_start_payload("add_movie_550"),
+            Some((550, MediaKind::Movie))
+        );
+        assert_eq!(
+            parse_start_payload("add_tv_1399"),
+            Some((1399, MediaKind::Tv))
+        );
+        assert_eq!(parse_start_payload(""), None);
+        assert_eq!(parse_start_payload("add_person_1"), None);
+        assert_eq!(parse_start_payload("add_movie_abc"), None);
+    }
+
+    #[test]
+    fn test_region_block() {
+        let providers = WatchProviders {
+            link: None,
+            flatrate: vec!["Netflix".to_string(), "Max".to_string()],
+            rent: Vec::new(),
+            buy: vec!["Apple TV".to_string()],
+        };
+        let block = region_block("US", Some("PG-13"), Some(&providers));
+        assert!(block.contains("Рейтинг (US): PG-13"));
+        assert!(block.contains("подписка (US): Netflix, Max"));
+        assert!(block.contains("покупка (US): Apple TV"));
+        assert!(!block.contains("аренда"));
+
+        let empty = region_block("RU", None, None);
+        assert_eq!(empty, "📺 Нет данных, где смотреть (RU)");
+    }
+
+    #[test]
+    fn test_where_text() {
+        let m = MultiNorm {
+            id: 872585,
+            media_type: MediaKind::Movie,
+            title: "Оппенгеймер".to_string(),
+            original_title: "Oppenheimer".to_string(),
+            overview: "Описание не нужно".to_string(),
+            release_date: Some("2023-07-19".to_string()),
+            image_path: None,
+            vote_average: None,
+            vote_count: None,
+            runtime: None,
+            seasons: None,
+            tagline: None,
+            original_language: None,
+        };
+        let providers = WatchProviders {
+            link: None,
+            flatrate: vec!["Peacock".to_string()],
+            rent: Vec::new(),
+            buy: Vec::new(),
+        };
+        let text = where_text(&m, "US", Some(&providers));
+        assert!(text.starts_with("<b>Оппенгеймер (2023)</b>\n"));
+        assert!(text.contains("подписка (US): Peacock"));
+        assert!(!text.contains("Описание"));
+
+        let none = where_text(&m, "RU", None);
+        assert!(none.ends_with("нет данных о доступности (RU)"));
+    }
+
+    #[tokio::test]
+    async fn test_send_retry_honors_retry_after() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path_regex(".*Message"))
+            .respond_with(ResponseTemplate::new(429).set_body_json(serde_json::json!({
+                "ok": false,
+                "error_code": 429,
+                "description": "Too Many Requests: retry after 1",
+                "parameters": { "retry_after": 1 }
+            })))
+            .up_to_n_times(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path_regex(".*Message"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "ok": true,
+                "result": {
+                    "message_id": 5,
+                    "date": 1,
+                    "chat": {"id": 42, "type": "private", "first_name": "test"},
+                    "text": "hi"
+                }
+            })))
+            .mount(&server)
+            .await;
+
+        let bot = Bot::new("token").set_api_url(server.uri().parse().unwrap());
+        let started = std::time::Instant::now();
+        let sent = send_retry(bot.send_message(ChatId(42), "hi"))
+            .await
+            .unwrap();
+        assert_eq!(sent.id.0, 5);
+        assert!(started.elapsed() >= std::time::Duration::from_secs(1));
+        assert_eq!(server.received_requests().await.unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_send_retry_does_not_retry_client_errors() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path_regex(".*Message"))
+            .respond_with(ResponseTemplate::new(400).set_body_json(serde_json::json!({
+                "ok": false,
+                "error_code": 400,
+                "description": "Bad Request: chat not found"
+            })))
+            .mount(&server)
+            .await;
+
+        let bot = Bot::new("token").set_api_url(server.uri().parse().unwrap());
+        assert!(send_retry(bot.send_message(ChatId(42), "hi"))
+            .await
+            .is_err());
+        assert_eq!(server.received_requests().await.unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_top_rated_index() {
+        assert_eq!(top_rated_index(&[Some(7.1), Some(8.4), Some(6.0)]), Some(1));
+        assert_eq!(top_rated_index(&[None, Some(5.0), Some(0.0)]), Some(1));
+        // при равенстве — первый
+        assert_eq!(top_rated_index(&[Some(8.0), Some(8.0)]), Some(0));
+        assert_eq!(top_rated_index(&[None, Some(0.0)]), None);
+        assert_eq!(top_rated_index(&[]), None);
+    }
+
+    #[test]
+    fn test_is_clear_top_hit() {
+        let hit = |votes: Option<u64>| MultiNorm {
+            id: 1,
+            media_type: MediaKind::Movie,
+            title: "Movie".to_string(),
+            original_title: "Movie".to_string(),
+            overview: "".to_string(),
+            release_date: None,
+            image_path: None,
+            vote_average: None,
+            vote_count: votes,
+            runtime: None,
+            seasons: None,
+            tagline: None,
+            original_language: None,
+        };
+        assert!(is_clear_top_hit(&[hit(Some(35_000)), hit(Some(120))]));
+        assert!(is_clear_top_hit(&[hit(Some(500))]));
+        assert!(!is_clear_top_hit(&[hit(Some(3))]));
+        assert!(!is_clear_top_hit(&[hit(None)]));
+        // два примерно равных кандидата — пусть выбирает пользователь
+        assert!(!is_clear_top_hit(&[hit(Some(1_000)), hit(Some(900))]));
+        assert!(!is_clear_top_hit(&[]));
+    }
+
+    #[test]
+    fn test_push_history() {
+        let mut history = Vec::new();
+        for q in ["Дюна", "Солярис", " дюна ", "Сталкер"] {
+            history = push_history(history, q);
+        }
+        assert_eq!(history, vec!["Сталкер", "дюна", "Солярис"]);
+        for i in 0..20 {
+            history = push_history(history, &format!("Фильм {}", i));
+        }
+        assert_eq!(history.len(), HISTORY_MAX);
+        assert_eq!(history[0], "Фильм 19");
+    }
+
+    #[test]
+    fn test_parse_starter_list() {
+        assert_eq!(
+            parse_starter_list(" 550, 13 ,tv/1399,movie/27205, 550, x, show/1,"),
+            vec![
+                (550, MediaKind::Movie),
+                (13, MediaKind::Movie),
+                (1399, MediaKind::Tv),
+                (27205, MediaKind::Movie),
+            ]
+        );
+        assert!(parse_starter_list("").is_empty());
+        let many: Vec<String> = (1..=15).map(|i| i.to_string()).collect();
+        assert_eq!(parse_starter_list(&many.join(",")).len(), MAX_LIST_LEN);
+    }
+
+    #[test]
+    fn test_membership_change() {
+        use teloxide::types::{Banned, Member, UntilDate};
+        let member = ChatMemberKind::Member(Member { until_date: None });
+        let kicked = ChatMemberKind::Banned(Banned {
+            until_date: UntilDate::Forever,
+        });
+        assert_eq!(
+            membership_change(&ChatMemberKind::Left, &member),
+            Some(Membership::Joined)
+        );
+        assert_eq!(membership_change(&member, &kicked), Some(Membership::Gone));
+        assert_eq!(
+            membership_change(&member, &ChatMemberKind::Left),
+            Some(Membership::Gone)
+        );
+        assert_eq!(membership_change(&member, &member), None);
+    }
+
+    #[test]
+    fn test_sort_search_results() {
+        let hit = |id: u64, date: Option<&str>, rating: Option<f64>| MultiNorm {
+            id,
+            media_type: MediaKind::Movie,
+            title: "Дюна".to_string(),
+            original_title: "Dune".to_string(),
+            overview: "".to_string(),
+            release_date: date.map(str::to_string),
+            image_path: None,
+            vote_average: rating,
+            vote_count: None,
+            runtime: None,
+            seasons: None,
+            tagline: None,
+            original_language: None,
+        };
+        let results = vec![
+            hit(1, Some("1984-12-14"), Some(6.3)),
+            hit(2, None, Some(8.0)),
+            hit(3, Some("2021-09-15"), None),
+            hit(4, Some(""), Some(7.8)),
+        ];
+        let ids = |by: SearchSort| {
+            let mut r = results.clone();
+            sort_search_results(&mut r, by);
+            r.iter().map(|m| m.id).collect::<Vec<_>>()
+        };
+        assert_eq!(ids(SearchSort::Popularity), vec![1, 2, 3, 4]);
+        assert_eq!(ids(SearchSort::Date), vec![3, 1, 2, 4]);
+        assert_eq!(ids(SearchSort::Rating), vec![2, 4, 1, 3]);
+        assert_eq!(SearchSort::parse(" Date "), Some(SearchSort::Date));
+        assert_eq!(SearchSort::parse("new"), None);
+    }
+
+    #[test]
+    fn test_parse_overview_len() {
+        assert_eq!(parse_overview_len("X", None, 600), 600);
+        assert_eq!(parse_overview_len("X", Some(" 400 "), 2000), 400);
+        assert_eq!(parse_overview_len("X", Some("abc"), 600), 600);
+        assert_eq!(parse_overview_len("X", Some("10"), 600), 600);
+        assert_eq!(parse_overview_len("X", Some("5000"), 2000), 2000);
+    }
+
+    #[test]
+    fn test_compare_tables() {
+        let rows: Vec<CompareRow> = (1..=3)
+            .map(|i| CompareRow {
+                title: format!("Фильм <{}>", i),
+                year: Some("2010-07-16".to_string()),
+                rating: Some(8.36),
+                runtime: if i == 2 { None } else { Some(148) },
+            })
+            .collect();
+        let parts = compare_tables(&rows, 4000);
+        assert_eq!(parts.len(), 1);
+        let lines: Vec<&str> = parts[0].lines().collect();
+        assert!(lines[0].starts_with("<pre>#  Название"));
+        assert_eq!(lines[1], "1  Фильм &lt;1&gt;             2010  8.4  148");
+        assert!(lines[2].ends_with("8.4  —"));
+        assert!(parts[0].ends_with("</pre>"));
+
+        // длинная таблица режется по строкам, заголовок повторяется
+        let parts = compare_tables(&rows, 150);
+        assert!(parts.len() > 1);
+        assert!(parts
+            .iter()
+            .all(|p| p.starts_with("<pre>#") && p.ends_with("</pre>")));
+    }
+
+    #[test]
+    fn test_pick_surprise_skips_listed() {
+        let found: Vec<MultiNorm> = (1..=3)
+            .map(|i| MultiNorm {
+                id: i,
+                media_type: MediaKind::Movie,
+                title: format!("Movie {}", i),
+                original_title: format!("Movie {}", i),
+                overview: "".to_string(),
+                release_date: None,
+                image_path: None,
+                vote_average: None,
+                vote_count: None,
+                runtime: None,
+                seasons: None,
+                tagline: None,
+                original_language: None,
+            })
+            .collect();
+        let listed = |id: u64, watched: bool| StoredMovie {
+            id,
+            title: format!("Movie {}", id),
+            original_title: format!("Movie {}", id),
+            media_type: MediaKind::Movie,
+            poster_path: None,
+            release_date: None,
+            watched,
+            vote_average: None,
+            custom_title: None,
+        };
+
+        let list = vec![listed(1, false), listed(3, true)];
+        for _ in 0..10 {
+            let m = pick_surprise(found.clone(), &list).unwrap();
+            assert_eq!(m.id, 2);
+        }
+
+        let list = vec![listed(1, false), listed(2, false), listed(3, true)];
+        assert!(pick_surprise(found, &list).is_none());
+    }
+
+    #[test]
+    fn test_parse_exported_list() {
+        let json = r#"[{"id": 1, "title": "Паразиты", "original_title": "기생충",
+            "media_type": "movie", "poster_path": null, "release_date": "2019-05-30"}]"#;
+        let movies = parse_exported_list(json).unwrap();
+        assert_eq!(movies.len(), 1);
+        assert_eq!(movies[0].title, "Паразиты");
+        let fenced = format!("```json\n{}\n```", json);
+        assert_eq!(parse_exported_list(&fenced).unwrap()[0].id, 1);
+        assert!(parse_exported_list("[]").is_none());
+        assert!(parse_exported_list("Паразиты").is_none());
+    }
+
+    #[test]
+    fn test_parse_album_max() {
+        assert_eq!(parse_album_max(None), 10);
+        assert_eq!(parse_album_max(Some("4")), 4);
+        assert_eq!(parse_album_max(Some("25")), 10);
+        assert_eq!(parse_album_max(Some("0")), 10);
+        assert_eq!(parse_album_max(Some("many")), 10);
+    }
+
+    #[test]
+    fn test_genres_text() {
+        let movie = vec!["Боевик".to_string(), "Комедия".to_string()];
+        let tv = vec!["Action & Adventure".to_string()];
+        assert_eq!(
+            genres_text(&movie, &tv),
+            "<b>Жанры фильмов</b>\nБоевик, Комедия\n\n<b>Жанры сериалов</b>\nAction &amp; Adventure"
+        );
+        assert!(genres_text(&[], &tv).contains("<b>Жанры фильмов</b>\n—"));
+    }
+
+    #[test]
+    fn test_album_caption_notes_missing_posters() {
+        assert_eq!(
+            album_caption(Some("<b>Постеры</b>"), 0).as_deref(),
+            Some("<b>Постеры</b>")
+        );
+        assert_eq!(album_caption(None, 0), None);
+        assert_eq!(
+            album_caption(Some("<b>Постеры</b>"), 2).as_deref(),
+            Some("<b>Постеры</b>\n(2 фильма без постера)")
+        );
+        assert_eq!(
+            album_caption(None, 5).as_deref(),
+            Some("(5 фильмов без постера)")
+        );
+    }
+
+    #[test]
+    fn test_person_button_goes_last() {
+        let item = |id: u64, media_type: MediaKind, title: &str| MultiNorm {
+            id,
+            media_type,
+            title: title.to_string(),
+            original_title: title.to_string(),
+            overview: "".to_string(),
+            release_date: None,
+            image_path: None,
+            vote_average: None,
+            vote_count: None,
+            runtime: None,
+            seasons: None,
+            tagline: None,
+            original_language: None,
+        };
+        let films = vec![item(1, MediaKind::Movie, "Довод")];
+        let person = item(525, MediaKind::Person, "Кристофер Нолан");
+        let kb = keyboard_add_results(&films, Some(&person));
+        assert_eq!(kb.inline_keyboard.len(), 2);
+        assert!(matches!(
+            &kb.inline_keyboard[0][0].kind,
+            teloxide::types::InlineKeyboardButtonKind::CallbackData(d) if d == "add:1:movie"
+        ));
+        let last = &kb.inline_keyboard[1][0];
+        assert_eq!(last.text, "🎭 Фильмы Кристофер Нолан");
+        assert!(matches!(
+            &last.kind,
+            teloxide::types::InlineKeyboardButtonKind::CallbackData(d) if d == "person:525:person"
+        ));
+        assert_eq!(keyboard_add_results(&films, None).inline_keyboard.len(), 1);
+    }
+
+    #[test]
+    fn test_feedback_report() {
+        let mut user = teloxide::types::User {
+            id: UserId(42),
+            is_bot: false,
+            first_name: "Анна".to_string(),
+            last_name: Some("К.".to_string()),
+            username: None,
+            language_code: None,
+            is_premium: false,
+            added_to_attachment_menu: false,
+        };
+        assert_eq!(
+            feedback_report(ChatId(42), None, Some(&user), "bug!"),
+            "📝 Отзыв из чата 42 от Анна К. (id 42):\nbug!"
+        );
+        user.username = Some("anna".to_string());
+        assert_eq!(
+            feedback_report(ChatId(-100), Some("Киноклуб"), Some(&user), "bug!"),
+            "📝 Отзыв из чата -100 «Киноклуб» от @anna (id 42):\nbug!"
+        );
+    }
+
+    #[test]
+    fn test_ids_text() {
+        let item = |id: u64, media_type: MediaKind, title: &str, date: Option<&str>| StoredMovie {
+            id,
+            title: title.to_string(),
+            original_title: title.to_string(),
+            media_type,
+            poster_path: None,
+            release_date: date.map(str::to_string),
+            watched: false,
+            vote_average: None,
+            custom_title: None,
+        };
+        let movie = item(550, MediaKind::Movie, "Бойцовский клуб", Some("1999-10-15"));
+        let show = item(1399, MediaKind::Tv, "Игра престолов", None);
+        assert_eq!(
+            ids_text(&[movie, show]),
+            "1. Бойцовский клуб (1999) — movie/550\n2. Игра престолов — tv/1399"
+        );
+    }
+
+    #[test]
+    fn test_episodes_arg_and_options() {
+        assert_eq!(parse_episodes_arg("2"), Some((2, 1)));
+        assert_eq!(parse_episodes_arg(" 2  11 "), Some((2, 11)));
+        assert_eq!(parse_episodes_arg(""), None);
+        assert_eq!(parse_episodes_arg("2 0"), None);
+        assert_eq!(parse_episodes_arg("два"), None);
+        assert_eq!(parse_episodes_arg("2 3 4"), None);
+
+        let episodes: Vec<tmdb::Episode> = (1..=12)
+            .map(|n| tmdb::Episode {
+                episode_number: n,
+                name: if n == 2 {
+                    String::new()
+                } else {
+                    format!("Эпизод {}", n)
+                },
+            })
+            .collect();
+        let options = episode_poll_options(&episodes, 1);
+        assert_eq!(options.len(), 10);
+        assert_eq!(options[0], "1. Эпизод 1");
+        assert_eq!(options[1], "Серия 2");
+        let tail = episode_poll_options(&episodes, 11);
+        assert_eq!(tail, vec!["11. Эпизод 11", "12. Эпизод 12"]);
+    }
+
+    #[test]
+    fn test_help_text() {
+        let all = Command::descriptions().to_string();
+        assert_eq!(help_text(""), all);
+        assert!(help_text("remove").contains("🗑"));
+        assert!(help_text("remove").contains("/remove N"));
+        // регистр и «/» в начале не мешают
+        assert_eq!(help_text("/Vote"), help_text("vote"));
+        assert!(help_text("vote").contains("/vote preview"));
+        let unknown = help_text("nope");
+        assert!(unknown.starts_with("Подробной справки по «nope» нет."));
+        assert!(unknown.ends_with(&all));
+    }
+
+    #[test]
+    fn test_settings_text() {
+        let settings = ChatSettings {
+            region: "US".to_string(),
+            language: "en".to_string(),
+            include_adult: false,
+            min_votes: 10,
+            show_original_title: true,
+            vote_question: None,
+            posters: false,
+            auto_list: false,
+            rich_trailers: true,
+            search_sort: SearchSort::Date,
+            auto_pin: true,
+        };
+        let flags = PollFlags {
+            anonymous: false,
+            multiple: true,
+            admin_only: true,
+        };
+        let text = settings_text(&settings, flags, 3, 12);
+        assert!(text.contains("Язык трейлеров: en"));
+        assert!(text.contains("Регион: US"));
+        assert!(text.contains("анонимный — нет, несколько ответов — да"));
+        assert!(text.contains("Список: 3/10, бэклог: 12"));
+        assert!(text.contains("только админы"));
+        assert!(text.contains("Взрослый контент в поиске: нет"));
+        assert!(text.contains("Мин. голосов TMDb в поиске: 10"));
+        assert!(text.contains("Оригинальные названия: да"));
+        assert!(text.contains("Вопрос опроса: Что смотрим?"));
+        assert!(text.contains("Постеры: нет"));
+        assert!(text.contains("Список после добавления: нет"));
+        assert!(text.contains("Трейлеры с превью: да"));
+        assert!(text.contains("Порядок поиска: date"));
+        assert!(text.contains("Закреплять опрос: да"));
+    }
+
+    #[test]
+    fn test_list_title_with_original() {
+        let m = StoredMovie {
+            id: 496243,
+            title: "Паразиты".to_string(),
+            original_title: "기생충".to_string(),
+            media_type: MediaKind::Movie,
+            poster_path: None,
+            release_date: Some("2019-05-30".to_string()),
+            watched: false,
+            vote_average: None,
+            custom_title: None,
+        };
+        assert_eq!(list_title(&m, false), "Паразиты (2019)");
+        assert_eq!(list_title(&m, true), "Паразиты (기생충, 2019)");
+
+        let same = StoredMovie {
+            original_title: "Паразиты".to_string(),
+            release_date: None,
+            ..m
+        };
+        assert_eq!(list_title(&same, true), "Паразиты");
+    }
+
+    #[test]
+    fn test_parse_attribution() {
+        let a = parse_attribution(None, None);
+        assert_eq!(a.text, DEFAULT_ATTRIBUTION);
+        assert!(!a.inline);
+
+        let a = parse_attribution(Some("Спасибо TMDb!"), Some("1"));
+        assert_eq!(a.text, "Спасибо TMDb!");
+        assert!(a.inline);
+
+        // без упоминания TMDb — дописываем
+        let a = parse_attribution(Some("Киноклуб"), Some("no"));
+        assert_eq!(a.text, "Киноклуб · © TMDB");
+        assert!(!a.inline);
+    }
+
+    #[test]
+    fn test_vote_preview_text() {
+        let movie = |id: u64, title: &str| StoredMovie {
+            id,
+            title: title.to_string(),
+            original_title: title.to_string(),
+            media_type: MediaKind::Movie,
+            poster_path: None,
+            release_date: Some("1999-03-31".to_string()),
+            watched: false,
+            vote_average: None,
+            custom_title: None,
+        };
+        let defaults = ChatSettings::default();
+        let text = vote_preview_text(&[movie(1, "Матрица"), movie(2, "Он & она")], &defaults);
+        assert!(text.contains("Что смотрим?"));
+        assert!(text.contains("1. Матрица (1999)"));
+        assert!(text.contains("2. Он &amp; она (1999)"));
+
+        let renamed = StoredMovie {
+            custom_title: Some("Та самая".to_string()),
+            ..movie(3, "Матрица")
+        };
+        let custom = ChatSettings {
+            vote_question: Some("Пятничное кино?".to_string()),
+            show_original_title: true,
+            ..ChatSettings::default()
+        };
+        let text = vote_preview_text(&[movie(1, "Матрица"), renamed], &custom);
+        assert!(text.contains("2. Та самая (Матрица, 1999)"));
+        assert!(text.contains("Пятничное кино?"));
+
+        // варианты те же, что уйдут в опрос: одинаковые названия различаются
+        let text = vote_preview_text(&[movie(1, "Дюна"), movie(2, "Дюна")], &defaults);
+        assert!(text.contains("1. Дюна (1999) · фильм #1"));
+        assert!(text.contains("2. Дюна (1999) · фильм #2"));
+
+        let text = vote_preview_text(&[movie(1, "Матрица")], &defaults);
+        assert!(text.contains("минимум 2"));
+    }
+
+    #[test]
+    fn test_sort_list() {
+        let movie = |id: u64, title: &str, rating: Option<f64>| StoredMovie {
+            id,
+            title: title.to_string(),
+            original_title: title.to_string(),
+            media_type: MediaKind::Movie,
+            poster_path: None,
+            release_date: None,
+            watched: false,
+            vote_average: rating,
+            custom_title: None,
+        };
+        let mut list = vec![
+            movie(1, "бойцовский клуб", Some(8.4)),
+            movie(2, "Амели", None),
+            movie(3, "Матрица", Some(8.7)),
+            movie(4, "Вечное сияние", Some(8.4)),
+        ];
+
+        sort_list(&mut list, SortBy::Rating);
+        let ids: Vec<u64> = list.iter().map(|m| m.id).collect();
+        assert_eq!(ids, vec![3, 1, 4, 2]);
+
+        sort_list(&mut list, SortBy::Title);
+        let ids: Vec<u64> = list.iter().map(|m| m.id).collect();
+        assert_eq!(ids, vec![2, 1, 4, 3]);
+    }
+
+    #[test]
+    fn test_html_link_escapes_quotes() {
+        assert_eq!(
+            html_attr_escape(r#"a"b'c<&>"#),
+            "a&quot;b&#39;c&lt;&amp;&gt;"
+        );
+        assert_eq!(
+            html_link("https://youtu.be/x?a=1&t=\"2\"", "\"Heat\" <1995>"),
+            "<a href=\"https://youtu.be/x?a=1&amp;t=&quot;2&quot;\">\"Heat\" &lt;1995&gt;</a>"
+        );
+    }
+
+    #[test]
+    fn test_keyboard_show_card_links() {
+        let m = MultiNorm {
+            id: 1399,
+            media_type: MediaKind::Tv,
+            title: "Игра престолов".to_string(),
+            original_title: "Game of Thrones".to_string(),
+            overview: "".to_string(),
+            release_date: None,
+            image_path: None,
+            vote_average: None,
+            vote_count: None,
+            runtime: None,
+            seasons: None,
+            tagline: None,
+            original_language: None,
+        };
+        let urls = |kb: &InlineKeyboardMarkup| -> Vec<String> {
+            kb.inline_keyboard[1]
+                .iter()
+                .filter_map(|b| match &b.kind {
+                    teloxide::types::InlineKeyboardButtonKind::Url(u) => Some(u.to_string()),
+                    _ => None,
+                })
+                .collect()
+        };
+
+        let kb = keyboard_show_card(
+            &m,
+            Some("tt0944947"),
+            Some("https://www.youtube.com/watch?v=abc"),
+        );
+        assert_eq!(
+            urls(&kb),
+            vec![
+                "https://www.themoviedb.org/tv/1399",
+                "https://www.imdb.com/title/tt0944947/",
+                "https://www.youtube.com/watch?v=abc"
+            ]
+        );
+
+        let kb = keyboard_show_card(&m, None, None);
+        assert_eq!(urls(&kb), vec!["https://www.themoviedb.org/tv/1399"]);
+
+        let callbacks: Vec<String> = kb.inline_keyboard[0]
+            .iter()
+            .filter_map(|b| match &b.kind {
+                teloxide::types::InlineKeyboardButtonKind::CallbackData(d) => Some(d.clone()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(callbacks, vec!["gallery:1399:tv", "tags:1399:tv"]);
+
+        let tags: Vec<String> = (1..=12).map(|i| format!("tag{}", i)).collect();
+        let text = tags_text(&m, &tags);
+        assert!(text.contains("tag10"));
+        assert!(!text.contains("tag11"));
+    }
+
+    #[test]
+    fn test_is_bot_admin() {
+        let user = |id: u64| teloxide::types::User {
+            id: UserId(id),
+            is_bot: false,
+            first_name: "test".to_string(),
+            last_name: None,
+            username: None,
+            language_code: None,
+            is_premium: false,
+            added_to_attachment_menu: false,
+        };
+        assert!(is_bot_admin(Some(&user(7)), Some(UserId(7))));
+        assert!(!is_bot_admin(Some(&user(8)), Some(UserId(7))));
+        assert!(!is_bot_admin(Some(&user(7)), None));
+        assert!(!is_bot_admin(None, Some(UserId(7))));
+    }
+
+    #[test]
+    fn test_parse_when() {
+        let tz = chrono::FixedOffset::east_opt(3 * 3600).unwrap();
+        let now = tz.with_ymd_and_hms(2025, 1, 31, 18, 0, 0).unwrap();
+
+        let at = parse_when("19:30", &now).unwrap();
+        assert_eq!(at, tz.with_ymd_and_hms(2025, 1, 31, 19, 30, 0).unwrap());
+        // уже прошло сегодня — значит завтра
+        let at = parse_when("17:00", &now).unwrap();
+        assert_eq!(at, tz.with_ymd_and_hms(2025, 2, 1, 17, 0, 0).unwrap());
+
+        let at = parse_when("2025-02-14 20:00", &now).unwrap();
+        assert_eq!(at, tz.with_ymd_and_hms(2025, 2, 14, 20, 0, 0).unwrap());
+        let at = parse_when("14.02.2025 20:00", &now).unwrap();
+        assert_eq!(at, tz.with_ymd_and_hms(2025, 2, 14, 20, 0, 0).unwrap());
+
+        assert!(parse_when("2024-12-31 20:00", &now).is_none());
+        assert!(parse_when("25:00", &now).is_none());
+        assert!(parse_when("завтра", &now).is_none());
+    }
+
+    #[test]
+    fn test_split_when() {
+        let tz = chrono::FixedOffset::east_opt(3 * 3600).unwrap();
+        let now = tz.with_ymd_and_hms(2025, 1, 31, 12, 0, 0).unwrap();
+
+        let (at, text) = split_when("18:00 Смотрим  сегодня!", &now).unwrap();
+        assert_eq!(at, tz.with_ymd_and_hms(2025, 1, 31, 18, 0, 0).unwrap());
+        assert_eq!(text, "Смотрим  сегодня!");
+
+        let (at, text) = split_when("2025-02-01 18:00 Завтра кино", &now).unwrap();
+        assert_eq!(at, tz.with_ymd_and_hms(2025, 2, 1, 18, 0, 0).unwrap());
+        assert_eq!(text, "Завтра кино");
+
+        assert!(split_when("18:00", &now).is_none());
+        assert!(split_when("вечером кино", &now).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_run_job_sends_reminder() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path_regex(".*Message"))
+            .and(wiremock::matchers::body_partial_json(
+                serde_json::json!({"chat_id": 901, "text": "⏰ Смотрим сегодня!"}),
+            ))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "ok": true,
+                "result": {
+                    "message_id": 1,
+                    "date": 1,
+                    "chat": {"id": 901, "type": "private", "first_name": "test"},
+                    "text": "⏰ Смотрим сегодня!"
+                }
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let bot = Bot::new("token").set_api_url(server.uri().parse().unwrap());
+        let tmdb = TmdbClient::new_test("token".to_string(), server.uri());
+        let storage_path = temp_store_path("tg_test_storage_remind");
+        let storage = Storage::new(storage_path.clone()).await.unwrap();
+
+        let job = ScheduledJob {
+            id: 1,
+            chat_id: 901,
+            at: 0,
+            kind: JobKind::Remind("Смотрим сегодня!".to_string()),
+        };
+        run_job(&bot, &tmdb, &storage, job, false, true)
+            .await
+            .unwrap();
+
+        drop(storage);
+        remove_temp_store(&storage_path);
+    }
+
+    #[test]
+    fn test_join_blocks_counts_visible_text() {
+        assert_eq!(
+            visible_len("<b>Дюна</b> &amp; <a href=\"https://x.y/?a=1\">ещё</a>"),
+            10
+        );
+        // 400 видимых символов, но вдвое больше с разметкой
+        let block = format!(
+            "<a href=\"https://www.themoviedb.org/movie/1\"><b>{}</b></a>{}",
+            "Ф".repeat(100),
+            "<i>о</i>".repeat(300)
+        );
+        assert_eq!(visible_len(&block), 400);
+        assert!(block.chars().count() > 800);
+        let text = join_blocks(vec![block; 10], 3500);
+        // 8 блоков влезают, девятый переполняет и добавляется последним
+        assert_eq!(text.matches("<a href").count(), 9);
+    }
+
+    #[test]
+    fn test_split_html_prefers_line_breaks() {
+        let text = (1..=50)
+            .map(|i| format!("<b>Фильм {}</b> — описание", i))
+            .collect::<Vec<_>>()
+            .join("\n");
+        let parts = split_html(&text, 200, true);
+        assert!(parts.len() > 1);
+        for p in &parts {
+            assert!(p.chars().count() <= 200);
+            assert_eq!(p.matches("<b>").count(), p.matches("</b>").count());
+        }
+        assert_eq!(parts.join("\n"), text);
+    }
+
+    #[test]
+    fn test_split_html_long_line_keeps_tags_balanced() {
+        let text = format!("<i>{}</i> &amp; конец", "слово ".repeat(100));
+        let parts = split_html(&text, 120, true);
+        assert!(parts.len() > 1);
+        for p in &parts {
+            assert!(p.chars().count() <= 120);
+            assert!(p.starts_with("<i>") || p.ends_with("конец"));
+            assert_eq!(p.matches("<i>").count(), p.matches("</i>").count());
+        }
+        assert!(parts.last().unwrap().ends_with("&amp; конец"));
+
+        // обычный текст: «<» — просто символ
+        let plain = "a<b ".repeat(50);
+        let parts = split_html(&plain, 60, false);
+        assert_eq!(parts.concat(), plain);
+    }
+
+    #[test]
+    fn test_cross_media_duplicate() {
+        let listed = StoredMovie {
+            id: 1,
+            title: "Шерлок Холмс".to_string(),
+            original_title: "Sherlock Holmes".to_string(),
+            media_type: MediaKind::Movie,
+            poster_path: None,
+            release_date: Some("2009-12-25".to_string()),
+            watched: false,
+            vote_average: None,
+            custom_title: None,
+        };
+        let candidate = |media_type: MediaKind, title: &str, date: Option<&str>| MultiNorm {
+            id: 2,
+            media_type,
+            title: title.to_string(),
+            original_title: "Sherlock Holmes".to_string(),
+            overview: "".to_string(),
+            release_date: date.map(str::to_string),
+            image_path: None,
+            vote_average: None,
+            vote_count: None,
+            runtime: None,
+            seasons: None,
+            tagline: None,
+            original_language: None,
+        };
+        let list = vec![listed];
+
+        let tv = candidate(MediaKind::Tv, "Шерлок Холмс!", Some("2009-01-01"));
+        assert_eq!(cross_media_duplicate(&list, &tv).map(|m| m.id), Some(1));
+        // другой год — другой тайтл
+        let tv = candidate(MediaKind::Tv, "Шерлок Холмс", Some("1979-01-01"));
+        assert!(cross_media_duplicate(&list, &tv).is_none());
+        // тот же тип — это обычный дубль, его ловит add_movie
+        let movie = candidate(MediaKind::Movie, "Шерлок Холмс", Some("2009-12-25"));
+        assert!(cross_media_duplicate(&list, &movie).is_none());
+    }
+
+    #[test]
+    fn test_html_escape() {
+        assert_eq!(html_escape("A & B < C > D"), "A &amp; B &lt; C &gt; D");
+    }
+
+    #[tokio::test]
+    async fn test_on_search_text_updates_last_search() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path_regex(".*"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "ok": true,
+                "result": {
+                    "message_id": 1,
+                    "date": 1,
+                    "chat": {"id": 123, "type": "private", "first_name": "test"},
+                    "text": "test"
+                }
+            })))
+            .mount(&server)
+            .await;
+
+        let bot = Bot::new("token").set_api_url(server.uri().parse().unwrap());
+
+        let tmdb_server = MockServer::start().await;
+        let tmdb = TmdbClient::new_test("token".to_string(), tmdb_server.uri());
+
+        let tmdb_response = serde_json::json!({
+            "page": 1,
+            "total_pages": 1,
+            "total_results": 1,
+            "results": [
+                {
+                    "media_type": "movie",
+                    "id": 1,
+                    "title": "Mock Movie",
+                    "original_title": "Mock Movie",
+                    "overview": "Overview",
+                    "poster_path": "/path.jpg",
+                    "release_date": "2023-01-01"
+                }
+            ]
+        });
+        Mock::given(method("GET"))
+            .and(path("/search/multi"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(tmdb_response))
+            .mount(&tmdb_server)
+            .await;
+
+        let storage_path = temp_store_path("tg_test_storage");
+        let storage = Storage::new(storage_path.clone()).await.unwrap();
+
+        let msg = serde_json::from_value::<Message>(serde_json::json!({
+            "message_id": 1,
+            "date": 1,
+            "chat": {"id": 123, "type": "private", "first_name": "test"},
+            "text": "test search"
+        })).unwrap();
+
+        on_search_text(bot, msg, &tmdb, &storage).await.unwrap();
+
+        let results = LAST_SEARCH.get(&(ChatId(123), 1)).await.unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].title, "Mock Movie");
+
+        remove_temp_store(&storage_path);
+    }
+
+    #[tokio::test]
+    async fn test_full_flow_search_and_add() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path_regex(".*Message"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "ok": true,
+                "result": {
+                    "message_id": 1,
+                    "date": 1,
+                    "chat": {"id": 456, "type": "private", "first_name": "test"},
+                    "text": "test"
+                }
+            })))
+            .mount(&server)
+            .await;
+
+        Mock::given(method("POST"))
+            .and(path_regex(".*Query"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "ok": true,
+                "result": true
+            })))
+            .mount(&server)
+            .await;
+
+        let bot = Bot::new("token").set_api_url(server.uri().parse().unwrap());
+
+        let tmdb_server = MockServer::start().await;
+        let tmdb = TmdbClient::new_test("token".to_string(), tmdb_server.uri());
+
+        Mock::given(method("GET"))
+            .and(wiremock::matchers::path("/search/multi"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "page": 1,
+                "total_pages": 1,
+                "total_results": 1,
+                "results": [
+                    {
+                        "media_type": "movie",
+                        "id": 456,
+                        "title": "Integration Movie",
+                        "original_title": "Integration Movie",
+                        "overview": "Integration Overview",
+                        "poster_path": "/int.jpg",
+                        "release_date": "2024-01-01"
+                    }
+                ]
+            })))
+            .mount(&tmdb_server)
+            .await;
+
+        let storage_path = temp_store_path("integration_test_storage");
+        let storage = Storage::new(storage_path.clone()).await.unwrap();
+
+        let search_msg = serde_json::from_value::<Message>(serde_json::json!({
+            "message_id": 1,
+            "date": 1,
+            "chat": {"id": 456, "type": "private", "first_name": "test"},
+            "text": "integration"
+        })).unwrap();
+
+        on_search_text(bot.clone(), search_msg, &tmdb, &storage).await.unwrap();
+
+        {
+            let results = LAST_SEARCH.get(&(ChatId(456), 1)).await.unwrap();
+            assert_eq!(results[0].id, 456);
+        }
+
+        let q = serde_json::from_value::<CallbackQuery>(serde_json::json!({
+            "id": "1",
+            "from": {"id": 456, "is_bot": false, "first_name": "test"},
+            "chat_instance": "1",
+            "data": "add:456:movie",
+            "message": {
+                "message_id": 1,
+                "date": 2,
+                "chat": {"id": 456, "type": "private", "first_name": "test"},
+                "text": "results"
+            }
+        })).unwrap();
+
+        on_callback(bot, q, &tmdb, &storage).await.unwrap();
+
+        let stored = storage.get(456).await;
+        assert_eq!(stored.len(), 1);
+        assert_eq!(stored[0].title, "Integration Movie");
+
+        remove_temp_store(&storage_path);
+    }
+
+    #[tokio::test]
+    async fn test_search_view_depends_on_chat_kind() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path_regex(".*Message"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "ok": true,
+                "result": {
+                    "message_id": 31,
+                    "date": 1,
+                    "chat": {"id": 906, "type": "private", "first_name": "test"},
+                    "text": "test"
+                }
+            })))
+            .mount(&server)
+            .await;
+        let bot = Bot::new("token").set_api_url(server.uri().parse().unwrap());
+
+        // явный лидер: много голосов и вдвое больше, чем у второго; без постера — не качаем
+        let tmdb_server = MockServer::start().await;
+        let hit = |id: u64, votes: u64| {
+            serde_json::json!({
+                "media_type": "movie", "id": id, "title": "Хит", "original_title": "Hit",
+                "overview": "", "vote_count": votes
+            })
+        };
+        Mock::given(method("GET"))
+            .and(path_regex("^/search/(multi|movie)$"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "page": 1, "total_pages": 1, "total_results": 2,
+                "results": [hit(1, 5000), hit(2, 40)]
+            })))
+            .mount(&tmdb_server)
+            .await;
+        let tmdb = TmdbClient::new_test("token".to_string(), tmdb_server.uri());
+        let storage_path = temp_store_path("tg_test_storage_search_view");
+        let storage = Storage::new(storage_path.clone()).await.unwrap();
+
+        let dm: Chat = serde_json::from_value(serde_json::json!({
+            "id": 906, "type": "private", "first_name": "test"
+        }))
+        .unwrap();
+        search_and_show(&bot, &dm, &tmdb, &storage, "хит")
+            .await
+            .unwrap();
+        let shown = LAST_SEARCH.get(&(ChatId(906), 31)).await.unwrap();
+        assert_eq!(shown.len(), 1, "в личке — карточка лучшего совпадения");
+
+        let group: Chat = serde_json::from_value(serde_json::json!({
+            "id": -102, "type": "group", "title": "group"
+        }))
+        .unwrap();
+        search_and_show(&bot, &group, &tmdb, &storage, "хит")
+            .await
+            .unwrap();
+        let shown = LAST_SEARCH.get(&(ChatId(-102), 31)).await.unwrap();
+        assert_eq!(shown.len(), 2, "в группе — компактный список");
+
+        // /find — всегда карточка, и в группе тоже
+        let find = serde_json::from_value::<Message>(serde_json::json!({
+            "message_id": 32,
+            "date": 1,
+            "chat": {"id": -103, "type": "group", "title": "group"},
+            "from": {"id": 7, "is_bot": false, "first_name": "test"},
+            "text": "/find хит"
+        }))
+        .unwrap();
+        on_command(
+            bot,
+            find,
+            Command::Find("хит".to_string()),
+            &tmdb,
+            &storage,
+            false,
+            true,
+        )
+        .await
+        .unwrap();
+        let shown = LAST_SEARCH.get(&(ChatId(-103), 31)).await.unwrap();
+        assert_eq!(shown.len(), 1, "/find в группе — тоже карточка");
+
+        drop(storage);
+        remove_temp_store(&storage_path);
+    }
+
+    #[tokio::test]
+    async fn test_edited_query_searches_again_once() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path_regex(".*Message"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "ok": true,
+                "result": {
+                    "message_id": 41,
+                    "date": 1,
+                    "chat": {"id": 908, "type": "private", "first_name": "test"},
+                    "text": "results"
+                }
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/search/multi"))
+            .and(wiremock::matchers::query_param("query", "Интерстеллар"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "page": 1, "total_pages": 1, "total_results": 1,
+                "results": [{
+                    "media_type": "movie", "id": 157336, "title": "Интерстеллар",
+                    "original_title": "Interstellar", "overview": "Overview",
+                    "poster_path": null, "release_date": "2014-11-05"
+                }]
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let bot = Bot::new("token").set_api_url(server.uri().parse().unwrap());
+        let tmdb = TmdbClient::new_test("token".to_string(), server.uri());
+        let storage_path = temp_store_path("tg_test_storage_edited");
+        let storage = Storage::new(storage_path.clone()).await.unwrap();
+
+        let edited = |text: &str| {
+            serde_json::from_value::<Message>(serde_json::json!({
+                "message_id": 5,
+                "date": 1,
+                "edit_date": 2,
+                "chat": {"id": 908, "type": "private", "first_name": "test"},
+                "from": {"id": 908, "is_bot": false, "first_name": "test"},
+                "text": text
+            }))
+            .unwrap()
+        };
+        // та же правка дважды — поиск один раз; команды при правке не запускаем
+        for text in ["Интерстеллар", "Интерстеллар", "/vote"] {
+            on_edited_search_text(bot.clone(), edited(text), &tmdb, &storage)
+                .await
+                .unwrap();
         }
+        let results = LAST_SEARCH.get(&(ChatId(908), 41)).await.unwrap();
+        assert_eq!(results[0].id, 157336);
+
+        drop(storage);
+        remove_temp_store(&storage_path);
     }
-    Ok(())
-}
 
-/* ====== /list: показать список с кнопками ====== */
-async fn send_list_view<R>(bot: &R, chat: ChatId, storage: &Storage) -> ResponseResult<()>
-where
-    R: Requester<Err = RequestError>,
-{
-    let list = storage.get(chat.0).await;
-    if list.is_empty() {
-        bot.send_message(chat, "Список пуст. Пришли название — добавлю варианты.")
-            .await?;
-        return Ok(());
+    #[tokio::test]
+    async fn test_on_search_text_in_group_chat() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path_regex(".*"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "ok": true,
+                "result": {
+                    "message_id": 1,
+                    "date": 1,
+                    "chat": {"id": -10012345, "type": "group", "title": "group"},
+                    "text": "test"
+                }
+            })))
+            .mount(&server)
+            .await;
+
+        let bot = Bot::new("token").set_api_url(server.uri().parse().unwrap());
+
+        let tmdb_server = MockServer::start().await;
+        let tmdb = TmdbClient::new_test("token".to_string(), tmdb_server.uri());
+
+        let tmdb_response = serde_json::json!({
+            "page": 1, "total_pages": 1, "total_results": 1,
+            "results": [{
+                "media_type": "movie", "id": 1, "title": "Mock Movie",
+                "original_title": "Mock Movie", "overview": "Overview",
+                "poster_path": "/path.jpg", "release_date": "2023-01-01"
+            }]
+        });
+        Mock::given(method("GET"))
+            .and(path("/search/multi"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(tmdb_response))
+            .mount(&tmdb_server)
+            .await;
+
+        let storage_path = temp_store_path("tg_test_storage_group");
+        let storage = Storage::new(storage_path.clone()).await.unwrap();
+
+        let msg = serde_json::from_value::<Message>(serde_json::json!({
+            "message_id": 1,
+            "date": 1,
+            "chat": {"id": -10012345, "type": "group", "title": "group"},
+            "text": "test search"
+        })).unwrap();
+
+        on_search_text(bot.clone(), msg, &tmdb, &storage)
+            .await
+            .unwrap();
+
+        // в группе — список вариантов, а не карточка, и на него можно ответить номером
+        let results = LAST_SEARCH.get(&(ChatId(-10012345), 1)).await.unwrap();
+        assert_eq!(results.len(), 1);
+
+        let reply = serde_json::from_value::<Message>(serde_json::json!({
+            "message_id": 2,
+            "date": 1,
+            "chat": {"id": -10012345, "type": "group", "title": "group"},
+            "from": {"id": 7, "is_bot": false, "first_name": "test"},
+            "text": "1",
+            "reply_to_message": {
+                "message_id": 1,
+                "date": 1,
+                "chat": {"id": -10012345, "type": "group", "title": "group"},
+                "text": "1. Mock Movie"
+            }
+        }))
+        .unwrap();
+        on_search_text(bot, reply, &tmdb, &storage).await.unwrap();
+        assert_eq!(storage.get(-10012345).await[0].title, "Mock Movie");
+
+        drop(storage);
+        remove_temp_store(&storage_path);
     }
-    let mut lines = Vec::new();
-    for m in &list {
-        lines.push(one_line_title_stored(m));
+
+    #[tokio::test]
+    async fn test_reply_number_add_respects_auto_list() {
+        let server = MockServer::start().await;
+        // /autolist off — после добавления ничего не присылаем
+        Mock::given(method("POST"))
+            .and(path_regex(".*"))
+            .respond_with(ResponseTemplate::new(500))
+            .expect(0)
+            .mount(&server)
+            .await;
+        let bot = Bot::new("token").set_api_url(server.uri().parse().unwrap());
+        let tmdb = TmdbClient::new_test("token".to_string(), server.uri());
+        let storage_path = temp_store_path("tg_test_storage_auto_list");
+        let storage = Storage::new(storage_path.clone()).await.unwrap();
+        storage
+            .update_settings(911, |s| s.auto_list = false)
+            .await
+            .unwrap();
+
+        let found = MultiNorm {
+            id: 603,
+            media_type: MediaKind::Movie,
+            title: "Матрица".to_string(),
+            original_title: "The Matrix".to_string(),
+            overview: "".to_string(),
+            release_date: Some("1999-03-31".to_string()),
+            image_path: None,
+            vote_average: None,
+            vote_count: None,
+            runtime: None,
+            seasons: None,
+            tagline: None,
+            original_language: None,
+        };
+        LAST_SEARCH.insert((ChatId(911), 5), vec![found]).await;
+        let reply = serde_json::from_value::<Message>(serde_json::json!({
+            "message_id": 6,
+            "date": 1,
+            "chat": {"id": 911, "type": "private", "first_name": "test"},
+            "from": {"id": 911, "is_bot": false, "first_name": "test"},
+            "text": "1",
+            "reply_to_message": {
+                "message_id": 5,
+                "date": 1,
+                "chat": {"id": 911, "type": "private", "first_name": "test"},
+                "text": "1. Матрица"
+            }
+        }))
+        .unwrap();
+        on_search_text(bot, reply, &tmdb, &storage).await.unwrap();
+        assert_eq!(storage.get(911).await[0].id, 603);
+
+        drop(storage);
+        remove_temp_store(&storage_path);
     }
-    let txt = format!("<b>В списке ({}/10):</b>\n{}", list.len(), lines.join("\n"));
-    let kb = keyboard_list_two_columns_stored(&list);
-    bot.send_message(chat, txt)
-        .parse_mode(ParseMode::Html)
-        .reply_markup(kb)
-        .await?;
-    Ok(())
-}
 
-async fn run_vote_flow<R>(
-    bot: &R,
-    chat: ChatId,
-    tmdb: &TmdbClient,
-    storage: &Storage,
-    anonymous: bool,
-    multiple_ans: bool,
-) -> ResponseResult<()>
-where
-    R: Requester<Err = RequestError>,
-{
-    let list = storage.get(chat.0).await;
-    if list.len() < 2 {
-        bot.send_message(
-            chat,
-            "Нужно минимум 2 фильма в списке. Добавь и повтори /vote.",
-        )
-        .await?;
-        return Ok(());
+    #[tokio::test]
+    async fn test_multiple_searches_in_same_chat() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path_regex(".*Message"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "ok": true,
+                "result": {
+                    "message_id": 12,
+                    "date": 1,
+                    "chat": {"id": 777, "type": "private", "first_name": "test"},
+                    "text": "results 1"
+                }
+            })))
+            .up_to_n_times(2)
+            .mount(&server)
+            .await;
+
+        Mock::given(method("POST"))
+            .and(path_regex(".*Message"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "ok": true,
+                "result": {
+                    "message_id": 22,
+                    "date": 2,
+                    "chat": {"id": 777, "type": "private", "first_name": "test"},
+                    "text": "results 2"
+                }
+            })))
+            .mount(&server)
+            .await;
+
+        Mock::given(method("POST"))
+            .and(path_regex(".*Query"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "ok": true,
+                "result": true
+            })))
+            .mount(&server)
+            .await;
+
+        let bot = Bot::new("token").set_api_url(server.uri().parse().unwrap());
+
+        let tmdb_server = MockServer::start().await;
+        let tmdb = TmdbClient::new_test("token".to_string(), tmdb_server.uri());
+
+        Mock::given(method("GET"))
+            .and(wiremock::matchers::path("/search/multi"))
+            .and(wiremock::matchers::query_param("query", "movie1"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "page": 1, "total_pages": 1, "total_results": 1,
+                "results": [{
+                    "media_type": "movie", "id": 100, "title": "Movie 1",
+                    "original_title": "Movie 1", "overview": "", "poster_path": null, "release_date": "2001-01-01"
+                }]
+            })))
+            .mount(&tmdb_server)
+            .await;
+
+        Mock::given(method("GET"))
+            .and(wiremock::matchers::path("/search/multi"))
+            .and(wiremock::matchers::query_param("query", "movie2"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "page": 1, "total_pages": 1, "total_results": 1,
+                "results": [{
+                    "media_type": "movie", "id": 200, "title": "Movie 2",
+                    "original_title": "Movie 2", "overview": "", "poster_path": null, "release_date": "2002-02-02"
+                }]
+            })))
+            .mount(&tmdb_server)
+            .await;
+
+        let storage_path = temp_store_path("tg_test_storage_multiple");
+        let storage = Storage::new(storage_path.clone()).await.unwrap();
+
+        let search_msg1 = serde_json::from_value::<Message>(serde_json::json!({
+            "message_id": 1, "date": 1, "chat": {"id": 777, "type": "private"}, "text": "movie1"
+        })).unwrap();
+        on_search_text(bot.clone(), search_msg1, &tmdb, &storage).await.unwrap();
+
+        let search_msg2 = serde_json::from_value::<Message>(serde_json::json!({
+            "message_id": 2, "date": 2, "chat": {"id": 777, "type": "private"}, "text": "movie2"
+        })).unwrap();
+        on_search_text(bot.clone(), search_msg2, &tmdb, &storage).await.unwrap();
+
+        let q1 = serde_json::from_value::<CallbackQuery>(serde_json::json!({
+            "id": "1", "from": {"id": 777, "is_bot": false, "first_name": "test"},
+            "chat_instance": "1", "data": "add:100:movie",
+            "message": {
+                "message_id": 12, "date": 1, "chat": {"id": 777, "type": "private"}, "text": "results 1"
+            }
+        })).unwrap();
+        on_callback(bot.clone(), q1, &tmdb, &storage).await.unwrap();
+
+        let stored = storage.get(777).await;
+        assert_eq!(stored.len(), 1);
+        assert_eq!(stored[0].title, "Movie 1");
+
+        remove_temp_store(&storage_path);
     }
-    // опрос
-    let options: Vec<teloxide::types::InputPollOption> = list
-        .iter()
-        .map(|m| teloxide::types::InputPollOption::new(one_line_title_stored(m)))
-        .collect();
-    bot.send_poll(chat, "Что смотрим?", options)
-        .is_anonymous(anonymous)
-        .allows_multiple_answers(multiple_ans)
-        .await?;
 
-    // альбом постеров (короткий общий caption)
-    send_album_from_stored(bot, chat, &list, Some("<b>Постеры</b>")).await?;
+    #[tokio::test]
+    async fn test_add_callback_matches_media_type() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path_regex(".*"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "ok": true, "result": true
+            })))
+            .mount(&server)
+            .await;
+        let bot = Bot::new("token").set_api_url(server.uri().parse().unwrap());
+        let tmdb = TmdbClient::new_test("token".to_string(), "http://127.0.0.1:9".to_string());
+        let storage_path = temp_store_path("tg_test_storage_add_kind");
+        let storage = Storage::new(storage_path.clone()).await.unwrap();
+        storage
+            .update_settings(912, |s| s.auto_list = false)
+            .await
+            .unwrap();
+
+        // фильм и сериал с одним id TMDb в одной выдаче
+        let item = |media_type: MediaKind, title: &str| MultiNorm {
+            id: 1396,
+            media_type,
+            title: title.to_string(),
+            original_title: title.to_string(),
+            overview: "".to_string(),
+            release_date: None,
+            image_path: None,
+            vote_average: None,
+            vote_count: None,
+            runtime: None,
+            seasons: None,
+            tagline: None,
+            original_language: None,
+        };
+        LAST_SEARCH
+            .insert(
+                (ChatId(912), 14),
+                vec![
+                    item(MediaKind::Movie, "Фильм"),
+                    item(MediaKind::Tv, "Сериал"),
+                ],
+            )
+            .await;
+        let q = serde_json::from_value::<CallbackQuery>(serde_json::json!({
+            "id": "1", "from": {"id": 912, "is_bot": false, "first_name": "test"},
+            "chat_instance": "1", "data": "add:1396:tv",
+            "message": {
+                "message_id": 14, "date": 1, "chat": {"id": 912, "type": "private"}, "text": "results"
+            }
+        }))
+        .unwrap();
+        on_callback(bot, q, &tmdb, &storage).await.unwrap();
+
+        let stored = storage.get(912).await;
+        assert_eq!(stored.len(), 1);
+        assert_eq!(stored[0].title, "Сериал");
+        assert_eq!(stored[0].media_type, MediaKind::Tv);
+
+        drop(storage);
+        remove_temp_store(&storage_path);
+    }
+
+    #[tokio::test]
+    async fn test_tmdb_fallback_on_cache_miss() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path_regex(".*Query"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "ok": true, "result": true
+            })))
+            .mount(&server)
+            .await;
+            
+        Mock::given(method("POST"))
+            .and(path_regex(".*Message"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "ok": true,
+                "result": {
+                    "message_id": 2,
+                    "date": 2,
+                    "chat": {"id": 888, "type": "private", "first_name": "test"},
+                    "text": "results 2"
+                }
+            })))
+            .mount(&server)
+            .await;
+
+        let bot = Bot::new("token").set_api_url(server.uri().parse().unwrap());
 
-    // описания + трейлеры (тянем детали по id)
-    let mut blocks = Vec::new();
-    let mut trailer_lines = Vec::new();
-    for sm in &list {
-        match sm.media_type {
-            tmdb::MediaKind::Movie => {
-                if let Some(m) = tmdb
-                    .movie_details_ru(sm.id, sm.media_type)
-                    .await
-                    .map_err(to_req_err)?
-                {
-                    let trailer = tmdb
-                        .best_trailer_url(m.clone())
-                        .await
-                        .map_err(to_req_err)
-                        .ok()
-                        .flatten();
-
-                    if let Some(t) = trailer.as_ref() {
-                        trailer_lines.push(format!(
-                            "• <b>{}</b>: {}",
-                            html_escape(&m.title),
-                            html_escape(t)
-                        ));
-                    }
-                    blocks.push(make_block(&m, 1200));
-                }
-            }
-            tmdb::MediaKind::Tv => {
-                if let Some(m) = tmdb
-                    .movie_details_ru(sm.id, sm.media_type)
-                    .await
-                    .map_err(to_req_err)?
-                {
-                    let trailer = tmdb
-                        .best_trailer_url(m.clone())
-                        .await
-                        .map_err(to_req_err)
-                        .ok()
-                        .flatten();
-
-                    if let Some(t) = trailer.as_ref() {
-                        trailer_lines.push(format!(
-                            "• <b>{}</b>: {}",
-                            html_escape(&m.title),
-                            html_escape(t)
-                        ));
-                    }
-                    blocks.push(make_block(&m, 1200));
-                }
-            }
-            tmdb::MediaKind::Person => {
-                // пропускаем
-            }
-        }
-    }
-    let text = join_blocks(blocks, 4000 - 50);
-    for part in split_by_chars(&text, 4000) {
-        bot.send_message(chat, part)
-            .parse_mode(ParseMode::Html)
-            .await?;
-    }
-    if !trailer_lines.is_empty() {
-        bot.send_message(
-            chat,
-            format!("<b>Трейлеры</b>\n{}", trailer_lines.join("\n")),
-        )
-        .parse_mode(ParseMode::Html)
-        .await?;
-    }
-    bot.send_message(chat, "Данные и изображения: © TMDB")
-        .await?;
-    Ok(())
-}
+        let tmdb_server = MockServer::start().await;
+        let tmdb = TmdbClient::new_test("token".to_string(), tmdb_server.uri());
 
-/* ====== Кнопки ====== */
+        Mock::given(method("GET"))
+            .and(wiremock::matchers::path("/movie/999"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "id": 999, "title": "Fallback Movie",
+                "original_title": "Fallback Movie", "overview": "", "poster_path": null, "release_date": "2003-03-03"
+            })))
+            .mount(&tmdb_server)
+            .await;
 
-fn keyboard_add_results(results: &[MultiNorm]) -> InlineKeyboardMarkup {
-    // по 1 в строке
-    let mut rows = Vec::new();
-    let mut row = Vec::new();
-    for m in results {
-        let btn = InlineKeyboardButton::callback(
-            format!("➕ {}", one_line_title(m)),
-            format!("add:{}", m.id),
-        );
-        row.push(btn);
-        rows.push(row);
-        row = Vec::new();
-    }
-    if !row.is_empty() {
-        rows.push(row);
-    }
-    InlineKeyboardMarkup::new(rows)
-}
+        let storage_path = temp_store_path("tg_test_storage_fallback");
+        let storage = Storage::new(storage_path.clone()).await.unwrap();
 
-/* ====== Вспомогательные ====== */
+        let _ = LAST_SEARCH.invalidate(&(ChatId(888), 99)).await;
 
-fn one_line_title(m: &MultiNorm) -> String {
-    if let Some(y) = m.release_date.as_ref().and_then(|d| d.get(..4)) {
-        format!("{} ({})", m.title, y)
-    } else {
-        m.title.clone()
-    }
-}
+        let q1 = serde_json::from_value::<CallbackQuery>(serde_json::json!({
+            "id": "1", "from": {"id": 888, "is_bot": false, "first_name": "test"},
+            "chat_instance": "1", "data": "add:999:movie",
+            "message": {
+                "message_id": 99, "date": 1, "chat": {"id": 888, "type": "private"}, "text": "results 1"
+            }
+        })).unwrap();
+        on_callback(bot.clone(), q1, &tmdb, &storage).await.unwrap();
 
-fn make_block(m: &MultiNorm, overview_limit: usize) -> String {
-    let year = m
-        .release_date
-        .as_ref()
-        .and_then(|d| d.get(..4))
-        .unwrap_or("");
-    let title = html_escape(&m.title);
-    let body = if m.overview.trim().is_empty() {
-        "<i>нет описания</i>".to_string()
-    } else {
-        clip(&html_escape(&m.overview), overview_limit)
-    };
+        let stored = storage.get(888).await;
+        assert_eq!(stored.len(), 1);
+        assert_eq!(stored[0].title, "Fallback Movie");
 
-    if year.is_empty() {
-        format!("<b>{}</b>\n\n{}", title, body)
-    } else {
-        format!("<b>{}</b> ({})\n\n{}", title, year, body)
+        remove_temp_store(&storage_path);
     }
-}
 
-fn join_blocks(blocks: Vec<String>, limit_hint: usize) -> String {
-    // аккуратно собираем, не превышая limit_hint
-    let mut out = String::new();
-    for b in blocks {
-        let piece = if out.is_empty() {
-            b
-        } else {
-            format!("\n\n{}", b)
-        };
-        if out.chars().count() + piece.chars().count() > limit_hint {
-            // если не влезает — всё равно добавим, верхний слой потом порежет split_by_chars
-            out.push_str(&piece);
-            break;
-        } else {
-            out.push_str(&piece);
-        }
-    }
-    out
-}
+    #[tokio::test]
+    async fn test_album_falls_back_to_text_without_posters() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path_regex(".*Message"))
+            .and(wiremock::matchers::body_string_contains("Poster-less"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "ok": true,
+                "result": {
+                    "message_id": 1,
+                    "date": 1,
+                    "chat": {"id": 321, "type": "private", "first_name": "test"},
+                    "text": "posters"
+                }
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path_regex(".*MediaGroup"))
+            .respond_with(ResponseTemplate::new(500))
+            .expect(0)
+            .mount(&server)
+            .await;
 
-fn html_escape(s: &str) -> String {
-    s.replace('&', "&amp;")
-        .replace('<', "&lt;")
-        .replace('>', "&gt;")
-}
+        let bot = Bot::new("token").set_api_url(server.uri().parse().unwrap());
+        let movies = vec![StoredMovie {
+            id: 1,
+            title: "Poster-less".to_string(),
+            original_title: "Poster-less".to_string(),
+            media_type: MediaKind::Movie,
+            poster_path: None,
+            release_date: None,
+            watched: false,
+            vote_average: None,
+            custom_title: None,
+        }];
 
-fn clip(s: &str, max: usize) -> String {
-    if s.chars().count() <= max {
-        s.to_string()
-    } else {
-        s.chars().take(max).collect::<String>() + "…"
+        let tmdb = TmdbClient::new_test("token".to_string(), server.uri());
+        send_album_from_stored(&bot, ChatId(321), &tmdb, &movies, Some("<b>Постеры</b>"))
+            .await
+            .unwrap();
     }
-}
 
-fn split_by_chars(s: &str, max: usize) -> Vec<String> {
-    if s.chars().count() <= max {
-        return vec![s.to_string()];
-    }
-    let mut out = Vec::new();
-    let mut cur = String::new();
-    for ch in s.chars() {
-        if cur.chars().count() >= max {
-            out.push(cur);
-            cur = String::new();
-        }
-        cur.push(ch);
-    }
-    if !cur.is_empty() {
-        out.push(cur);
-    }
-    out
-}
+    #[tokio::test]
+    async fn test_album_skips_missing_posters_and_keeps_caption() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/w500/ok1.jpg"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header("content-type", "image/jpeg")
+                    .set_body_bytes(vec![1u8, 2, 3]),
+            )
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/w500/ok2.jpg"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header("content-type", "image/jpeg")
+                    .set_body_bytes(vec![4u8, 5, 6]),
+            )
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/w500/missing.jpg"))
+            .respond_with(ResponseTemplate::new(404))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path_regex(".*MediaGroup"))
+            .and(wiremock::matchers::body_string_contains("Постеры"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "ok": true,
+                "result": []
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
 
-async fn answer_cb<R>(bot: &R, q: &CallbackQuery, text: &str) -> ResponseResult<()>
-where
-    R: Requester<Err = RequestError>,
-{
-    bot.answer_callback_query(q.id.clone())
-        .text(text)
-        .show_alert(false)
-        .await?;
-    Ok(())
-}
+        let bot = Bot::new("token").set_api_url(server.uri().parse().unwrap());
+        let tmdb = TmdbClient::with_urls("token".to_string(), server.uri(), server.uri());
+        let movies: Vec<StoredMovie> = ["/missing.jpg", "/ok1.jpg", "/ok2.jpg"]
+            .iter()
+            .enumerate()
+            .map(|(i, p)| StoredMovie {
+                id: i as u64,
+                title: format!("Movie {}", i),
+                original_title: format!("Movie {}", i),
+                media_type: MediaKind::Movie,
+                poster_path: Some(p.to_string()),
+                release_date: None,
+                watched: false,
+                vote_average: None,
+                custom_title: None,
+            })
+            .collect();
 
-fn message_text_any(msg: &Message) -> Option<String> {
-    if let Some(t) = msg.text() {
-        return Some(t.to_string());
-    }
-    if let Some(c) = msg.caption() {
-        return Some(c.to_string());
+        send_album_from_stored(&bot, ChatId(322), &tmdb, &movies, Some("<b>Постеры</b>"))
+            .await
+            .unwrap();
     }
-    None
-}
 
-/* ====== Загрузка постера байтами (устойчиво к редиректам/CDN) ====== */
-async fn fetch_image(url: &str) -> Result<Vec<u8>, teloxide::RequestError> {
-    let client = reqwest::Client::builder()
-        .timeout(std::time::Duration::from_secs(15))
-        .user_agent("Mozilla/5.0 (compatible; tg-bot/1.0)")
-        .build()
-        .map_err(to_req_err)?;
-    let resp = client
-        .get(url)
-        .header(reqwest::header::ACCEPT, "image/*")
-        .send()
-        .await
-        .map_err(to_req_err)?;
-    if !resp.status().is_success() {
-        return Err(to_req_err(format!("status {}", resp.status())));
-    }
-    if let Some(ct) = resp.headers().get(reqwest::header::CONTENT_TYPE) {
-        let ct = ct.to_str().unwrap_or("");
-        if !ct.starts_with("image/") {
-            return Err(to_req_err(format!("unexpected content-type: {ct}")));
+    #[tokio::test]
+    async fn test_album_keeps_list_order_with_parallel_downloads() {
+        let server = MockServer::start().await;
+        // первый постер отдаётся дольше всех — в альбоме он всё равно первый
+        for (name, delay_ms) in [("slow", 300u64), ("mid", 100), ("fast", 0)] {
+            Mock::given(method("GET"))
+                .and(path(format!("/w500/{}.jpg", name)))
+                .respond_with(
+                    ResponseTemplate::new(200)
+                        .insert_header("content-type", "image/jpeg")
+                        .set_body_bytes(name.as_bytes().to_vec())
+                        .set_delay(std::time::Duration::from_millis(delay_ms)),
+                )
+                .mount(&server)
+                .await;
         }
-    }
-    let bytes = resp.bytes().await.map_err(to_req_err)?;
-    Ok(bytes.to_vec())
-}
+        Mock::given(method("POST"))
+            .and(path_regex(".*MediaGroup"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "ok": true,
+                "result": []
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
 
-fn to_req_err<E: std::fmt::Display>(e: E) -> teloxide::RequestError {
-    teloxide::RequestError::Io(std::sync::Arc::new(std::io::Error::other(e.to_string())))
-}
+        let bot = Bot::new("token").set_api_url(server.uri().parse().unwrap());
+        let tmdb = TmdbClient::with_urls("token".to_string(), server.uri(), server.uri());
+        let movies: Vec<StoredMovie> = ["/slow.jpg", "/mid.jpg", "/fast.jpg"]
+            .iter()
+            .enumerate()
+            .map(|(i, p)| StoredMovie {
+                id: i as u64,
+                title: format!("Movie {}", i),
+                original_title: format!("Movie {}", i),
+                media_type: MediaKind::Movie,
+                poster_path: Some(p.to_string()),
+                release_date: None,
+                watched: false,
+                vote_average: None,
+                custom_title: None,
+            })
+            .collect();
 
-fn one_line_title_stored(m: &StoredMovie) -> String {
-    if let Some(y) = m.release_date.as_ref().and_then(|d| d.get(..4)) {
-        format!("{} ({})", m.title, y)
-    } else {
-        m.title.clone()
+        send_album_from_stored(&bot, ChatId(325), &tmdb, &movies, None)
+            .await
+            .unwrap();
+
+        let requests = server.received_requests().await.unwrap();
+        let album = requests
+            .iter()
+            .find(|r| r.url.path().ends_with("MediaGroup"))
+            .unwrap();
+        let body = String::from_utf8_lossy(&album.body);
+        let pos = |name: &str| body.find(name).unwrap();
+        assert!(pos("poster_0.jpg") < pos("poster_1.jpg"));
+        assert!(pos("poster_1.jpg") < pos("poster_2.jpg"));
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::tmdb::MediaKind;
-    use std::path::PathBuf;
-    use wiremock::matchers::{method, path, path_regex};
-    use wiremock::{Mock, MockServer, ResponseTemplate};
+    #[tokio::test]
+    async fn test_album_error_falls_back_to_single_photos() {
+        let server = MockServer::start().await;
+        for name in ["a", "b", "c"] {
+            Mock::given(method("GET"))
+                .and(path(format!("/w500/{}.jpg", name)))
+                .respond_with(
+                    ResponseTemplate::new(200)
+                        .insert_header("content-type", "image/jpeg")
+                        .set_body_bytes(name.as_bytes().to_vec()),
+                )
+                .mount(&server)
+                .await;
+        }
+        let bad_request = serde_json::json!({
+            "ok": false,
+            "error_code": 400,
+            "description": "Bad Request: IMAGE_PROCESS_FAILED"
+        });
+        Mock::given(method("POST"))
+            .and(path_regex(".*MediaGroup"))
+            .respond_with(ResponseTemplate::new(400).set_body_json(bad_request.clone()))
+            .expect(1)
+            .mount(&server)
+            .await;
+        // первый постер битый, остальные два доходят по одному
+        Mock::given(method("POST"))
+            .and(path_regex(".*Photo"))
+            .respond_with(ResponseTemplate::new(400).set_body_json(bad_request))
+            .up_to_n_times(1)
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path_regex(".*Photo"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "ok": true,
+                "result": {
+                    "message_id": 61,
+                    "date": 1,
+                    "chat": {"id": 324, "type": "private", "first_name": "test"},
+                    "photo": []
+                }
+            })))
+            .expect(2)
+            .mount(&server)
+            .await;
 
-    #[test]
-    fn test_one_line_title() {
-        let m = MultiNorm {
-            id: 1,
-            media_type: MediaKind::Movie,
-            title: "Inception".to_string(),
-            original_title: "Inception".to_string(),
-            overview: "".to_string(),
-            release_date: Some("2010-07-16".to_string()),
-            image_path: None,
-        };
-        assert_eq!(one_line_title(&m), "Inception (2010)");
+        let bot = Bot::new("token").set_api_url(server.uri().parse().unwrap());
+        let tmdb = TmdbClient::with_urls("token".to_string(), server.uri(), server.uri());
+        let movies: Vec<StoredMovie> = ["/a.jpg", "/b.jpg", "/c.jpg"]
+            .iter()
+            .enumerate()
+            .map(|(i, p)| StoredMovie {
+                id: i as u64,
+                title: format!("Movie {}", i),
+                original_title: format!("Movie {}", i),
+                media_type: MediaKind::Movie,
+                poster_path: Some(p.to_string()),
+                release_date: None,
+                watched: false,
+                vote_average: None,
+                custom_title: None,
+            })
+            .collect();
+
+        send_album_from_stored(&bot, ChatId(324), &tmdb, &movies, None)
+            .await
+            .unwrap();
     }
 
-    #[test]
-    fn test_make_block() {
-        let m = MultiNorm {
-            id: 1,
+    #[tokio::test]
+    async fn test_send_poster_sends_only_image() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/w780/big.jpg"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header("content-type", "image/jpeg")
+                    .set_body_bytes(vec![9u8, 9, 9]),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path_regex(".*Photo"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "ok": true,
+                "result": {
+                    "message_id": 60,
+                    "date": 1,
+                    "chat": {"id": 323, "type": "private", "first_name": "test"},
+                    "photo": []
+                }
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path_regex(".*Message"))
+            .respond_with(ResponseTemplate::new(500))
+            .expect(0)
+            .mount(&server)
+            .await;
+
+        let bot = Bot::new("token").set_api_url(server.uri().parse().unwrap());
+        let tmdb = TmdbClient::with_urls("token".to_string(), server.uri(), server.uri());
+        let movie = StoredMovie {
+            id: 7,
+            title: "Big".to_string(),
+            original_title: "Big".to_string(),
             media_type: MediaKind::Movie,
-            title: "Inception".to_string(),
-            original_title: "Inception".to_string(),
-            overview: "A thief who steals corporate secrets...".to_string(),
-            release_date: Some("2010-07-16".to_string()),
-            image_path: None,
+            poster_path: Some("/big.jpg".to_string()),
+            release_date: None,
+            watched: false,
+            vote_average: None,
+            custom_title: None,
         };
-        let block = make_block(&m, 10);
-        assert!(block.contains("<b>Inception</b> (2010)"));
-        assert!(block.contains("A thief wh…"));
-    }
+        send_poster(&bot, ChatId(323), &tmdb, &movie).await.unwrap();
 
-    #[test]
-    fn test_html_escape() {
-        assert_eq!(html_escape("A & B < C > D"), "A &amp; B &lt; C &gt; D");
+        let requests = server.received_requests().await.unwrap();
+        let photo = requests
+            .iter()
+            .find(|r| r.url.path().ends_with("Photo"))
+            .unwrap();
+        assert!(!String::from_utf8_lossy(&photo.body).contains("caption"));
     }
 
     #[tokio::test]
-    async fn test_on_search_text_updates_last_search() {
+    async fn test_reply_with_number_adds_result() {
         let server = MockServer::start().await;
         Mock::given(method("POST"))
-            .and(path_regex(".*"))
+            .and(path_regex(".*Message"))
             .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
                 "ok": true,
                 "result": {
-                    "message_id": 1,
+                    "message_id": 50,
                     "date": 1,
-                    "chat": {"id": 123, "type": "private", "first_name": "test"},
-                    "text": "test"
+                    "chat": {"id": 999, "type": "private", "first_name": "test"},
+                    "text": "list"
                 }
             })))
             .mount(&server)
             .await;
 
         let bot = Bot::new("token").set_api_url(server.uri().parse().unwrap());
-
         let tmdb_server = MockServer::start().await;
         let tmdb = TmdbClient::new_test("token".to_string(), tmdb_server.uri());
 
-        let tmdb_response = serde_json::json!({
-            "page": 1,
-            "total_pages": 1,
-            "total_results": 1,
-            "results": [
-                {
-                    "media_type": "movie",
-                    "id": 1,
-                    "title": "Mock Movie",
-                    "original_title": "Mock Movie",
-                    "overview": "Overview",
-                    "poster_path": "/path.jpg",
-                    "release_date": "2023-01-01"
-                }
-            ]
-        });
-        Mock::given(method("GET"))
-            .and(path("/search/multi"))
-            .respond_with(ResponseTemplate::new(200).set_body_json(tmdb_response))
-            .mount(&tmdb_server)
-            .await;
+        let storage_path = temp_store_path("tg_test_storage_reply");
+        let storage = Storage::new(storage_path.clone()).await.unwrap();
 
-        let storage_path = PathBuf::from("tests/data/tg_test_storage.json");
-        let storage = Storage::new(storage_path).await.unwrap();
+        let results: Vec<MultiNorm> = (1..=3)
+            .map(|i| MultiNorm {
+                id: i,
+                media_type: MediaKind::Movie,
+                title: format!("Result {}", i),
+                original_title: format!("Result {}", i),
+                overview: "".to_string(),
+                release_date: None,
+                image_path: None,
+                vote_average: None,
+                vote_count: None,
+                runtime: None,
+                seasons: None,
+                tagline: None,
+                original_language: None,
+            })
+            .collect();
+        LAST_SEARCH.insert((ChatId(999), 41), results).await;
 
         let msg = serde_json::from_value::<Message>(serde_json::json!({
-            "message_id": 1,
-            "date": 1,
-            "chat": {"id": 123, "type": "private", "first_name": "test"},
-            "text": "test search"
-        })).unwrap();
-
+            "message_id": 42,
+            "date": 2,
+            "chat": {"id": 999, "type": "private", "first_name": "test"},
+            "text": "3",
+            "reply_to_message": {
+                "message_id": 41,
+                "date": 1,
+                "chat": {"id": 999, "type": "private", "first_name": "test"},
+                "text": "results"
+            }
+        }))
+        .unwrap();
         on_search_text(bot, msg, &tmdb, &storage).await.unwrap();
 
-        let results = LAST_SEARCH.get(&(ChatId(123), 1)).await.unwrap();
-        assert_eq!(results.len(), 1);
-        assert_eq!(results[0].title, "Mock Movie");
+        let stored = storage.get(999).await;
+        assert_eq!(stored.len(), 1);
+        assert_eq!(stored[0].title, "Result 3");
+        // в TMDb не ходили
+        assert!(tmdb_server.received_requests().await.unwrap().is_empty());
+
+        remove_temp_store(&storage_path);
     }
 
     #[tokio::test]
-    async fn test_full_flow_search_and_add() {
+    async fn test_remove_by_number() {
         let server = MockServer::start().await;
         Mock::given(method("POST"))
             .and(path_regex(".*Message"))
             .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
                 "ok": true,
                 "result": {
-                    "message_id": 1,
+                    "message_id": 62,
                     "date": 1,
-                    "chat": {"id": 456, "type": "private", "first_name": "test"},
-                    "text": "test"
+                    "chat": {"id": 803, "type": "private", "first_name": "test"},
+                    "text": "ok"
                 }
             })))
             .mount(&server)
             .await;
+        let bot = Bot::new("token").set_api_url(server.uri().parse().unwrap());
+        let tmdb = TmdbClient::new_test("token".to_string(), server.uri());
+        let storage_path = temp_store_path("tg_test_storage_remove");
+        let storage = Storage::new(storage_path.clone()).await.unwrap();
+        for id in 1..=3 {
+            let m = StoredMovie {
+                id,
+                title: format!("Movie {}", id),
+                original_title: format!("Movie {}", id),
+                media_type: MediaKind::Movie,
+                poster_path: None,
+                release_date: None,
+                watched: false,
+                vote_average: None,
+                custom_title: None,
+            };
+            storage.add_movie(803, m).await.unwrap();
+        }
+
+        let msg = |text: &str| {
+            serde_json::from_value::<Message>(serde_json::json!({
+                "message_id": 63,
+                "date": 2,
+                "chat": {"id": 803, "type": "private", "first_name": "test"},
+                "text": text
+            }))
+            .unwrap()
+        };
+        for arg in ["2", "7", "два"] {
+            let cmd = Command::Remove(arg.to_string());
+            on_command(
+                bot.clone(),
+                msg(&format!("/remove {}", arg)),
+                cmd,
+                &tmdb,
+                &storage,
+                false,
+                true,
+            )
+            .await
+            .unwrap();
+        }
+        // убран только второй; неверные номера список не трогают
+        let ids: Vec<u64> = storage.get(803).await.iter().map(|m| m.id).collect();
+        assert_eq!(ids, vec![1, 3]);
+
+        drop(storage);
+        remove_temp_store(&storage_path);
+    }
 
+    #[tokio::test]
+    async fn test_move_list_between_chats() {
+        let server = MockServer::start().await;
         Mock::given(method("POST"))
-            .and(path_regex(".*Query"))
+            .and(path_regex(".*Message"))
             .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
                 "ok": true,
-                "result": true
+                "result": {
+                    "message_id": 60,
+                    "date": 1,
+                    "chat": {"id": 802, "type": "private", "first_name": "test"},
+                    "text": "ok"
+                }
             })))
             .mount(&server)
             .await;
 
         let bot = Bot::new("token").set_api_url(server.uri().parse().unwrap());
+        let tmdb = TmdbClient::new_test("token".to_string(), server.uri());
 
-        let tmdb_server = MockServer::start().await;
-        let tmdb = TmdbClient::new_test("token".to_string(), tmdb_server.uri());
+        let storage_path = temp_store_path("tg_test_storage_move");
+        let storage = Storage::new(storage_path.clone()).await.unwrap();
+        for id in 1..=2 {
+            let m = StoredMovie {
+                id,
+                title: format!("Movie {}", id),
+                original_title: format!("Movie {}", id),
+                media_type: MediaKind::Movie,
+                poster_path: None,
+                release_date: None,
+                watched: false,
+                vote_average: None,
+                custom_title: None,
+            };
+            storage.add_movie(801, m).await.unwrap();
+        }
+
+        let code = issue_move_code(ChatId(801)).await;
+        let msg = serde_json::from_value::<Message>(serde_json::json!({
+            "message_id": 61,
+            "date": 2,
+            "chat": {"id": 802, "type": "private", "first_name": "test"},
+            "text": format!("/move {}", code.to_lowercase())
+        }))
+        .unwrap();
+        let cmd = Command::Move(code.to_lowercase());
+        on_command(
+            bot.clone(),
+            msg.clone(),
+            cmd.clone(),
+            &tmdb,
+            &storage,
+            false,
+            true,
+        )
+        .await
+        .unwrap();
+
+        let moved = storage.get(802).await;
+        assert_eq!(moved.len(), 2);
+        assert_eq!(moved[1].title, "Movie 2");
+        assert_eq!(storage.get(801).await.len(), 2);
+
+        // код одноразовый
+        storage.remove_chat(802).await.unwrap();
+        on_command(bot, msg, cmd, &tmdb, &storage, false, true)
+            .await
+            .unwrap();
+        assert!(storage.get(802).await.is_empty());
+
+        drop(storage);
+        remove_temp_store(&storage_path);
+    }
+
+    #[tokio::test]
+    async fn test_may_mutate_in_group() {
+        let server = MockServer::start().await;
+        let user_json = serde_json::json!({"id": 5, "is_bot": false, "first_name": "test"});
+        Mock::given(method("POST"))
+            .and(path_regex(".*ChatMember"))
+            .and(wiremock::matchers::body_partial_json(
+                serde_json::json!({"user_id": 5}),
+            ))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "ok": true,
+                "result": {"status": "member", "user": user_json}
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path_regex(".*ChatMember"))
+            .and(wiremock::matchers::body_partial_json(
+                serde_json::json!({"user_id": 6}),
+            ))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "ok": true,
+                "result": {
+                    "status": "creator",
+                    "user": {"id": 6, "is_bot": false, "first_name": "owner"},
+                    "is_anonymous": false
+                }
+            })))
+            .mount(&server)
+            .await;
+        let bot = Bot::new("token").set_api_url(server.uri().parse().unwrap());
+
+        let group = serde_json::from_value::<teloxide::types::Chat>(
+            serde_json::json!({"id": -100, "type": "supergroup", "title": "club"}),
+        )
+        .unwrap();
+        let private = serde_json::from_value::<teloxide::types::Chat>(
+            serde_json::json!({"id": 5, "type": "private", "first_name": "test"}),
+        )
+        .unwrap();
+        let user = |id: u64| teloxide::types::User {
+            id: UserId(id),
+            is_bot: false,
+            first_name: "test".to_string(),
+            last_name: None,
+            username: None,
+            language_code: None,
+            is_premium: false,
+            added_to_attachment_menu: false,
+        };
+
+        assert!(!may_mutate(&bot, &group, Some(&user(5)), true).await);
+        assert!(may_mutate(&bot, &group, Some(&user(6)), true).await);
+        assert!(may_mutate(&bot, &group, Some(&user(5)), false).await);
+        assert!(may_mutate(&bot, &private, Some(&user(5)), true).await);
+        assert!(!may_mutate(&bot, &group, None, true).await);
+
+        // кнопки: «➕» и ✅ от участника закрыты, показ карточки — нет
+        let callback = |from: u64, data: &str| {
+            serde_json::from_value::<CallbackQuery>(serde_json::json!({
+                "id": "1",
+                "from": {"id": from, "is_bot": false, "first_name": "test"},
+                "chat_instance": "ci",
+                "data": data,
+                "message": {
+                    "message_id": 10,
+                    "date": 1,
+                    "chat": {"id": -100, "type": "supergroup", "title": "club"},
+                    "text": "results"
+                }
+            }))
+            .unwrap()
+        };
+        assert!(!callback_may_mutate(&bot, &callback(5, "add:550"), true).await);
+        assert!(!callback_may_mutate(&bot, &callback(5, "addforce:550:movie"), true).await);
+        assert!(!callback_may_mutate(&bot, &callback(5, "watched:550:movie"), true).await);
+        assert!(callback_may_mutate(&bot, &callback(6, "add:550"), true).await);
+        assert!(callback_may_mutate(&bot, &callback(5, "show:550:movie"), true).await);
+        assert!(callback_may_mutate(&bot, &callback(5, "add:550"), false).await);
+    }
 
+    #[tokio::test]
+    async fn test_vote_details_skip_hanging_item() {
+        let tmdb_server = MockServer::start().await;
         Mock::given(method("GET"))
-            .and(wiremock::matchers::path("/search/multi"))
+            .and(path("/movie/1"))
             .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
-                "page": 1,
-                "total_pages": 1,
-                "total_results": 1,
-                "results": [
-                    {
-                        "media_type": "movie",
-                        "id": 456,
-                        "title": "Integration Movie",
-                        "original_title": "Integration Movie",
-                        "overview": "Integration Overview",
-                        "poster_path": "/int.jpg",
-                        "release_date": "2024-01-01"
-                    }
-                ]
+                "id": 1, "title": "Быстрый", "original_title": "Fast",
+                "overview": "ok", "poster_path": null, "release_date": "2001-01-01"
             })))
             .mount(&tmdb_server)
             .await;
+        Mock::given(method("GET"))
+            .and(path("/movie/2"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_delay(std::time::Duration::from_secs(10))
+                    .set_body_json(serde_json::json!({
+                        "id": 2, "title": "Зависший", "original_title": "Slow",
+                        "overview": "", "poster_path": null, "release_date": null
+                    })),
+            )
+            .mount(&tmdb_server)
+            .await;
+        let tmdb = TmdbClient::new_test("token".to_string(), tmdb_server.uri());
+        let list: Vec<StoredMovie> = [(1, "Быстрый"), (2, "Зависший")]
+            .iter()
+            .map(|(id, title)| StoredMovie {
+                id: *id,
+                title: title.to_string(),
+                original_title: title.to_string(),
+                media_type: MediaKind::Movie,
+                poster_path: None,
+                release_date: None,
+                watched: false,
+                vote_average: None,
+                custom_title: None,
+            })
+            .collect();
+
+        let started = std::time::Instant::now();
+        let (blocks, _) = vote_details(
+            &tmdb,
+            &list,
+            "RU",
+            "ru",
+            std::time::Duration::from_millis(300),
+        )
+        .await
+        .unwrap();
+        assert!(started.elapsed() < std::time::Duration::from_secs(3));
+        assert_eq!(blocks.len(), 2);
+        assert!(blocks[0].contains("Быстрый"));
+        assert!(blocks[1].contains("Зависший") && blocks[1].contains("детали недоступны"));
+    }
+
+    #[tokio::test]
+    async fn test_vote_details_parallel_keeps_order() {
+        let tmdb_server = MockServer::start().await;
+        for id in 1..=4u64 {
+            // первый отвечает дольше всех, но в выдаче должен остаться первым
+            let delay = if id == 1 { 700 } else { 400 };
+            Mock::given(method("GET"))
+                .and(path(format!("/movie/{}", id)))
+                .respond_with(
+                    ResponseTemplate::new(200)
+                        .set_delay(std::time::Duration::from_millis(delay))
+                        .set_body_json(serde_json::json!({
+                            "id": id, "title": format!("Фильм {}", id),
+                            "original_title": "x", "overview": "ok",
+                            "poster_path": null, "release_date": null
+                        })),
+                )
+                .mount(&tmdb_server)
+                .await;
+        }
+        let tmdb = TmdbClient::new_test("token".to_string(), tmdb_server.uri());
+        let list: Vec<StoredMovie> = (1..=4u64)
+            .map(|id| StoredMovie {
+                id,
+                title: format!("Фильм {}", id),
+                original_title: "x".to_string(),
+                media_type: MediaKind::Movie,
+                poster_path: None,
+                release_date: None,
+                watched: false,
+                vote_average: None,
+                custom_title: None,
+            })
+            .collect();
+
+        let started = std::time::Instant::now();
+        let (blocks, _) = vote_details(&tmdb, &list, "RU", "ru", std::time::Duration::from_secs(5))
+            .await
+            .unwrap();
+        // последовательно было бы ≥ 1.9 с
+        assert!(started.elapsed() < std::time::Duration::from_millis(1400));
+        let titles: Vec<bool> = (1..=4)
+            .map(|i| blocks[i - 1].contains(&format!("Фильм {}", i)))
+            .collect();
+        assert_eq!(titles, vec![true; 4]);
+    }
+
+    #[tokio::test]
+    async fn test_vote_without_poll_rights_still_sends_descriptions() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path_regex(".*Poll"))
+            .respond_with(ResponseTemplate::new(400).set_body_json(serde_json::json!({
+                "ok": false,
+                "error_code": 400,
+                "description": "Bad Request: not enough rights to send polls to the chat"
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path_regex(".*Message"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "ok": true,
+                "result": {
+                    "message_id": 70,
+                    "date": 1,
+                    "chat": {"id": -100, "type": "group", "title": "club"},
+                    "text": "ok"
+                }
+            })))
+            .mount(&server)
+            .await;
+        for id in 1..=2u64 {
+            Mock::given(method("GET"))
+                .and(path(format!("/movie/{}", id)))
+                .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                    "id": id, "title": format!("Описание {}", id), "original_title": "x",
+                    "overview": "ok", "poster_path": null, "release_date": null
+                })))
+                .mount(&server)
+                .await;
+        }
 
-        let storage_path = PathBuf::from("tests/data/integration_test_storage.json");
-        let _ = std::fs::remove_file(&storage_path);
+        let bot = Bot::new("token").set_api_url(server.uri().parse().unwrap());
+        let tmdb = TmdbClient::new_test("token".to_string(), server.uri());
+        let storage_path = temp_store_path("tg_test_storage_no_polls");
         let storage = Storage::new(storage_path.clone()).await.unwrap();
+        for id in 1..=2u64 {
+            let m = StoredMovie {
+                id,
+                title: format!("Фильм {}", id),
+                original_title: "x".to_string(),
+                media_type: MediaKind::Movie,
+                poster_path: None,
+                release_date: None,
+                watched: false,
+                vote_average: None,
+                custom_title: None,
+            };
+            storage.add_movie(-100, m).await.unwrap();
+        }
 
-        let search_msg = serde_json::from_value::<Message>(serde_json::json!({
-            "message_id": 1,
-            "date": 1,
-            "chat": {"id": 456, "type": "private", "first_name": "test"},
-            "text": "integration"
-        })).unwrap();
+        run_vote_flow(
+            &bot,
+            ChatId(-100),
+            &tmdb,
+            &storage,
+            false,
+            true,
+            PollKind::Regular,
+        )
+        .await
+        .unwrap();
 
-        on_search_text(bot.clone(), search_msg, &tmdb, &storage).await.unwrap();
+        let bodies: Vec<String> = server
+            .received_requests()
+            .await
+            .unwrap()
+            .iter()
+            .filter(|r| r.url.path().ends_with("Message"))
+            .map(|r| String::from_utf8_lossy(&r.body).into_owned())
+            .collect();
+        assert!(bodies
+            .iter()
+            .any(|b| b.contains("нет прав на создание опросов")));
+        assert!(bodies.iter().any(|b| b.contains("Описание 2")));
 
-        {
-            let results = LAST_SEARCH.get(&(ChatId(456), 1)).await.unwrap();
-            assert_eq!(results[0].id, 456);
+        drop(storage);
+        remove_temp_store(&storage_path);
+    }
+
+    #[tokio::test]
+    async fn test_vote_without_posters_sends_no_images() {
+        let server = MockServer::start().await;
+        let ok = ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "ok": true,
+            "result": {
+                "message_id": 71,
+                "date": 1,
+                "chat": {"id": -101, "type": "group", "title": "club"},
+                "text": "ok"
+            }
+        }));
+        Mock::given(method("POST"))
+            .and(path_regex(".*Poll"))
+            .respond_with(ok.clone())
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path_regex(".*Message"))
+            .respond_with(ok)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path_regex(".*(MediaGroup|Photo)"))
+            .respond_with(ResponseTemplate::new(500))
+            .expect(0)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path_regex("^/w500/"))
+            .respond_with(ResponseTemplate::new(404))
+            .expect(0)
+            .mount(&server)
+            .await;
+        for id in 1..=2u64 {
+            Mock::given(method("GET"))
+                .and(path(format!("/movie/{}", id)))
+                .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                    "id": id, "title": format!("Описание {}", id), "original_title": "x",
+                    "overview": "ok", "poster_path": format!("/p{}.jpg", id), "release_date": null
+                })))
+                .mount(&server)
+                .await;
+        }
+
+        let bot = Bot::new("token").set_api_url(server.uri().parse().unwrap());
+        let tmdb = TmdbClient::with_urls("token".to_string(), server.uri(), server.uri());
+        let storage_path = temp_store_path("tg_test_storage_no_posters");
+        let storage = Storage::new(storage_path.clone()).await.unwrap();
+        storage
+            .update_settings(-101, |s| s.posters = false)
+            .await
+            .unwrap();
+        for id in 1..=2u64 {
+            let m = StoredMovie {
+                id,
+                title: format!("Фильм {}", id),
+                original_title: "x".to_string(),
+                media_type: MediaKind::Movie,
+                poster_path: Some(format!("/p{}.jpg", id)),
+                release_date: None,
+                watched: false,
+                vote_average: None,
+                custom_title: None,
+            };
+            storage.add_movie(-101, m).await.unwrap();
+        }
+
+        run_vote_flow(
+            &bot,
+            ChatId(-101),
+            &tmdb,
+            &storage,
+            false,
+            true,
+            PollKind::Regular,
+        )
+        .await
+        .unwrap();
+
+        drop(storage);
+        remove_temp_store(&storage_path);
+    }
+
+    #[tokio::test]
+    async fn test_vote_auto_pin_without_rights_keeps_going() {
+        let server = MockServer::start().await;
+        let ok = ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "ok": true,
+            "result": {
+                "message_id": 73,
+                "date": 1,
+                "chat": {"id": -103, "type": "group", "title": "club"},
+                "text": "ok"
+            }
+        }));
+        Mock::given(method("POST"))
+            .and(path_regex(".*Poll"))
+            .respond_with(ok.clone())
+            .expect(1)
+            .mount(&server)
+            .await;
+        // раньше «.*Message», иначе PinChatMessage заберёт общий мок
+        Mock::given(method("POST"))
+            .and(path_regex(".*PinChatMessage"))
+            .respond_with(ResponseTemplate::new(400).set_body_json(serde_json::json!({
+                "ok": false,
+                "error_code": 400,
+                "description": "Bad Request: not enough rights to pin a message"
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path_regex(".*Message"))
+            .respond_with(ok)
+            .mount(&server)
+            .await;
+        for id in 1..=2u64 {
+            Mock::given(method("GET"))
+                .and(path(format!("/movie/{}", id)))
+                .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                    "id": id, "title": format!("Описание {}", id), "original_title": "x",
+                    "overview": "ok", "poster_path": null, "release_date": null
+                })))
+                .mount(&server)
+                .await;
+        }
+
+        let bot = Bot::new("token").set_api_url(server.uri().parse().unwrap());
+        let tmdb = TmdbClient::with_urls("token".to_string(), server.uri(), server.uri());
+        let storage_path = temp_store_path("tg_test_storage_auto_pin");
+        let storage = Storage::new(storage_path.clone()).await.unwrap();
+        storage
+            .update_settings(-103, |s| {
+                s.posters = false;
+                s.auto_pin = true;
+            })
+            .await
+            .unwrap();
+        for id in 1..=2u64 {
+            let m = StoredMovie {
+                id,
+                title: format!("Фильм {}", id),
+                original_title: "x".to_string(),
+                media_type: MediaKind::Movie,
+                poster_path: None,
+                release_date: None,
+                watched: false,
+                vote_average: None,
+                custom_title: None,
+            };
+            storage.add_movie(-103, m).await.unwrap();
+        }
+
+        run_vote_flow(
+            &bot,
+            ChatId(-103),
+            &tmdb,
+            &storage,
+            false,
+            true,
+            PollKind::Regular,
+        )
+        .await
+        .unwrap();
+
+        let bodies: Vec<String> = server
+            .received_requests()
+            .await
+            .unwrap()
+            .into_iter()
+            .filter(|r| r.url.path().ends_with("SendMessage"))
+            .map(|r| String::from_utf8_lossy(&r.body).into_owned())
+            .collect();
+        assert!(bodies.iter().any(|b| b.contains("Не могу закрепить опрос")));
+        assert!(bodies.iter().any(|b| b.contains("Описание 2")));
+
+        drop(storage);
+        remove_temp_store(&storage_path);
+    }
+
+    #[tokio::test]
+    async fn test_vote_resumes_after_failed_album() {
+        let server = MockServer::start().await;
+        let ok = ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "ok": true,
+            "result": {
+                "message_id": 72,
+                "date": 1,
+                "chat": {"id": 907, "type": "private", "first_name": "test"},
+                "text": "ok"
+            }
+        }));
+        let bad_request = ResponseTemplate::new(400).set_body_json(serde_json::json!({
+            "ok": false,
+            "error_code": 400,
+            "description": "Bad Request: IMAGE_PROCESS_FAILED"
+        }));
+        // опрос — ровно один раз на оба запуска
+        Mock::given(method("POST"))
+            .and(path_regex(".*Poll"))
+            .respond_with(ok.clone())
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path_regex(".*Message"))
+            .respond_with(ok)
+            .mount(&server)
+            .await;
+        // первый запуск: ни альбом, ни постеры по одному не уходят
+        Mock::given(method("POST"))
+            .and(path_regex(".*MediaGroup"))
+            .respond_with(bad_request.clone())
+            .up_to_n_times(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path_regex(".*Photo"))
+            .respond_with(bad_request)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path_regex(".*MediaGroup"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "ok": true,
+                "result": []
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+        for id in 1..=2u64 {
+            Mock::given(method("GET"))
+                .and(path(format!("/w500/resume{}.jpg", id)))
+                .respond_with(
+                    ResponseTemplate::new(200)
+                        .insert_header("content-type", "image/jpeg")
+                        .set_body_bytes(vec![id as u8]),
+                )
+                .mount(&server)
+                .await;
+            Mock::given(method("GET"))
+                .and(path(format!("/movie/{}", id)))
+                .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                    "id": id, "title": format!("Описание {}", id), "original_title": "x",
+                    "overview": "ok", "poster_path": null, "release_date": null
+                })))
+                .mount(&server)
+                .await;
         }
 
-        let q = serde_json::from_value::<CallbackQuery>(serde_json::json!({
-            "id": "1",
-            "from": {"id": 456, "is_bot": false, "first_name": "test"},
-            "chat_instance": "1",
-            "data": "add:456:movie",
-            "message": {
-                "message_id": 1,
-                "date": 2,
-                "chat": {"id": 456, "type": "private", "first_name": "test"},
-                "text": "results"
-            }
-        })).unwrap();
+        let bot = Bot::new("token").set_api_url(server.uri().parse().unwrap());
+        let tmdb = TmdbClient::with_urls("token".to_string(), server.uri(), server.uri());
+        let storage_path = temp_store_path("tg_test_storage_vote_resume");
+        let storage = Storage::new(storage_path.clone()).await.unwrap();
+        for id in 1..=2u64 {
+            let m = StoredMovie {
+                id,
+                title: format!("Фильм {}", id),
+                original_title: "x".to_string(),
+                media_type: MediaKind::Movie,
+                poster_path: Some(format!("/resume{}.jpg", id)),
+                release_date: None,
+                watched: false,
+                vote_average: None,
+                custom_title: None,
+            };
+            storage.add_movie(907, m).await.unwrap();
+        }
 
-        on_callback(bot, q, &tmdb, &storage).await.unwrap();
+        let vote = || {
+            run_vote_flow(
+                &bot,
+                ChatId(907),
+                &tmdb,
+                &storage,
+                false,
+                true,
+                PollKind::Regular,
+            )
+        };
+        assert!(vote().await.is_err());
+        let progress = VOTE_PROGRESS.get(&ChatId(907)).await.unwrap();
+        assert_eq!(progress.stage, VoteStage::PollSent);
 
-        let stored = storage.get(456).await;
-        assert_eq!(stored.len(), 1);
-        assert_eq!(stored[0].title, "Integration Movie");
+        vote().await.unwrap();
+        assert!(VOTE_PROGRESS.get(&ChatId(907)).await.is_none());
 
-        let _ = std::fs::remove_file(storage_path);
+        drop(storage);
+        remove_temp_store(&storage_path);
     }
 
     #[tokio::test]
-    async fn test_on_search_text_ignores_group_chats() {
+    async fn test_multiline_text_imports_each_line() {
         let server = MockServer::start().await;
         Mock::given(method("POST"))
-            .and(path_regex(".*"))
+            .and(path_regex(".*Message"))
             .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
                 "ok": true,
                 "result": {
-                    "message_id": 1,
+                    "message_id": 80,
                     "date": 1,
-                    "chat": {"id": -10012345, "type": "group", "title": "group"},
-                    "text": "test"
+                    "chat": {"id": 777, "type": "private", "first_name": "test"},
+                    "text": "ok"
                 }
             })))
             .mount(&server)
             .await;
-
-        let bot = Bot::new("token").set_api_url(server.uri().parse().unwrap());
-
-        let tmdb_server = MockServer::start().await;
-        let tmdb = TmdbClient::new_test("token".to_string(), tmdb_server.uri());
-
-        let tmdb_response = serde_json::json!({
-            "page": 1, "total_pages": 1, "total_results": 1,
-            "results": [{
-                "media_type": "movie", "id": 1, "title": "Mock Movie",
-                "original_title": "Mock Movie", "overview": "Overview",
-                "poster_path": "/path.jpg", "release_date": "2023-01-01"
-            }]
-        });
+        for (id, q) in [(1, "Матрица"), (2, "Начало"), (3, "Дюна")] {
+            Mock::given(method("GET"))
+                .and(path("/search/multi"))
+                .and(wiremock::matchers::query_param("query", q))
+                .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                    "page": 1, "total_pages": 1, "total_results": 1,
+                    "results": [{
+                        "media_type": "movie", "id": id, "title": q, "original_title": q,
+                        "overview": "", "poster_path": null, "release_date": "2000-01-01"
+                    }]
+                })))
+                .mount(&server)
+                .await;
+        }
         Mock::given(method("GET"))
             .and(path("/search/multi"))
-            .respond_with(ResponseTemplate::new(200).set_body_json(tmdb_response))
-            .mount(&tmdb_server)
+            .and(wiremock::matchers::query_param("query", "Нет такого"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "page": 1, "total_pages": 0, "total_results": 0, "results": []
+            })))
+            .mount(&server)
             .await;
 
-        let storage_path = PathBuf::from("tests/data/tg_test_storage_group.json");
+        let bot = Bot::new("token").set_api_url(server.uri().parse().unwrap());
+        let tmdb = TmdbClient::new_test("token".to_string(), server.uri());
+        let storage_path = temp_store_path("tg_test_storage_bulk");
         let storage = Storage::new(storage_path.clone()).await.unwrap();
 
         let msg = serde_json::from_value::<Message>(serde_json::json!({
-            "message_id": 1,
+            "message_id": 81,
             "date": 1,
-            "chat": {"id": -10012345, "type": "group", "title": "group"},
-            "text": "test search"
-        })).unwrap();
-
+            "chat": {"id": 777, "type": "private", "first_name": "test"},
+            "text": "Матрица\n Начало \n\nНет такого\nДюна\nМатрица"
+        }))
+        .unwrap();
         on_search_text(bot, msg, &tmdb, &storage).await.unwrap();
 
-        let results = LAST_SEARCH.get(&(ChatId(-10012345), 1)).await;
-        assert!(results.is_none());
-        
-        let _ = std::fs::remove_file(storage_path);
+        let ids: Vec<u64> = storage.get(777).await.iter().map(|m| m.id).collect();
+        assert_eq!(ids, vec![1, 2, 3]);
+        let summary = server
+            .received_requests()
+            .await
+            .unwrap()
+            .iter()
+            .map(|r| String::from_utf8_lossy(&r.body).into_owned())
+            .find(|b| b.contains("Импорт"))
+            .unwrap();
+        assert!(summary.contains("добавлено 3 из 5"));
+        assert!(summary.contains("Нет такого — не нашёл"));
+        assert!(summary.contains("уже в списке"));
+
+        drop(storage);
+        remove_temp_store(&storage_path);
     }
 
     #[tokio::test]
-    async fn test_multiple_searches_in_same_chat() {
+    async fn test_digest_hints_when_dm_is_closed() {
         let server = MockServer::start().await;
         Mock::given(method("POST"))
             .and(path_regex(".*Message"))
-            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
-                "ok": true,
-                "result": {
-                    "message_id": 12,
-                    "date": 1,
-                    "chat": {"id": 777, "type": "private", "first_name": "test"},
-                    "text": "results 1"
-                }
+            .and(wiremock::matchers::body_string_contains("\"chat_id\":55"))
+            .respond_with(ResponseTemplate::new(403).set_body_json(serde_json::json!({
+                "ok": false,
+                "error_code": 403,
+                "description": "Forbidden: bot can't initiate conversation with a user"
             })))
-            .up_to_n_times(2)
+            .expect(1)
             .mount(&server)
             .await;
-
         Mock::given(method("POST"))
             .and(path_regex(".*Message"))
             .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
                 "ok": true,
                 "result": {
-                    "message_id": 22,
-                    "date": 2,
-                    "chat": {"id": 777, "type": "private", "first_name": "test"},
-                    "text": "results 2"
+                    "message_id": 90,
+                    "date": 1,
+                    "chat": {"id": -200, "type": "group", "title": "club"},
+                    "text": "ok"
                 }
             })))
             .mount(&server)
             .await;
 
+        let bot = Bot::new("token").set_api_url(server.uri().parse().unwrap());
+        let storage_path = temp_store_path("tg_test_storage_digest");
+        let storage = Storage::new(storage_path.clone()).await.unwrap();
+        let chat = serde_json::from_value::<teloxide::types::Chat>(
+            serde_json::json!({"id": -200, "type": "group", "title": "club"}),
+        )
+        .unwrap();
+        let user = serde_json::from_value::<teloxide::types::User>(
+            serde_json::json!({"id": 55, "is_bot": false, "first_name": "u"}),
+        )
+        .unwrap();
+
+        send_digest(&bot, &chat, &user, &storage).await.unwrap();
+
+        let hint = server
+            .received_requests()
+            .await
+            .unwrap()
+            .iter()
+            .map(|r| String::from_utf8_lossy(&r.body).into_owned())
+            .any(|b| b.contains("-200") && b.contains("/start"));
+        assert!(hint);
+
+        drop(storage);
+        remove_temp_store(&storage_path);
+    }
+
+    #[tokio::test]
+    async fn test_again_repeats_last_query_with_page() {
+        let server = MockServer::start().await;
         Mock::given(method("POST"))
-            .and(path_regex(".*Query"))
+            .and(path_regex(".*Message"))
             .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
                 "ok": true,
-                "result": true
+                "result": {
+                    "message_id": 95,
+                    "date": 1,
+                    "chat": {"id": 903, "type": "private", "first_name": "test"},
+                    "text": "ok"
+                }
             })))
             .mount(&server)
             .await;
+        for page in ["1", "2"] {
+            Mock::given(method("GET"))
+                .and(path("/search/multi"))
+                .and(wiremock::matchers::query_param("query", "дюна"))
+                .and(wiremock::matchers::query_param("page", page))
+                .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                    "page": 1, "total_pages": 2, "total_results": 2,
+                    "results": [{
+                        "media_type": "movie", "id": 100 + page.parse::<u64>().unwrap(),
+                        "title": format!("Дюна {}", page), "original_title": "Dune",
+                        "overview": "", "poster_path": null, "release_date": null
+                    }]
+                })))
+                .expect(1)
+                .mount(&server)
+                .await;
+        }
 
         let bot = Bot::new("token").set_api_url(server.uri().parse().unwrap());
+        let tmdb = TmdbClient::new_test("token".to_string(), server.uri());
+        let storage_path = temp_store_path("tg_test_storage_again");
+        let storage = Storage::new(storage_path.clone()).await.unwrap();
+        let msg = |text: &str| {
+            serde_json::from_value::<Message>(serde_json::json!({
+                "message_id": 94,
+                "date": 1,
+                "chat": {"id": 903, "type": "private", "first_name": "test"},
+                "text": text
+            }))
+            .unwrap()
+        };
 
-        let tmdb_server = MockServer::start().await;
-        let tmdb = TmdbClient::new_test("token".to_string(), tmdb_server.uri());
-
-        Mock::given(method("GET"))
-            .and(wiremock::matchers::path("/search/multi"))
-            .and(wiremock::matchers::query_param("query", "movie1"))
-            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
-                "page": 1, "total_pages": 1, "total_results": 1,
-                "results": [{
-                    "media_type": "movie", "id": 100, "title": "Movie 1",
-                    "original_title": "Movie 1", "overview": "", "poster_path": null, "release_date": "2001-01-01"
-                }]
-            })))
-            .mount(&tmdb_server)
-            .await;
-
-        Mock::given(method("GET"))
-            .and(wiremock::matchers::path("/search/multi"))
-            .and(wiremock::matchers::query_param("query", "movie2"))
-            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
-                "page": 1, "total_pages": 1, "total_results": 1,
-                "results": [{
-                    "media_type": "movie", "id": 200, "title": "Movie 2",
-                    "original_title": "Movie 2", "overview": "", "poster_path": null, "release_date": "2002-02-02"
-                }]
-            })))
-            .mount(&tmdb_server)
-            .await;
+        on_search_text(bot.clone(), msg("дюна"), &tmdb, &storage)
+            .await
+            .unwrap();
+        let cmd = Command::Again("2".to_string());
+        on_command(bot, msg("/again 2"), cmd, &tmdb, &storage, false, true)
+            .await
+            .unwrap();
 
-        let storage_path = PathBuf::from("tests/data/tg_test_storage_multiple.json");
-        let _ = std::fs::remove_file(&storage_path);
-        let storage = Storage::new(storage_path.clone()).await.unwrap();
+        // оба сообщения бота — 95; последние результаты — со 2-й страницы
+        let results = LAST_SEARCH.get(&(ChatId(903), 95)).await.unwrap();
+        assert_eq!(results[0].id, 102);
 
-        let search_msg1 = serde_json::from_value::<Message>(serde_json::json!({
-            "message_id": 1, "date": 1, "chat": {"id": 777, "type": "private"}, "text": "movie1"
-        })).unwrap();
-        on_search_text(bot.clone(), search_msg1, &tmdb, &storage).await.unwrap();
+        drop(storage);
+        remove_temp_store(&storage_path);
+    }
 
-        let search_msg2 = serde_json::from_value::<Message>(serde_json::json!({
-            "message_id": 2, "date": 2, "chat": {"id": 777, "type": "private"}, "text": "movie2"
-        })).unwrap();
-        on_search_text(bot.clone(), search_msg2, &tmdb, &storage).await.unwrap();
+    #[test]
+    fn test_coming_soon_badge() {
+        let today = NaiveDate::from_ymd_opt(2025, 6, 1).unwrap();
+        assert_eq!(
+            coming_soon(Some("2025-12-19"), today).as_deref(),
+            Some("🔜 скоро (12.2025)")
+        );
+        assert_eq!(coming_soon(Some("2025-06-01"), today), None);
+        assert_eq!(coming_soon(Some("1999-03-31"), today), None);
+        assert_eq!(coming_soon(Some("2025"), today), None);
+        assert_eq!(coming_soon(Some("не дата"), today), None);
+        assert_eq!(coming_soon(None, today), None);
 
-        let q1 = serde_json::from_value::<CallbackQuery>(serde_json::json!({
-            "id": "1", "from": {"id": 777, "is_bot": false, "first_name": "test"},
-            "chat_instance": "1", "data": "add:100:movie",
-            "message": {
-                "message_id": 12, "date": 1, "chat": {"id": 777, "type": "private"}, "text": "results 1"
-            }
-        })).unwrap();
-        on_callback(bot.clone(), q1, &tmdb, &storage).await.unwrap();
+        let m = MultiNorm {
+            release_date: Some("2999-12-01".into()),
+            id: 2,
+            media_type: MediaKind::Movie,
+            title: "Фильм будущего".to_string(),
+            original_title: String::new(),
+            overview: String::new(),
+            image_path: None,
+            vote_average: None,
+            vote_count: None,
+            runtime: None,
+            seasons: None,
+            tagline: None,
+            original_language: None,
+        };
+        let block = make_block(&m, 100, Lang::Ru);
+        assert!(block.contains("🔜 скоро (12.2999)"), "{}", block);
+    }
 
-        let stored = storage.get(777).await;
-        assert_eq!(stored.len(), 1);
-        assert_eq!(stored[0].title, "Movie 1");
+    #[test]
+    fn test_clip_plain_fits_poll_limits() {
+        let long = format!("{} (2019)", "Очень длинное название ".repeat(10));
+        let option = clip_plain(&long, POLL_OPTION_LIMIT);
+        assert_eq!(option.chars().count(), POLL_OPTION_LIMIT);
+        assert!(option.ends_with('…'));
+        assert_eq!(clip_plain("Дюна (2021)", POLL_OPTION_LIMIT), "Дюна (2021)");
+        let custom = ChatSettings {
+            vote_question: Some("?".repeat(500)),
+            ..ChatSettings::default()
+        };
+        assert_eq!(poll_question(&custom).chars().count(), POLL_QUESTION_LIMIT);
+        assert!(
+            clip_plain(&"?".repeat(500), POLL_QUESTION_LIMIT)
+                .chars()
+                .count()
+                <= 300
+        );
+    }
 
-        let _ = std::fs::remove_file(storage_path);
+    #[test]
+    fn test_top_text_orders_by_rating() {
+        let movie = |id: u64, title: &str, rating: Option<f64>| StoredMovie {
+            id,
+            title: title.to_string(),
+            original_title: title.to_string(),
+            media_type: MediaKind::Movie,
+            poster_path: None,
+            release_date: None,
+            watched: false,
+            vote_average: rating,
+            custom_title: None,
+        };
+        let mut list = vec![
+            movie(1, "Середняк", Some(6.4)),
+            movie(2, "Без оценок", None),
+            movie(3, "Шедевр", Some(8.55)),
+        ];
+        sort_list(&mut list, SortBy::Rating);
+        let text = top_text(&list);
+        assert!(text.contains("1. Шедевр — ⭐ 8.6"), "{}", text);
+        assert!(text.contains("2. Середняк — ⭐ 6.4"));
+        assert!(text.contains("3. Без оценок — нет оценок"));
     }
 
     #[tokio::test]
-    async fn test_tmdb_fallback_on_cache_miss() {
+    async fn test_second_vote_hits_cooldown() {
         let server = MockServer::start().await;
+        let reply = |text: &str| {
+            ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "ok": true,
+                "result": {
+                    "message_id": 97,
+                    "date": 1,
+                    "chat": {"id": 904, "type": "private", "first_name": "test"},
+                    "text": text
+                }
+            }))
+        };
         Mock::given(method("POST"))
-            .and(path_regex(".*Query"))
-            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
-                "ok": true, "result": true
-            })))
+            .and(path_regex(".*Message"))
+            .and(wiremock::matchers::body_string_contains("минимум 2"))
+            .respond_with(reply("min"))
+            .expect(1)
             .mount(&server)
             .await;
-            
         Mock::given(method("POST"))
             .and(path_regex(".*Message"))
-            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
-                "ok": true,
-                "result": {
-                    "message_id": 2,
-                    "date": 2,
-                    "chat": {"id": 888, "type": "private", "first_name": "test"},
-                    "text": "results 2"
-                }
-            })))
+            .and(wiremock::matchers::body_string_contains("Подожди немного"))
+            .respond_with(reply("cooldown"))
+            .expect(1)
             .mount(&server)
             .await;
 
         let bot = Bot::new("token").set_api_url(server.uri().parse().unwrap());
+        let tmdb = TmdbClient::new_test("token".to_string(), server.uri());
+        let storage_path = temp_store_path("tg_test_storage_cooldown");
+        let storage = Storage::new(storage_path.clone()).await.unwrap();
+        let msg = serde_json::from_value::<Message>(serde_json::json!({
+            "message_id": 96,
+            "date": 1,
+            "chat": {"id": 904, "type": "private", "first_name": "test"},
+            "text": "/vote"
+        }))
+        .unwrap();
 
-        let tmdb_server = MockServer::start().await;
-        let tmdb = TmdbClient::new_test("token".to_string(), tmdb_server.uri());
+        for _ in 0..2 {
+            let cmd = Command::Vote(String::new());
+            on_command(bot.clone(), msg.clone(), cmd, &tmdb, &storage, false, true)
+                .await
+                .unwrap();
+        }
 
-        Mock::given(method("GET"))
-            .and(wiremock::matchers::path("/movie/999"))
+        drop(storage);
+        remove_temp_store(&storage_path);
+    }
+
+    #[tokio::test]
+    async fn test_report_storage_error() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path_regex(".*Message"))
+            .and(wiremock::matchers::body_string_contains(
+                "Не получилось сохранить",
+            ))
             .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
-                "id": 999, "title": "Fallback Movie",
-                "original_title": "Fallback Movie", "overview": "", "poster_path": null, "release_date": "2003-03-03"
+                "ok": true,
+                "result": {
+                    "message_id": 98,
+                    "date": 1,
+                    "chat": {"id": 905, "type": "private", "first_name": "test"},
+                    "text": "err"
+                }
             })))
-            .mount(&tmdb_server)
+            .expect(1)
+            .mount(&server)
             .await;
+        let bot = Bot::new("token").set_api_url(server.uri().parse().unwrap());
 
-        let storage_path = PathBuf::from("tests/data/tg_test_storage_fallback.json");
-        let _ = std::fs::remove_file(&storage_path);
-        let storage = Storage::new(storage_path.clone()).await.unwrap();
-
-        let _ = LAST_SEARCH.invalidate(&(ChatId(888), 99)).await;
-
-        let q1 = serde_json::from_value::<CallbackQuery>(serde_json::json!({
-            "id": "1", "from": {"id": 888, "is_bot": false, "first_name": "test"},
-            "chat_instance": "1", "data": "add:999:movie",
-            "message": {
-                "message_id": 99, "date": 1, "chat": {"id": 888, "type": "private"}, "text": "results 1"
-            }
-        })).unwrap();
-        on_callback(bot.clone(), q1, &tmdb, &storage).await.unwrap();
-
-        let stored = storage.get(888).await;
-        assert_eq!(stored.len(), 1);
-        assert_eq!(stored[0].title, "Fallback Movie");
+        let err: BotError = anyhow::anyhow!("disk full").into();
+        assert!(matches!(err, BotError::Storage(_)));
+        assert_eq!(err.to_string(), "хранилище: disk full");
+        report(&bot, Some(ChatId(905)), Err(err)).await.unwrap();
 
-        let _ = std::fs::remove_file(storage_path);
+        // ошибки Telegram только логируем — повторная отправка всё равно не дойдёт
+        let tg = BotError::from(RequestError::Api(teloxide::ApiError::BotBlocked));
+        report(&bot, Some(ChatId(905)), Err(tg)).await.unwrap();
     }
-}
 
-fn keyboard_list_two_columns_stored(list: &[StoredMovie]) -> InlineKeyboardMarkup {
-    let mut rows = Vec::new();
-    for m in list {
-        let show = InlineKeyboardButton::callback(
-            format!("🎬 {}", one_line_title_stored(m)),
-            format!("show:{}:{}", m.id, m.media_type.as_str()),
-        );
-        let del = InlineKeyboardButton::callback(
-            "🗑".to_string(),
-            format!("del:{}:{}", m.id, m.media_type.as_str()),
+    #[test]
+    fn test_poll_option_labels_are_unique() {
+        let item = |id: u64, media_type: MediaKind, title: &str| StoredMovie {
+            id,
+            title: title.to_string(),
+            original_title: title.to_string(),
+            media_type,
+            poster_path: None,
+            release_date: None,
+            watched: false,
+            vote_average: None,
+            custom_title: None,
+        };
+        let list = vec![
+            item(1, MediaKind::Movie, "Дюна"),
+            item(2, MediaKind::Tv, "Дюна"),
+            item(3, MediaKind::Movie, "Дюна"),
+            item(4, MediaKind::Movie, "Солярис"),
+        ];
+        let labels = poll_option_labels(&list, false);
+        assert_eq!(
+            labels,
+            vec![
+                "Дюна · фильм #1",
+                "Дюна · сериал",
+                "Дюна · фильм #3",
+                "Солярис"
+            ]
         );
-        rows.push(vec![show, del]);
-    }
-    InlineKeyboardMarkup::new(rows)
-}
 
-// отправка альбома из StoredMovie (постеры — по байтам)
-async fn send_album_from_stored<R>(
-    bot: &R,
-    chat_id: ChatId,
-    movies: &[StoredMovie],
-    common_caption_html: Option<&str>,
-) -> Result<(), teloxide::RequestError>
-where
-    R: Requester<Err = RequestError>,
-{
-    let mut media: Vec<InputMedia> = Vec::new();
-    for (i, m) in movies.iter().take(10).enumerate() {
-        if let Some(p) = &m.poster_path {
-            let url = format!("https://image.tmdb.org/t/p/w500{}", p);
-            if let Ok(bytes) = fetch_image(&url).await {
-                let file = InputFile::memory(bytes).file_name(format!("poster_{i}.jpg"));
-                if i == 0 {
-                    let mut first = InputMediaPhoto::new(file);
-                    if let Some(c) = common_caption_html {
-                        first.caption = Some(clip(c, 1024));
-                        first.show_caption_above_media = true;
-                        first.parse_mode = Some(ParseMode::Html);
-                    }
-                    media.push(InputMedia::Photo(first));
-                } else {
-                    media.push(InputMedia::Photo(
-                        InputMediaPhoto::new(file).show_caption_above_media(true),
-                    ));
-                }
-            }
-        }
-    }
-    if !media.is_empty() {
-        bot.send_media_group(chat_id, media).await?;
+        let long = "Я".repeat(150);
+        let labels = poll_option_labels(
+            &[
+                item(5, MediaKind::Movie, &long),
+                item(6, MediaKind::Movie, &long),
+            ],
+            false,
+        );
+        assert!(labels[0].ends_with("… · фильм #1"));
+        assert!(labels[1].ends_with("… · фильм #2"));
+        assert!(labels
+            .iter()
+            .all(|l| l.chars().count() == POLL_OPTION_LIMIT));
     }
-    Ok(())
 }
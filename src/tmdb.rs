@@ -1,7 +1,7 @@
 use reqwest::{Client, StatusCode};
 use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
-use std::cmp::PartialEq;
+use std::cmp::{PartialEq, Reverse};
 use thiserror::Error;
 use tokio::time::{sleep, Duration};
 
@@ -37,11 +37,76 @@ impl TmdbErr {
     }
 }
 
+const DEFAULT_BASE_URL: &str = "https://api.themoviedb.org/3";
+const DEFAULT_IMAGE_BASE_URL: &str = "https://image.tmdb.org/t/p";
+const DEFAULT_TIMEOUT_SECS: u64 = 12;
+
+/// Фильтры поиска из настроек чата.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SearchFilter {
+    /// include_adult в запросе к TMDb
+    pub include_adult: bool,
+    /// меньше голосов — прячем, если есть что-то известнее
+    pub min_votes: u64,
+}
+
+/// Размеры картинок TMDb (сегмент пути после IMAGE_BASE_URL).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageSize {
+    /// для альбомов и карточек
+    W500,
+    /// отдельный постер покрупнее
+    W780,
+}
+
+impl ImageSize {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ImageSize::W500 => "w500",
+            ImageSize::W780 => "w780",
+        }
+    }
+}
+
+/* ====== Авторизация ======
+   v4 read access token (JWT, «eyJ…») — заголовком Bearer, классический v3 ключ
+   (32 hex-символа) — параметром api_key; TMDB_AUTH=bearer|api_key — принудительно */
+#[derive(Clone, Debug, PartialEq)]
+pub enum TmdbAuth {
+    Bearer(String),
+    ApiKey(String),
+}
+
+impl TmdbAuth {
+    pub fn detect(token: String) -> Self {
+        if token.len() == 32 && token.chars().all(|c| c.is_ascii_hexdigit()) {
+            TmdbAuth::ApiKey(token)
+        } else {
+            TmdbAuth::Bearer(token)
+        }
+    }
+
+    fn from_style(token: String, style: Option<&str>) -> Self {
+        match style.map(|s| s.trim().to_lowercase()).as_deref() {
+            Some("bearer") => TmdbAuth::Bearer(token),
+            Some("api_key") => TmdbAuth::ApiKey(token),
+            Some("") | None => TmdbAuth::detect(token),
+            Some(other) => {
+                tracing::warn!("TMDB_AUTH={} ignored: expected bearer or api_key", other);
+                TmdbAuth::detect(token)
+            }
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct TmdbClient {
-    bearer_token: String,
+    auth: TmdbAuth,
     http: Client,
     base_url: String,
+    image_base_url: String,
+    /// TMDB_DEBUG: каждый запрос (без ключа) и ответ — в лог на уровне debug
+    debug: bool,
 }
 
 impl PartialEq for MediaKind {
@@ -55,78 +120,110 @@ impl PartialEq for MediaKind {
     }
 }
 
+// пустая переменная окружения (`IMAGE_BASE_URL=` в .env) — как незаданная,
+// иначе все картинки уйдут на относительный путь «/w500/…»
+fn url_or_default(value: Option<String>, default: &str) -> String {
+    value
+        .map(|v| v.trim().to_string())
+        .filter(|v| !v.is_empty())
+        .unwrap_or_else(|| default.to_string())
+}
+
 impl TmdbClient {
-    pub fn new(bearer_token: String) -> Self {
-        let http = Client::builder()
-            .timeout(Duration::from_secs(12))
-            .user_agent("tg-movie-bot/1.0 (+teloxide)")
-            .build()
-            .expect("reqwest client");
+    /// Хосты берутся из TMDB_BASE_URL / IMAGE_BASE_URL, иначе — настоящие TMDb;
+    /// таймаут запроса — TMDB_TIMEOUT_SECS; вид ключа — TMDB_AUTH или по виду токена;
+    /// TMDB_DEBUG=1 — журнал запросов.
+    pub fn new(token: String) -> Self {
+        let base_url = url_or_default(std::env::var("TMDB_BASE_URL").ok(), DEFAULT_BASE_URL);
+        let image_base_url =
+            url_or_default(std::env::var("IMAGE_BASE_URL").ok(), DEFAULT_IMAGE_BASE_URL);
+        let timeout = crate::config::env_secs("TMDB_TIMEOUT_SECS", DEFAULT_TIMEOUT_SECS);
+        let auth = TmdbAuth::from_style(token.clone(), std::env::var("TMDB_AUTH").ok().as_deref());
+        let debug = std::env::var("TMDB_DEBUG")
+            .map(|v| matches!(v.trim(), "1" | "true" | "yes"))
+            .unwrap_or(false);
         Self {
-            bearer_token,
-            http,
-            base_url: "https://api.themoviedb.org/3".to_string(),
+            auth,
+            debug,
+            ..Self::with_urls(token, base_url, image_base_url)
         }
+        .with_timeout(timeout)
     }
 
-    #[cfg(test)]
-    pub fn new_test(bearer_token: String, base_url: String) -> Self {
-        let http = Client::builder()
-            .timeout(Duration::from_secs(12))
-            .user_agent("tg-movie-bot/1.0 (+teloxide)")
-            .build()
-            .expect("reqwest client");
+    /// Клиент с другими хостами API/картинок (прокси, зеркало, мок-сервер).
+    pub fn with_urls(token: String, base_url: String, image_base_url: String) -> Self {
         Self {
-            bearer_token,
-            http,
-            base_url,
+            auth: TmdbAuth::detect(token),
+            http: http_client(Duration::from_secs(DEFAULT_TIMEOUT_SECS)),
+            base_url: base_url.trim_end_matches('/').to_string(),
+            image_base_url: image_base_url.trim_end_matches('/').to_string(),
+            debug: false,
         }
     }
 
+    /// Другой таймаут на запрос к API (медленные каналы / жёсткие требования к задержке).
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.http = http_client(timeout);
+        self
+    }
+
+    #[cfg(test)]
+    pub fn new_test(token: String, base_url: String) -> Self {
+        Self::with_urls(token, base_url, DEFAULT_IMAGE_BASE_URL.to_string())
+    }
+
+    /// Полный URL постера w500 по file_path из TMDb.
+    pub fn image_url(&self, file_path: &str) -> String {
+        self.image_url_sized(file_path, ImageSize::W500)
+    }
+
+    /// Полный URL картинки нужного размера по file_path из TMDb.
+    pub fn image_url_sized(&self, file_path: &str, size: ImageSize) -> String {
+        format!("{}/{}{}", self.image_base_url, size.as_str(), file_path)
+    }
+
     // Обобщённая загрузка + JSON с ретраями (для 5xx/429/сетевых)
     async fn get_json<T: DeserializeOwned>(&self, url: &str) -> Result<T, TmdbErr> {
-        // 3 попытки, бэкофф 300/800/1500 мс
-        let mut delays = [300u64, 800, 1500].into_iter();
-        loop {
-            let req = self.http.get(url).bearer_auth(&self.bearer_token); // 👈 тут
-            let resp = match req.send().await {
-                Ok(r) => r,
-                Err(_) => {
-                    if let Some(ms) = delays.next() {
-                        sleep(Duration::from_millis(ms)).await;
-                        continue;
-                    } else {
-                        return Err(TmdbErr::Net);
-                    }
-                }
-            };
+        with_backoff(
+            || self.try_get_json(url),
+            |ms| self.trace(url, format_args!("повтор через {} мс", ms)),
+        )
+        .await
+    }
 
-            match resp.status() {
-                StatusCode::OK => {
-                    let v = resp.json::<T>().await.map_err(|_| TmdbErr::Net)?;
-                    return Ok(v);
-                }
-                StatusCode::TOO_MANY_REQUESTS => {
-                    if let Some(ms) = delays.next() {
-                        sleep(Duration::from_millis(ms)).await;
-                        continue;
-                    } else {
-                        return Err(TmdbErr::RateLimited);
-                    }
-                }
-                StatusCode::UNAUTHORIZED => return Err(TmdbErr::Auth),
-                StatusCode::FORBIDDEN => return Err(TmdbErr::Forbidden),
-                StatusCode::NOT_FOUND => return Err(TmdbErr::NotFound),
-                s if s.is_server_error() => {
-                    if let Some(ms) = delays.next() {
-                        sleep(Duration::from_millis(ms)).await;
-                        continue;
-                    } else {
-                        return Err(TmdbErr::Server(s.as_u16()));
-                    }
-                }
-                s => return Err(TmdbErr::Unexpected(s.as_u16())),
+    // одна попытка: что вернуть и можно ли повторить
+    async fn try_get_json<T: DeserializeOwned>(&self, url: &str) -> Attempt<T> {
+        let req = match &self.auth {
+            TmdbAuth::Bearer(token) => self.http.get(url).bearer_auth(token),
+            TmdbAuth::ApiKey(key) => self.http.get(url).query(&[("api_key", key)]),
+        };
+        let resp = match req.send().await {
+            Ok(r) => r,
+            Err(e) => {
+                // без URL: в нём может оказаться api_key
+                self.trace(url, format_args!("{}", e.without_url()));
+                return Attempt::Retry(TmdbErr::Net, None);
             }
+        };
+
+        self.trace(url, format_args!("{}", resp.status().as_u16()));
+        match resp.status() {
+            StatusCode::OK => Attempt::Done(resp.json::<T>().await.map_err(|_| TmdbErr::Net)),
+            StatusCode::TOO_MANY_REQUESTS => {
+                Attempt::Retry(TmdbErr::RateLimited, retry_after_secs(&resp))
+            }
+            StatusCode::UNAUTHORIZED => Attempt::Done(Err(TmdbErr::Auth)),
+            StatusCode::FORBIDDEN => Attempt::Done(Err(TmdbErr::Forbidden)),
+            StatusCode::NOT_FOUND => Attempt::Done(Err(TmdbErr::NotFound)),
+            s if s.is_server_error() => Attempt::Retry(TmdbErr::Server(s.as_u16()), None),
+            s => Attempt::Done(Err(TmdbErr::Unexpected(s.as_u16()))),
+        }
+    }
+
+    // строка журнала TMDB_DEBUG: «GET /search/multi?... -> 200»
+    fn trace(&self, url: &str, what: std::fmt::Arguments) {
+        if self.debug {
+            tracing::debug!("GET {} -> {}", redact_url(url, &self.base_url), what);
         }
     }
 
@@ -135,16 +232,48 @@ impl TmdbClient {
         &self,
         query: &str,
         limit: usize,
+        filter: SearchFilter,
+    ) -> Result<Vec<MultiNorm>, TmdbErr> {
+        self.search_movies_page(query, limit, filter, 1).await
+    }
+
+    /// То же, что search_movies_ru, но со страницы `page` выдачи TMDb.
+    pub async fn search_movies_page(
+        &self,
+        query: &str,
+        limit: usize,
+        filter: SearchFilter,
+        page: u32,
     ) -> Result<Vec<MultiNorm>, TmdbErr> {
+        Ok(self
+            .search_with_person(query, limit, filter, page)
+            .await?
+            .items)
+    }
+
+    /// Поиск как search_movies_page, плюс персона, если она — первый результат TMDb.
+    pub async fn search_with_person(
+        &self,
+        query: &str,
+        limit: usize,
+        filter: SearchFilter,
+        page: u32,
+    ) -> Result<SearchHits, TmdbErr> {
         let url = format!(
-            "{}/search/multi?query={}&language=ru-RU&include_adult=false&page=1",
+            "{}/search/multi?query={}&language=ru-RU&include_adult={}&page={}",
             self.base_url,
-            urlencoding::encode(query)
+            urlencoding::encode(query),
+            filter.include_adult,
+            page
         );
 
         let data: SearchResp<SearchMultiDto> = self.get_json(&url).await?;
+        let person = match data.results.first() {
+            Some(p @ SearchMultiDto::Person { .. }) => Some(MultiNorm::from(p.clone())),
+            _ => None,
+        };
 
-        let items = data
+        let items: Vec<MultiNorm> = data
             .results
             .into_iter()
             .filter(|item| {
@@ -154,10 +283,78 @@ impl TmdbClient {
                 )
             })
             .map(Into::into) // -> MultiNorm
-            .take(limit)
             .collect();
 
-        Ok(items)
+        // multi нашёл только людей — фильмы могут найтись обычным поиском фильмов
+        let items = if items.is_empty() && data.total_results > 0 {
+            let url = format!(
+                "{}/search/movie?query={}&language=ru-RU&include_adult={}&page={}",
+                self.base_url,
+                urlencoding::encode(query),
+                filter.include_adult,
+                page
+            );
+            let data: SearchResp<MovieDetailsDto> = self.get_json(&url).await?;
+            data.results.into_iter().map(Into::into).collect()
+        } else {
+            items
+        };
+
+        // малоизвестное с парой голосов не загораживает известное;
+        // если известного нет вовсе — отдаём как есть
+        let known: Vec<MultiNorm> = items
+            .iter()
+            .filter(|m| m.vote_count.unwrap_or(0) >= filter.min_votes)
+            .cloned()
+            .collect();
+        let items = if known.is_empty() { items } else { known };
+
+        Ok(SearchHits {
+            items: items.into_iter().take(limit).collect(),
+            person,
+        })
+    }
+
+    /// Имя персоны и её фильмы/сериалы (роли и работа в команде), самые известные — первыми.
+    pub async fn person_filmography(
+        &self,
+        person_id: u64,
+    ) -> Result<(String, Vec<MultiNorm>), TmdbErr> {
+        let url = format!(
+            "{}/person/{}?language=ru-RU&append_to_response=combined_credits",
+            self.base_url, person_id
+        );
+        let data: PersonDto = self.get_json(&url).await?;
+        let mut films: Vec<MultiNorm> = Vec::new();
+        let credits = data.combined_credits;
+        for m in credits
+            .cast
+            .into_iter()
+            .chain(credits.crew)
+            .map(MultiNorm::from)
+        {
+            // одна роль — одна строка в credits; фильм с двумя ролями не дублируем
+            if !films
+                .iter()
+                .any(|f| f.id == m.id && f.media_type == m.media_type)
+            {
+                films.push(m);
+            }
+        }
+        films.sort_by_key(|m| Reverse(m.vote_count.unwrap_or(0)));
+        films.truncate(MAX_FILMOGRAPHY);
+        Ok((data.name, films))
+    }
+
+    /// Популярные фильмы (RU) со страницы `page` /discover/movie — для «сюрприза».
+    pub async fn discover_popular(&self, page: u32) -> Result<Vec<MultiNorm>, TmdbErr> {
+        let url = format!(
+            "{}/discover/movie?language=ru-RU&sort_by=popularity.desc&include_adult=false&vote_count.gte=200&page={}",
+            self.base_url, page
+        );
+        // у discover те же поля, что у деталей фильма (кроме runtime)
+        let data: SearchResp<MovieDetailsDto> = self.get_json(&url).await?;
+        Ok(data.results.into_iter().map(Into::into).collect())
     }
 
     /// Детали фильма (RU) — чтобы «показать описание и постер» в списке.
@@ -189,59 +386,346 @@ impl TmdbClient {
         Ok(Some(res))
     }
 
-    /// Лучший трейлер (YouTube), RU→EN
-    pub async fn best_trailer_url(&self, video: MultiNorm) -> Result<Option<String>, TmdbErr> {
-        let mut all: Vec<Video> = Vec::new();
-        let mut any_ok = false;
-        let mut last_err: Option<TmdbErr> = None;
+    /// Альтернативные постеры (file_path), без дублей, RU/EN/без текста.
+    pub async fn images(&self, item: &MultiNorm) -> Result<Vec<String>, TmdbErr> {
+        let section = match item.media_type {
+            MediaKind::Movie => "movie",
+            MediaKind::Tv => "tv",
+            MediaKind::Person => return Ok(Vec::new()),
+        };
+        let url = format!(
+            "{}/{}/{}/images?include_image_language=ru,en,null",
+            self.base_url, section, item.id
+        );
+
+        let data: ImagesResp = self.get_json(&url).await?;
 
-        let section = match video.media_type {
+        let mut out: Vec<String> = Vec::new();
+        for img in data.posters {
+            if !out.contains(&img.file_path) {
+                out.push(img.file_path);
+            }
+        }
+        Ok(out)
+    }
+
+    /// Ключевые слова TMDb (тематические теги) в порядке TMDb.
+    pub async fn keywords(&self, item: &MultiNorm) -> Result<Vec<String>, TmdbErr> {
+        let section = match item.media_type {
             MediaKind::Movie => "movie",
             MediaKind::Tv => "tv",
-            MediaKind::Person => return Ok(None), // у персоны нет трейлеров
+            MediaKind::Person => return Ok(Vec::new()),
         };
-        for lang in ["ru-RU", "en-US"] {
-            let url = format!(
-                "{}/{}/{}/videos?language={}",
-                self.base_url, section, video.id, lang
-            );
+        let url = format!("{}/{}/{}/keywords", self.base_url, section, item.id);
+        let data: KeywordsResp = self.get_json(&url).await?;
+        Ok(data.keywords.into_iter().map(|k| k.name).collect())
+    }
 
-            match self.get_json::<VideosResp>(&url).await {
-                Ok(mut v) => {
-                    any_ok = true;
-                    all.append(&mut v.results);
-                }
-                Err(e) => {
-                    // запомним ошибку, но попробуем следующий язык
-                    last_err = Some(e);
+    /// Серии сезона `season` сериала `tv_id` (в порядке номеров).
+    pub async fn season(&self, tv_id: u64, season: u32) -> Result<Season, TmdbErr> {
+        let url = format!(
+            "{}/tv/{}/season/{}?language=ru-RU",
+            self.base_url, tv_id, season
+        );
+        let mut data: Season = self.get_json(&url).await?;
+        data.episodes.sort_by_key(|e| e.episode_number);
+        Ok(data)
+    }
+
+    /// Названия жанров TMDb для фильмов или сериалов на языке `lang`.
+    pub async fn genres(&self, kind: MediaKind, lang: &str) -> Result<Vec<String>, TmdbErr> {
+        let section = match kind {
+            MediaKind::Movie => "movie",
+            MediaKind::Tv => "tv",
+            MediaKind::Person => return Ok(Vec::new()),
+        };
+        let url = format!("{}/genre/{}/list?language={}", self.base_url, section, lang);
+        let data: GenresResp = self.get_json(&url).await?;
+        Ok(data.genres.into_iter().map(|g| g.name).collect())
+    }
+
+    /// Где смотреть в регионе (подписка/аренда/покупка). None — TMDb ничего не знает.
+    pub async fn watch_providers(
+        &self,
+        item: &MultiNorm,
+        region: &str,
+    ) -> Result<Option<WatchProviders>, TmdbErr> {
+        let section = match item.media_type {
+            MediaKind::Movie => "movie",
+            MediaKind::Tv => "tv",
+            MediaKind::Person => return Ok(None),
+        };
+        let url = format!("{}/{}/{}/watch/providers", self.base_url, section, item.id);
+
+        let data: WatchProvidersResp = self.get_json(&url).await?;
+        Ok(region_providers(data, region))
+    }
+
+    /// Всё для карточки одним запросом (append_to_response): детали, трейлер,
+    /// IMDb id, рейтинг и «где смотреть» в регионе, первые актёры.
+    /// Трейлер (YouTube): язык чата → язык оригинала → EN; ролики
+    /// оригинала догружаются вторым запросом, только если на языке чата ничего нет.
+    pub async fn movie_details_full(
+        &self,
+        id: u64,
+        media_type: MediaKind,
+        region: &str,
+        chat_lang: &str,
+    ) -> Result<Option<FullDetails>, TmdbErr> {
+        let (section, rating) = match media_type {
+            MediaKind::Movie => ("movie", "release_dates"),
+            MediaKind::Tv => ("tv", "content_ratings"),
+            MediaKind::Person => return Ok(None),
+        };
+        let requested = trailer_languages(chat_lang, None);
+        let url = format!(
+            "{}/{}/{}?language=ru-RU&append_to_response=videos,external_ids,credits,watch/providers,{}&include_video_language={},null",
+            self.base_url,
+            section,
+            id,
+            rating,
+            requested.join(",")
+        );
+        let (item, extra): (MultiNorm, AppendedDto) = match media_type {
+            MediaKind::Movie => {
+                let data: FullDto<MovieDetailsDto> = self.get_json(&url).await?;
+                (data.details.into(), data.extra)
+            }
+            _ => {
+                let data: FullDto<TvDetailsDto> = self.get_json(&url).await?;
+                (data.details.into(), data.extra)
+            }
+        };
+
+        let mut videos = extra.videos.map(|v| v.results).unwrap_or_default();
+        let langs = trailer_languages(chat_lang, item.original_language.as_deref());
+        let has_chat_lang = videos.iter().any(|v| {
+            v.site.eq_ignore_ascii_case("YouTube")
+                && v.iso_639_1
+                    .as_deref()
+                    .is_some_and(|l| l.eq_ignore_ascii_case(chat_lang))
+        });
+        if let Some(original) = langs.iter().find(|l| !requested.contains(l)) {
+            if !has_chat_lang {
+                let url = format!(
+                    "{}/{}/{}/videos?language={}",
+                    self.base_url, section, id, original
+                );
+                match self.get_json::<VideosResp>(&url).await {
+                    Ok(mut v) => videos.append(&mut v.results),
+                    // карточка важнее трейлера: без роликов оригинала обойдёмся
+                    Err(e) => tracing::warn!("videos {} {}: {:?}", section, id, e),
                 }
             }
         }
-        // Если оба запроса провалились — отдаём ошибку пользователю/в верхний слой
-        if !any_ok {
-            return Err(last_err.unwrap_or(TmdbErr::Net));
-        }
+        let trailer = best_video(&videos, &langs)
+            .map(|v| format!("https://www.youtube.com/watch?v={}", v.key));
+        let certification = match (extra.release_dates, extra.content_ratings) {
+            (Some(d), _) => movie_certification(d, region),
+            (None, Some(r)) => tv_rating(r, region),
+            (None, None) => None,
+        };
+        let cast = extra
+            .credits
+            .map(|c| c.cast.into_iter().take(MAX_CAST).map(|p| p.name).collect())
+            .unwrap_or_default();
+        Ok(Some(FullDetails {
+            item,
+            trailer,
+            imdb_id: extra
+                .external_ids
+                .and_then(|e| e.imdb_id)
+                .filter(|id| !id.is_empty()),
+            certification,
+            providers: extra
+                .watch_providers
+                .and_then(|p| region_providers(p, region)),
+            cast,
+        }))
+    }
+}
 
-        // Фильтруем и сортируем кандидатов
-        let mut candidates: Vec<&Video> = all
-            .iter()
-            .filter(|v| v.site.eq_ignore_ascii_case("YouTube"))
-            .collect();
+const MAX_CAST: usize = 5;
+
+fn region_providers(mut data: WatchProvidersResp, region: &str) -> Option<WatchProviders> {
+    let r = data.results.remove(region)?;
+    let names = |v: Vec<Provider>| v.into_iter().map(|p| p.provider_name).collect();
+    let res = WatchProviders {
+        link: r.link,
+        flatrate: names(r.flatrate),
+        rent: names(r.rent),
+        buy: names(r.buy),
+    };
+    if res.flatrate.is_empty() && res.rent.is_empty() && res.buy.is_empty() {
+        return None;
+    }
+    Some(res)
+}
+
+fn movie_certification(data: ReleaseDatesResp, region: &str) -> Option<String> {
+    data.results
+        .into_iter()
+        .find(|r| r.iso_3166_1 == region)
+        .and_then(|r| {
+            r.release_dates
+                .into_iter()
+                .map(|d| d.certification)
+                .find(|c| !c.trim().is_empty())
+        })
+}
+
+fn tv_rating(data: ContentRatingsResp, region: &str) -> Option<String> {
+    data.results
+        .into_iter()
+        .find(|r| r.iso_3166_1 == region)
+        .map(|r| r.rating)
+        .filter(|c| !c.trim().is_empty())
+}
+
+/* ====== Ретраи запросов к TMDb ======
+   3 повтора с бэкоффом 300/800/1500 мс; на 429 ждём не меньше Retry-After,
+   а если сервер просит больше MAX_RETRY_AFTER_SECS — сразу отдаём RateLimited */
+enum Attempt<T> {
+    Done(Result<T, TmdbErr>),
+    /// Можно повторить; второе поле — Retry-After в секундах.
+    Retry(TmdbErr, Option<u64>),
+}
+
+/// Дольше этого на 429 не ждём: пользователь сидит перед экраном.
+const MAX_RETRY_AFTER_SECS: u64 = 10;
 
-        candidates.sort_by_key(|v| {
+async fn with_backoff<T, F, Fut>(
+    mut attempt: F,
+    mut on_retry: impl FnMut(u64),
+) -> Result<T, TmdbErr>
+where
+    F: FnMut() -> Fut,
+    Fut: std::future::Future<Output = Attempt<T>>,
+{
+    let mut delays = [300u64, 800, 1500].into_iter();
+    loop {
+        let (err, retry_after) = match attempt().await {
+            Attempt::Done(r) => return r,
+            Attempt::Retry(err, retry_after) => (err, retry_after),
+        };
+        let Some(ms) = delays.next() else {
+            return Err(err);
+        };
+        let ms = match retry_after {
+            Some(secs) if secs > MAX_RETRY_AFTER_SECS => return Err(err),
+            Some(secs) => ms.max(secs * 1000),
+            None => ms,
+        };
+        on_retry(ms);
+        sleep(Duration::from_millis(ms)).await;
+    }
+}
+
+// Retry-After в секундах; вариант с HTTP-датой TMDb не присылает
+fn retry_after_secs(resp: &reqwest::Response) -> Option<u64> {
+    resp.headers()
+        .get(reqwest::header::RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim()
+        .parse()
+        .ok()
+}
+
+fn http_client(timeout: Duration) -> Client {
+    Client::builder()
+        .timeout(timeout)
+        .user_agent("tg-movie-bot/1.0 (+teloxide)")
+        .build()
+        .expect("reqwest client")
+}
+
+/// Лучшее видео YouTube: язык по порядку `langs` (см. `trailer_languages`),
+/// официальное, трейлер раньше тизера, затем выше разрешение и свежее дата публикации.
+fn best_video<'a>(all: &'a [Video], langs: &[String]) -> Option<&'a Video> {
+    all.iter()
+        .filter(|v| v.site.eq_ignore_ascii_case("YouTube"))
+        .min_by_key(|v| {
+            let lang = v
+                .iso_639_1
+                .as_deref()
+                .and_then(|l| langs.iter().position(|x| x.eq_ignore_ascii_case(l)))
+                .unwrap_or(langs.len());
             let official = if v.official.unwrap_or(false) { 0 } else { 1 };
             let typ = match v.r#type.as_str() {
                 "Trailer" => 0,
                 "Teaser" => 1,
                 _ => 2,
             };
-            (official, typ)
-        });
+            // published_at — ISO 8601, строки сравниваются как даты
+            (
+                lang,
+                official,
+                typ,
+                Reverse(v.size.unwrap_or(0)),
+                Reverse(v.published_at.clone().unwrap_or_default()),
+            )
+        })
+}
+
+// путь без хоста API и без значения api_key (ключ в логи не пишем)
+fn redact_url(url: &str, base_url: &str) -> String {
+    let path = url.strip_prefix(base_url).unwrap_or(url);
+    let Some((head, query)) = path.split_once('?') else {
+        return path.to_string();
+    };
+    let query: Vec<&str> = query
+        .split('&')
+        .map(|kv| {
+            if kv.starts_with("api_key=") {
+                "api_key=***"
+            } else {
+                kv
+            }
+        })
+        .collect();
+    format!("{}?{}", head, query.join("&"))
+}
+
+/// Больше работ персоны не показываем — как и в обычной выдаче поиска.
+const MAX_FILMOGRAPHY: usize = 10;
 
-        Ok(candidates
-            .first()
-            .map(|v| format!("https://www.youtube.com/watch?v={}", v.key)))
+/// Сезон сериала: название («Сезон 2») и серии.
+#[derive(Deserialize, Debug, Clone)]
+pub struct Season {
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub episodes: Vec<Episode>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Episode {
+    pub episode_number: u32,
+    #[serde(default)]
+    pub name: String,
+}
+
+/// Выдача поиска: фильмы и сериалы; персона — если TMDb поставил её первой.
+#[derive(Debug, Clone)]
+pub struct SearchHits {
+    pub items: Vec<MultiNorm>,
+    pub person: Option<MultiNorm>,
+}
+
+/// Порядок языков для видео: язык чата, язык оригинала, английский — без повторов.
+fn trailer_languages(chat_lang: &str, original: Option<&str>) -> Vec<String> {
+    let mut out: Vec<String> = Vec::new();
+    for lang in [Some(chat_lang), original, Some("en")]
+        .into_iter()
+        .flatten()
+    {
+        let lang = lang.trim().to_lowercase();
+        if !lang.is_empty() && !out.contains(&lang) {
+            out.push(lang);
+        }
     }
+    out
 }
 /* ======= DTOs ======= */
 
@@ -252,8 +736,7 @@ pub struct SearchResp<T> {
     pub results: Vec<T>,
     #[serde(rename = "total_pages")]
     pub _total_pages: u32,
-    #[serde(rename = "total_results")]
-    pub _total_results: u32,
+    pub total_results: u32,
 }
 
 #[derive(Deserialize, Debug, Clone)]
@@ -262,22 +745,36 @@ pub enum SearchMultiDto {
     #[serde(rename = "movie")]
     Movie {
         id: u64,
+        #[serde(default)]
         title: String,
         original_title: String,
         #[serde(default)]
         overview: String,
         poster_path: Option<String>,
         release_date: Option<String>,
+        #[serde(default)]
+        vote_average: Option<f64>,
+        #[serde(default)]
+        vote_count: Option<u64>,
+        #[serde(default)]
+        original_language: Option<String>,
     },
     #[serde(rename = "tv")]
     Tv {
         id: u64,
+        #[serde(default)]
         name: String,
         original_name: String,
         #[serde(default)]
         overview: String,
         poster_path: Option<String>,
         first_air_date: Option<String>,
+        #[serde(default)]
+        vote_average: Option<f64>,
+        #[serde(default)]
+        vote_count: Option<u64>,
+        #[serde(default)]
+        original_language: Option<String>,
     },
     #[serde(rename = "person")]
     Person {
@@ -290,23 +787,45 @@ pub enum SearchMultiDto {
 #[derive(Deserialize, Debug, Clone)]
 pub struct TvDetailsDto {
     pub id: u64,
+    #[serde(default)]
     pub name: String,
     pub original_name: String,
     #[serde(default)]
     pub overview: String,
     pub poster_path: Option<String>,
     pub first_air_date: Option<String>,
+    #[serde(default)]
+    pub vote_average: Option<f64>,
+    #[serde(default)]
+    pub vote_count: Option<u64>,
+    #[serde(default)]
+    pub original_language: Option<String>,
+    #[serde(default)]
+    pub episode_run_time: Vec<u32>,
+    #[serde(default)]
+    pub number_of_seasons: Option<u32>,
 }
 
 #[derive(Deserialize, Debug, Clone)]
 pub struct MovieDetailsDto {
     pub id: u64,
+    #[serde(default)]
     pub title: String,
     pub original_title: String,
     #[serde(default)]
     pub overview: String,
     pub poster_path: Option<String>,
     pub release_date: Option<String>,
+    #[serde(default)]
+    pub vote_average: Option<f64>,
+    #[serde(default)]
+    pub vote_count: Option<u64>,
+    #[serde(default)]
+    pub original_language: Option<String>,
+    #[serde(default)]
+    pub runtime: Option<u32>,
+    #[serde(default)]
+    pub tagline: Option<String>,
 }
 
 #[derive(Deserialize, Debug)]
@@ -320,73 +839,289 @@ struct Video {
     site: String,
     r#type: String,
     official: Option<bool>,
+    #[serde(default)]
+    iso_639_1: Option<String>,
+    #[serde(default)]
+    size: Option<u32>,
+    #[serde(default)]
+    published_at: Option<String>,
 }
 
+/// Результат movie_details_full: карточка и всё, что к ней подтянули.
 #[derive(Debug, Clone)]
-pub struct MultiNorm {
-    pub id: u64,
-    pub media_type: MediaKind,        // всегда есть
-    pub title: String,                // гарантируем при маппинге
-    pub original_title: String,       // гарантируем при маппинге (для person = title)
-    pub overview: String,             // пустая строка, если нет
-    pub release_date: Option<String>, // у person нет
-    pub image_path: Option<String>,   // poster_path или profile_path
+pub struct FullDetails {
+    pub item: MultiNorm,
+    pub trailer: Option<String>,
+    pub imdb_id: Option<String>,
+    pub certification: Option<String>,
+    pub providers: Option<WatchProviders>,
+    pub cast: Vec<String>,
 }
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
-#[serde(rename_all = "lowercase")]
-pub enum MediaKind {
-    Movie,
-    Tv,
-    Person,
+// детали + дописанные append_to_response разделы в одном JSON
+#[derive(Deserialize, Debug)]
+struct FullDto<D> {
+    #[serde(flatten)]
+    details: D,
+    #[serde(flatten)]
+    extra: AppendedDto,
 }
 
-impl MediaKind {
-    pub(crate) fn as_str(&self) -> &'static str {
-        match self {
-            MediaKind::Movie => "movie",
-            MediaKind::Tv => "tv",
-            MediaKind::Person => "person",
-        }
-    }
+#[derive(Deserialize, Debug)]
+struct AppendedDto {
+    #[serde(default)]
+    videos: Option<VideosResp>,
+    #[serde(default)]
+    external_ids: Option<ExternalIdsResp>,
+    #[serde(default)]
+    credits: Option<CreditsResp>,
+    #[serde(default, rename = "watch/providers")]
+    watch_providers: Option<WatchProvidersResp>,
+    #[serde(default)]
+    release_dates: Option<ReleaseDatesResp>,
+    #[serde(default)]
+    content_ratings: Option<ContentRatingsResp>,
 }
-/* Mapping to internal model */
 
-impl From<SearchMultiDto> for MultiNorm {
-    fn from(x: SearchMultiDto) -> Self {
-        match x {
-            SearchMultiDto::Movie {
-                id,
-                title,
-                original_title,
-                overview,
-                poster_path,
-                release_date,
-            } => Self {
-                id,
-                media_type: MediaKind::Movie,
-                title,
-                original_title,
-                overview,
-                release_date,
-                image_path: poster_path,
-            },
-            SearchMultiDto::Tv {
-                id,
-                name,
-                original_name,
-                overview,
-                poster_path,
-                first_air_date,
-            } => Self {
-                id,
-                media_type: MediaKind::Tv,
-                title: name,
-                original_title: original_name,
-                overview,
-                release_date: first_air_date,
-                image_path: poster_path,
-            },
+#[derive(Deserialize, Debug)]
+struct CreditsResp {
+    #[serde(default)]
+    cast: Vec<CastMember>,
+}
+
+#[derive(Deserialize, Debug)]
+struct CastMember {
+    name: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct ExternalIdsResp {
+    #[serde(default)]
+    imdb_id: Option<String>,
+}
+
+// у фильма список в `keywords`, у сериала — в `results`
+#[derive(Deserialize, Debug)]
+struct KeywordsResp {
+    #[serde(default, alias = "results")]
+    keywords: Vec<Keyword>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Keyword {
+    name: String,
+}
+
+// /person/{id} c append_to_response=combined_credits; в cast есть media_type
+#[derive(Deserialize, Debug)]
+struct PersonDto {
+    name: String,
+    #[serde(default)]
+    combined_credits: CombinedCredits,
+}
+
+#[derive(Deserialize, Debug, Default)]
+struct CombinedCredits {
+    #[serde(default)]
+    cast: Vec<SearchMultiDto>,
+    // режиссёры и сценаристы в cast не попадают
+    #[serde(default)]
+    crew: Vec<SearchMultiDto>,
+}
+
+#[derive(Deserialize, Debug)]
+struct GenresResp {
+    #[serde(default)]
+    genres: Vec<Genre>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Genre {
+    name: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct ImagesResp {
+    #[serde(default)]
+    posters: Vec<Image>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Image {
+    file_path: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct WatchProvidersResp {
+    #[serde(default)]
+    results: std::collections::HashMap<String, RegionProviders>,
+}
+
+#[derive(Deserialize, Debug)]
+struct RegionProviders {
+    link: Option<String>,
+    #[serde(default)]
+    flatrate: Vec<Provider>,
+    #[serde(default)]
+    rent: Vec<Provider>,
+    #[serde(default)]
+    buy: Vec<Provider>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Provider {
+    provider_name: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct WatchProviders {
+    pub link: Option<String>,
+    pub flatrate: Vec<String>,
+    pub rent: Vec<String>,
+    pub buy: Vec<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ReleaseDatesResp {
+    results: Vec<RegionReleaseDates>,
+}
+
+#[derive(Deserialize, Debug)]
+struct RegionReleaseDates {
+    iso_3166_1: String,
+    release_dates: Vec<ReleaseDate>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ReleaseDate {
+    #[serde(default)]
+    certification: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct ContentRatingsResp {
+    results: Vec<ContentRating>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ContentRating {
+    iso_3166_1: String,
+    #[serde(default)]
+    rating: String,
+}
+
+/// Единая модель результата TMDb (поиск, детали, inline) для всего бота;
+/// в список чата попадает как `StoredMovie` через `From<MultiNorm>`.
+#[derive(Debug, Clone)]
+pub struct MultiNorm {
+    pub id: u64,
+    pub media_type: MediaKind,             // всегда есть
+    pub title: String,                     // гарантируем при маппинге
+    pub original_title: String,            // гарантируем при маппинге (для person = title)
+    pub overview: String,                  // пустая строка, если нет
+    pub release_date: Option<String>,      // у person нет
+    pub image_path: Option<String>,        // poster_path или profile_path
+    pub vote_average: Option<f64>,         // у person нет
+    pub vote_count: Option<u64>,           // у person нет
+    pub runtime: Option<u32>,              // минуты; только из деталей, в поиске нет
+    pub seasons: Option<u32>,              // число сезонов; только у деталей сериала
+    pub tagline: Option<String>,           // слоган; только у деталей фильма
+    pub original_language: Option<String>, // ISO 639-1; у person нет
+}
+
+impl MultiNorm {
+    /// Постер фильма или сериала; у персоны в image_path фото профиля — тогда None.
+    pub fn poster_path(&self) -> Option<&str> {
+        match self.media_type {
+            MediaKind::Movie | MediaKind::Tv => self.image_path.as_deref(),
+            MediaKind::Person => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MediaKind {
+    Movie,
+    Tv,
+    Person,
+}
+
+impl MediaKind {
+    pub(crate) fn as_str(&self) -> &'static str {
+        match self {
+            MediaKind::Movie => "movie",
+            MediaKind::Tv => "tv",
+            MediaKind::Person => "person",
+        }
+    }
+}
+/* Mapping to internal model */
+
+/* ====== Локализованное название ======
+   без перевода TMDb обычно отдаёт оригинал, но у сериалов бывает пусто
+   или заглушка вроде «-»: тогда показываем оригинальное название */
+fn localized_title(title: String, original: &str) -> String {
+    if title.chars().any(char::is_alphanumeric) || original.trim().is_empty() {
+        title
+    } else {
+        original.to_string()
+    }
+}
+
+impl From<SearchMultiDto> for MultiNorm {
+    fn from(x: SearchMultiDto) -> Self {
+        match x {
+            SearchMultiDto::Movie {
+                id,
+                title,
+                original_title,
+                overview,
+                poster_path,
+                release_date,
+                vote_average,
+                vote_count,
+                original_language,
+            } => Self {
+                id,
+                media_type: MediaKind::Movie,
+                title: localized_title(title, &original_title),
+                original_title,
+                overview,
+                release_date,
+                image_path: poster_path,
+                vote_average,
+                vote_count,
+                runtime: None,
+                seasons: None,
+                tagline: None,
+                original_language,
+            },
+            SearchMultiDto::Tv {
+                id,
+                name,
+                original_name,
+                overview,
+                poster_path,
+                first_air_date,
+                vote_average,
+                vote_count,
+                original_language,
+            } => Self {
+                id,
+                media_type: MediaKind::Tv,
+                title: localized_title(name, &original_name),
+                original_title: original_name,
+                overview,
+                release_date: first_air_date,
+                image_path: poster_path,
+                vote_average,
+                vote_count,
+                runtime: None,
+                seasons: None,
+                tagline: None,
+                original_language,
+            },
             SearchMultiDto::Person {
                 id,
                 name,
@@ -399,6 +1134,12 @@ impl From<SearchMultiDto> for MultiNorm {
                 overview: String::new(),
                 release_date: None,
                 image_path: profile_path,
+                vote_average: None,
+                vote_count: None,
+                runtime: None,
+                seasons: None,
+                tagline: None,
+                original_language: None,
             },
         }
     }
@@ -409,11 +1150,17 @@ impl From<TvDetailsDto> for MultiNorm {
         Self {
             id: tv.id,
             media_type: MediaKind::Tv,
-            title: tv.name,
+            title: localized_title(tv.name, &tv.original_name),
             original_title: tv.original_name,
             overview: tv.overview,
             release_date: tv.first_air_date,
             image_path: tv.poster_path,
+            vote_average: tv.vote_average,
+            vote_count: tv.vote_count,
+            runtime: tv.episode_run_time.first().copied(),
+            seasons: tv.number_of_seasons,
+            tagline: None,
+            original_language: tv.original_language,
         }
     }
 }
@@ -423,11 +1170,17 @@ impl From<MovieDetailsDto> for MultiNorm {
         Self {
             id: m.id,
             media_type: MediaKind::Movie,
-            title: m.title,
+            title: localized_title(m.title, &m.original_title),
             original_title: m.original_title,
             overview: m.overview,
             release_date: m.release_date,
             image_path: m.poster_path,
+            vote_average: m.vote_average,
+            vote_count: m.vote_count,
+            runtime: m.runtime,
+            seasons: None,
+            tagline: m.tagline.filter(|t| !t.trim().is_empty()),
+            original_language: m.original_language,
         }
     }
 }
@@ -436,6 +1189,22 @@ impl From<MovieDetailsDto> for MultiNorm {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_redact_url() {
+        let base = "https://api.themoviedb.org/3";
+        assert_eq!(
+            redact_url(
+                "https://api.themoviedb.org/3/search/multi?query=x&api_key=0123abcd&page=1",
+                base
+            ),
+            "/search/multi?query=x&api_key=***&page=1"
+        );
+        assert_eq!(
+            redact_url("https://api.themoviedb.org/3/movie/1", base),
+            "/movie/1"
+        );
+    }
+
     #[test]
     fn test_media_kind_as_str() {
         assert_eq!(MediaKind::Movie.as_str(), "movie");
@@ -449,6 +1218,28 @@ mod tests {
         assert_ne!(MediaKind::Movie, MediaKind::Tv);
     }
 
+    #[test]
+    fn test_poster_path_skips_person_profiles() {
+        let item = |media_type: MediaKind| MultiNorm {
+            id: 1,
+            media_type,
+            title: "x".to_string(),
+            original_title: "x".to_string(),
+            overview: "".to_string(),
+            release_date: None,
+            image_path: Some("/img.jpg".to_string()),
+            vote_average: None,
+            vote_count: None,
+            runtime: None,
+            seasons: None,
+            tagline: None,
+            original_language: None,
+        };
+        assert_eq!(item(MediaKind::Movie).poster_path(), Some("/img.jpg"));
+        assert_eq!(item(MediaKind::Tv).poster_path(), Some("/img.jpg"));
+        assert_eq!(item(MediaKind::Person).poster_path(), None);
+    }
+
     #[test]
     fn test_mapping_movie_dto() {
         let dto = SearchMultiDto::Movie {
@@ -458,12 +1249,16 @@ mod tests {
             overview: "Overview".to_string(),
             poster_path: Some("/path.jpg".to_string()),
             release_date: Some("2023-01-01".to_string()),
+            vote_average: Some(7.5),
+            vote_count: None,
+            original_language: Some("en".to_string()),
         };
         let norm: MultiNorm = dto.into();
         assert_eq!(norm.id, 1);
         assert_eq!(norm.media_type, MediaKind::Movie);
         assert_eq!(norm.title, "Movie Title");
         assert_eq!(norm.image_path, Some("/path.jpg".to_string()));
+        assert_eq!(norm.vote_average, Some(7.5));
     }
 
     #[test]
@@ -475,6 +1270,9 @@ mod tests {
             overview: "Overview TV".to_string(),
             poster_path: Some("/tv.jpg".to_string()),
             first_air_date: Some("2022-01-01".to_string()),
+            vote_average: None,
+            vote_count: None,
+            original_language: None,
         };
         let norm: MultiNorm = dto.into();
         assert_eq!(norm.id, 2);
@@ -482,6 +1280,49 @@ mod tests {
         assert_eq!(norm.title, "TV Show");
     }
 
+    #[test]
+    fn test_movie_details_tagline() {
+        let dto: MovieDetailsDto = serde_json::from_value(serde_json::json!({
+            "id": 27205, "title": "Начало", "original_title": "Inception",
+            "poster_path": null, "release_date": "2010-07-15",
+            "tagline": "Твой разум — место преступления"
+        }))
+        .unwrap();
+        assert_eq!(
+            MultiNorm::from(dto).tagline.as_deref(),
+            Some("Твой разум — место преступления")
+        );
+
+        let blank: MovieDetailsDto = serde_json::from_value(serde_json::json!({
+            "id": 1, "title": "T", "original_title": "T",
+            "poster_path": null, "release_date": null, "tagline": "  "
+        }))
+        .unwrap();
+        assert_eq!(MultiNorm::from(blank).tagline, None);
+    }
+
+    #[test]
+    fn test_missing_localized_title_falls_back_to_original() {
+        let tv: SearchMultiDto = serde_json::from_value(serde_json::json!({
+            "media_type": "tv", "id": 5, "name": "", "original_name": "ジョジョの奇妙な冒険",
+            "poster_path": null, "first_air_date": null
+        }))
+        .unwrap();
+        assert_eq!(MultiNorm::from(tv).title, "ジョジョの奇妙な冒険");
+
+        let movie: MovieDetailsDto = serde_json::from_value(serde_json::json!({
+            "id": 6, "original_title": "Amélie", "poster_path": null, "release_date": null
+        }))
+        .unwrap();
+        let movie = MultiNorm::from(movie);
+        assert_eq!(movie.title, "Amélie");
+        assert_eq!(movie.tagline, None);
+
+        assert_eq!(localized_title("-".to_string(), "Dark"), "Dark");
+        assert_eq!(localized_title("Тьма".to_string(), "Dark"), "Тьма");
+        assert_eq!(localized_title(" ".to_string(), ""), " ");
+    }
+
     #[test]
     fn test_mapping_person_dto() {
         let dto = SearchMultiDto::Person {
@@ -519,7 +1360,7 @@ mod tests {
 
     #[tokio::test]
     async fn test_search_movies_ru_mock() {
-        use wiremock::matchers::{method, path};
+        use wiremock::matchers::{method, path, query_param};
         use wiremock::{Mock, MockServer, ResponseTemplate};
 
         let server = MockServer::start().await;
@@ -544,61 +1385,973 @@ mod tests {
 
         Mock::given(method("GET"))
             .and(path("/search/multi"))
+            .and(query_param("include_adult", "false"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(response_body.clone()))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/search/multi"))
+            .and(query_param("include_adult", "true"))
             .respond_with(ResponseTemplate::new(200).set_body_json(response_body))
+            .expect(1)
             .mount(&server)
             .await;
 
-        let results = client.search_movies_ru("test", 1).await.unwrap();
+        let results = client
+            .search_movies_ru("test", 1, SearchFilter::default())
+            .await
+            .unwrap();
         assert_eq!(results.len(), 1);
         assert_eq!(results[0].title, "Mock Movie");
+        let adult = SearchFilter {
+            include_adult: true,
+            min_votes: 0,
+        };
+        assert_eq!(
+            client
+                .search_movies_ru("test", 1, adult)
+                .await
+                .unwrap()
+                .len(),
+            1
+        );
     }
 
     #[tokio::test]
-    async fn test_best_trailer_url_mock() {
+    async fn test_search_min_votes_filter_with_fallback() {
         use wiremock::matchers::{method, path, query_param};
         use wiremock::{Mock, MockServer, ResponseTemplate};
 
         let server = MockServer::start().await;
         let client = TmdbClient::new_test("token".to_string(), server.uri());
+        let movie = |id: u64, votes: u64| {
+            serde_json::json!({
+                "media_type": "movie", "id": id, "title": format!("M{}", id),
+                "original_title": "x", "overview": "", "poster_path": null,
+                "release_date": null, "vote_count": votes
+            })
+        };
+        Mock::given(method("GET"))
+            .and(path("/search/multi"))
+            .and(query_param("query", "broad"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "page": 1, "total_pages": 1, "total_results": 3,
+                "results": [movie(1, 0), movie(2, 2500), movie(3, 1)]
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/search/multi"))
+            .and(query_param("query", "obscure"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "page": 1, "total_pages": 1, "total_results": 2,
+                "results": [movie(4, 0), movie(5, 3)]
+            })))
+            .mount(&server)
+            .await;
 
-        let video = MultiNorm {
-            id: 1,
-            media_type: MediaKind::Movie,
-            title: "Movie".to_string(),
-            original_title: "Movie".to_string(),
-            overview: "".to_string(),
-            release_date: None,
-            image_path: None,
+        let filter = SearchFilter {
+            include_adult: false,
+            min_votes: 10,
         };
+        let ids = |v: Vec<MultiNorm>| v.iter().map(|m| m.id).collect::<Vec<_>>();
+        let known = client.search_movies_ru("broad", 10, filter).await.unwrap();
+        assert_eq!(ids(known), vec![2]);
+        // всё малоизвестное — не прячем ничего
+        let all = client
+            .search_movies_ru("obscure", 10, filter)
+            .await
+            .unwrap();
+        assert_eq!(ids(all), vec![4, 5]);
+    }
+
+    #[tokio::test]
+    async fn test_search_falls_back_to_movie_search() {
+        use wiremock::matchers::{method, path, query_param};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
 
-        // Mock for RU videos
+        let server = MockServer::start().await;
+        let client = TmdbClient::new_test("token".to_string(), server.uri());
+        // multi знает только режиссёра — фильмы находит /search/movie
+        Mock::given(method("GET"))
+            .and(path("/search/multi"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "page": 1, "total_pages": 1, "total_results": 1,
+                "results": [{"media_type": "person", "id": 7, "name": "Нолан"}]
+            })))
+            .mount(&server)
+            .await;
         Mock::given(method("GET"))
-            .and(path("/movie/1/videos"))
-            .and(query_param("language", "ru-RU"))
+            .and(path("/search/movie"))
+            .and(query_param("query", "Нолан"))
             .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
-                "results": []
+                "page": 1, "total_pages": 1, "total_results": 1,
+                "results": [{
+                    "id": 11, "title": "Нолан: фильм", "original_title": "Nolan",
+                    "overview": "", "poster_path": null, "release_date": "2020-01-01"
+                }]
             })))
+            .expect(1)
             .mount(&server)
             .await;
 
-        // Mock for EN videos
+        let found = client
+            .search_movies_ru("Нолан", 10, SearchFilter::default())
+            .await
+            .unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].id, 11);
+        assert_eq!(found[0].media_type, MediaKind::Movie);
+    }
+
+    #[tokio::test]
+    async fn test_person_hit_and_filmography() {
+        use wiremock::matchers::{method, path, query_param};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        let client = TmdbClient::new_test("token".to_string(), server.uri());
+        let credit = |id: u64, votes: u64| {
+            serde_json::json!({
+                "media_type": "movie", "id": id, "title": format!("M{}", id),
+                "original_title": "x", "poster_path": null, "release_date": null,
+                "vote_count": votes
+            })
+        };
         Mock::given(method("GET"))
-            .and(path("/movie/1/videos"))
-            .and(query_param("language", "en-US"))
+            .and(path("/search/multi"))
             .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "page": 1, "total_pages": 1, "total_results": 2,
                 "results": [
-                    {
-                        "key": "xyz",
-                        "site": "YouTube",
-                        "type": "Trailer",
-                        "official": true
-                    }
+                    {"media_type": "person", "id": 525, "name": "Кристофер Нолан", "profile_path": "/n.jpg"},
+                    credit(1, 50)
                 ]
             })))
             .mount(&server)
             .await;
+        Mock::given(method("GET"))
+            .and(path("/person/525"))
+            .and(query_param("append_to_response", "combined_credits"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "id": 525,
+                "name": "Кристофер Нолан",
+                "combined_credits": {
+                    "cast": [credit(2, 10)],
+                    // режиссёр и сценарист одного фильма — две строки в crew
+                    "crew": [credit(3, 30000), credit(3, 30000), credit(2, 10)]
+                }
+            })))
+            .mount(&server)
+            .await;
+
+        let hits = client
+            .search_with_person("Нолан", 10, SearchFilter::default(), 1)
+            .await
+            .unwrap();
+        assert_eq!(hits.items.len(), 1);
+        let person = hits.person.unwrap();
+        assert_eq!((person.id, person.media_type), (525, MediaKind::Person));
+
+        let (name, films) = client.person_filmography(525).await.unwrap();
+        assert_eq!(name, "Кристофер Нолан");
+        let ids: Vec<u64> = films.iter().map(|m| m.id).collect();
+        assert_eq!(ids, vec![3, 2]);
+    }
+
+    #[test]
+    fn test_best_video_language_order() {
+        let videos: Vec<Video> = serde_json::from_value(serde_json::json!([
+            {"key": "en1", "site": "YouTube", "type": "Trailer", "official": true, "iso_639_1": "en"},
+            {"key": "fr1", "site": "YouTube", "type": "Trailer", "official": false, "iso_639_1": "fr"},
+            {"key": "none", "site": "YouTube", "type": "Trailer", "official": true}
+        ]))
+        .unwrap();
+        // язык чата важнее «официальности»
+        let langs = trailer_languages("fr", None);
+        assert_eq!(best_video(&videos, &langs).unwrap().key, "fr1");
+        // на языке чата нет — язык оригинала раньше английского
+        let langs = trailer_languages("ru", Some("fr"));
+        assert_eq!(best_video(&videos, &langs).unwrap().key, "fr1");
+        // ни того ни другого — английский, ролики без языка в конце
+        let langs = trailer_languages("ru", None);
+        assert_eq!(best_video(&videos, &langs).unwrap().key, "en1");
+    }
+
+    #[test]
+    fn test_best_video_prefers_hd_and_newer() {
+        let videos: Vec<Video> = serde_json::from_value(serde_json::json!([
+            {"key": "old", "site": "YouTube", "type": "Trailer", "official": true,
+             "size": 720, "published_at": "2010-05-01T10:00:00.000Z"},
+            {"key": "teaser", "site": "YouTube", "type": "Teaser", "official": true,
+             "size": 2160, "published_at": "2012-01-01T10:00:00.000Z"},
+            {"key": "new", "site": "YouTube", "type": "Trailer", "official": true,
+             "size": 1080, "published_at": "2011-03-01T10:00:00.000Z"},
+            {"key": "older_hd", "site": "YouTube", "type": "Trailer", "official": true,
+             "size": 1080, "published_at": "2009-03-01T10:00:00.000Z"},
+            {"key": "vimeo", "site": "Vimeo", "type": "Trailer", "official": true, "size": 2160}
+        ]))
+        .unwrap();
+        assert_eq!(
+            best_video(&videos, &trailer_languages("ru", None))
+                .unwrap()
+                .key,
+            "new"
+        );
+    }
+
+    #[test]
+    fn test_trailer_languages() {
+        assert_eq!(trailer_languages("ru", Some("fr")), vec!["ru", "fr", "en"]);
+        assert_eq!(trailer_languages("en", Some("en")), vec!["en"]);
+        assert_eq!(trailer_languages("RU", None), vec!["ru", "en"]);
+    }
+
+    #[tokio::test]
+    async fn test_discover_popular_mock() {
+        use wiremock::matchers::{method, path, query_param};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/discover/movie"))
+            .and(query_param("page", "3"))
+            .and(query_param("sort_by", "popularity.desc"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "page": 3,
+                "results": [{
+                    "id": 27205,
+                    "title": "Начало",
+                    "original_title": "Inception",
+                    "overview": "Сон во сне",
+                    "poster_path": "/inception.jpg",
+                    "release_date": "2010-07-15",
+                    "vote_average": 8.4,
+                    "vote_count": 37000
+                }],
+                "total_pages": 500,
+                "total_results": 10000
+            })))
+            .mount(&server)
+            .await;
+
+        let client = TmdbClient::new_test("token".to_string(), server.uri());
+        let items = client.discover_popular(3).await.unwrap();
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].id, 27205);
+        assert_eq!(items[0].media_type, MediaKind::Movie);
+        assert_eq!(items[0].original_title, "Inception");
+        assert_eq!(items[0].vote_count, Some(37000));
+    }
+
+    #[tokio::test]
+    async fn test_details_full_imdb_id_for_tv_and_missing() {
+        use wiremock::matchers::{method, path};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
 
-        let url = client.best_trailer_url(video).await.unwrap();
-        assert_eq!(url, Some("https://www.youtube.com/watch?v=xyz".to_string()));
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/tv/1399"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "id": 1399, "name": "Игра престолов", "original_name": "Game of Thrones",
+                "poster_path": null, "first_air_date": "2011-04-17",
+                "external_ids": { "imdb_id": "tt0944947" }
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/movie/7"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "id": 7, "title": "T", "original_title": "T",
+                "poster_path": null, "release_date": null,
+                "external_ids": { "imdb_id": "" }
+            })))
+            .mount(&server)
+            .await;
+
+        let client = TmdbClient::new_test("token".to_string(), server.uri());
+        let tv = client
+            .movie_details_full(1399, MediaKind::Tv, "RU", "ru")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(tv.imdb_id.as_deref(), Some("tt0944947"));
+        let movie = client
+            .movie_details_full(7, MediaKind::Movie, "RU", "ru")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(movie.imdb_id, None);
+    }
+
+    #[tokio::test]
+    async fn test_details_full_is_one_request() {
+        use wiremock::matchers::{method, path, query_param};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/movie/27205"))
+            .and(query_param(
+                "append_to_response",
+                "videos,external_ids,credits,watch/providers,release_dates",
+            ))
+            .and(query_param("include_video_language", "ru,en,null"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "id": 27205, "title": "Начало", "original_title": "Inception",
+                "poster_path": "/p.jpg", "release_date": "2010-07-15", "runtime": 148,
+                "videos": { "results": [
+                    { "key": "abc", "site": "YouTube", "type": "Trailer", "official": true, "iso_639_1": "ru" }
+                ] },
+                "external_ids": { "imdb_id": "tt1375666" },
+                "credits": { "cast": [
+                    {"name": "Леонардо ДиКаприо"}, {"name": "Джозеф Гордон-Левитт"},
+                    {"name": "Эллиот Пейдж"}, {"name": "Том Харди"},
+                    {"name": "Кэн Ватанабэ"}, {"name": "Киллиан Мёрфи"}
+                ] },
+                "watch/providers": { "results": {
+                    "RU": { "link": null, "flatrate": [{ "provider_name": "Okko" }] }
+                } },
+                "release_dates": { "results": [
+                    { "iso_3166_1": "RU", "release_dates": [{ "certification": "12+" }] }
+                ] }
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let client = TmdbClient::new_test("token".to_string(), server.uri());
+        let full = client
+            .movie_details_full(27205, MediaKind::Movie, "RU", "ru")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(full.item.title, "Начало");
+        assert_eq!(full.item.runtime, Some(148));
+        assert_eq!(
+            full.trailer.as_deref(),
+            Some("https://www.youtube.com/watch?v=abc")
+        );
+        assert_eq!(full.imdb_id.as_deref(), Some("tt1375666"));
+        assert_eq!(full.certification.as_deref(), Some("12+"));
+        assert_eq!(full.providers.unwrap().flatrate, vec!["Okko"]);
+        assert_eq!(full.cast.len(), MAX_CAST);
+        assert_eq!(full.cast[0], "Леонардо ДиКаприо");
+        assert_eq!(server.received_requests().await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_details_full_fetches_original_language_trailer() {
+        use wiremock::matchers::{method, path, query_param};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/movie/194"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "id": 194, "title": "Амели", "original_title": "Le Fabuleux Destin d'Amélie Poulain",
+                "original_language": "fr", "poster_path": null, "release_date": "2001-04-25",
+                "videos": { "results": [
+                    { "key": "en1", "site": "YouTube", "type": "Trailer", "official": true, "iso_639_1": "en" }
+                ] }
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/movie/194/videos"))
+            .and(query_param("language", "fr"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "results": [
+                    { "key": "fr1", "site": "YouTube", "type": "Trailer", "official": true, "iso_639_1": "fr" }
+                ]
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let client = TmdbClient::new_test("token".to_string(), server.uri());
+        // на языке чата (ru) роликов нет — французский оригинал раньше английского
+        let full = client
+            .movie_details_full(194, MediaKind::Movie, "RU", "ru")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            full.trailer.as_deref(),
+            Some("https://www.youtube.com/watch?v=fr1")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_season_episodes_in_order() {
+        use wiremock::matchers::{method, path};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/tv/1399/season/2"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "id": 3625, "name": "Сезон 2", "season_number": 2,
+                "episodes": [
+                    {"episode_number": 2, "name": "Ночные земли", "air_date": "2012-04-08"},
+                    {"episode_number": 1, "name": "Север помнит", "air_date": "2012-04-01"}
+                ]
+            })))
+            .mount(&server)
+            .await;
+
+        let client = TmdbClient::new_test("token".to_string(), server.uri());
+        let season = client.season(1399, 2).await.unwrap();
+        assert_eq!(season.name, "Сезон 2");
+        let names: Vec<&str> = season.episodes.iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(names, vec!["Север помнит", "Ночные земли"]);
+        assert!(matches!(
+            client.season(1399, 9).await,
+            Err(TmdbErr::NotFound)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_genres_in_chat_language() {
+        use wiremock::matchers::{method, path, query_param};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/genre/tv/list"))
+            .and(query_param("language", "fr"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "genres": [{"id": 10759, "name": "Action & Adventure"}, {"id": 35, "name": "Comédie"}]
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let client = TmdbClient::new_test("token".to_string(), server.uri());
+        let genres = client.genres(MediaKind::Tv, "fr").await.unwrap();
+        assert_eq!(genres, vec!["Action & Adventure", "Comédie"]);
+        assert!(client
+            .genres(MediaKind::Person, "fr")
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_keywords_for_movie_tv_and_empty() {
+        use wiremock::matchers::{method, path};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/movie/27205/keywords"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "id": 27205,
+                "keywords": [{"id": 1, "name": "dream"}, {"id": 2, "name": "heist"}]
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/tv/1399/keywords"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "id": 1399,
+                "results": [{"id": 3, "name": "dragon"}]
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/movie/7/keywords"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({"id": 7})))
+            .mount(&server)
+            .await;
+
+        let client = TmdbClient::new_test("token".to_string(), server.uri());
+        let item = |id: u64, media_type: MediaKind| MultiNorm {
+            id,
+            media_type,
+            title: "T".to_string(),
+            original_title: "T".to_string(),
+            overview: "".to_string(),
+            release_date: None,
+            image_path: None,
+            vote_average: None,
+            vote_count: None,
+            runtime: None,
+            seasons: None,
+            tagline: None,
+            original_language: None,
+        };
+        assert_eq!(
+            client
+                .keywords(&item(27205, MediaKind::Movie))
+                .await
+                .unwrap(),
+            vec!["dream", "heist"]
+        );
+        assert_eq!(
+            client.keywords(&item(1399, MediaKind::Tv)).await.unwrap(),
+            vec!["dragon"]
+        );
+        assert!(client
+            .keywords(&item(7, MediaKind::Movie))
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_images_dedupes_posters() {
+        use wiremock::matchers::{method, path};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        let client = TmdbClient::new_test("token".to_string(), server.uri());
+
+        Mock::given(method("GET"))
+            .and(path("/movie/1/images"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "id": 1,
+                "backdrops": [{ "file_path": "/b.jpg" }],
+                "posters": [
+                    { "file_path": "/a.jpg" },
+                    { "file_path": "/b.jpg" },
+                    { "file_path": "/a.jpg" }
+                ]
+            })))
+            .mount(&server)
+            .await;
+
+        let item = MultiNorm {
+            id: 1,
+            media_type: MediaKind::Movie,
+            title: "Movie".to_string(),
+            original_title: "Movie".to_string(),
+            overview: "".to_string(),
+            release_date: None,
+            image_path: None,
+            vote_average: None,
+            vote_count: None,
+            runtime: None,
+            seasons: None,
+            tagline: None,
+            original_language: None,
+        };
+        let posters = client.images(&item).await.unwrap();
+        assert_eq!(posters, vec!["/a.jpg".to_string(), "/b.jpg".to_string()]);
+    }
+
+    #[test]
+    fn test_image_url_uses_configured_base() {
+        let client = TmdbClient::with_urls(
+            "token".to_string(),
+            "http://localhost:1/3/".to_string(),
+            "http://images.local/t/p/".to_string(),
+        );
+        assert_eq!(
+            client.image_url("/abc.jpg"),
+            "http://images.local/t/p/w500/abc.jpg"
+        );
+        assert_eq!(
+            client.image_url_sized("/abc.jpg", ImageSize::W780),
+            "http://images.local/t/p/w780/abc.jpg"
+        );
+    }
+
+    #[test]
+    fn test_blank_image_base_url_falls_back_to_tmdb() {
+        assert_eq!(
+            url_or_default(Some("  ".to_string()), DEFAULT_IMAGE_BASE_URL),
+            DEFAULT_IMAGE_BASE_URL
+        );
+        assert_eq!(
+            url_or_default(None, DEFAULT_IMAGE_BASE_URL),
+            DEFAULT_IMAGE_BASE_URL
+        );
+        assert_eq!(
+            url_or_default(
+                Some(" https://img.proxy/t/p ".to_string()),
+                DEFAULT_IMAGE_BASE_URL
+            ),
+            "https://img.proxy/t/p"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_get_json_retries_server_errors_then_succeeds() {
+        use wiremock::matchers::{method, path};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        let client = TmdbClient::new_test("token".to_string(), server.uri());
+
+        Mock::given(method("GET"))
+            .and(path("/retry"))
+            .respond_with(ResponseTemplate::new(500))
+            .up_to_n_times(2)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/retry"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({ "ok": 1 })))
+            .mount(&server)
+            .await;
+
+        let url = format!("{}/retry", server.uri());
+        let v: serde_json::Value = client.get_json(&url).await.unwrap();
+        assert_eq!(v["ok"], 1);
+        assert_eq!(server.received_requests().await.unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_get_json_gives_up_on_server_errors() {
+        use wiremock::matchers::{method, path};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        let client = TmdbClient::new_test("token".to_string(), server.uri());
+
+        Mock::given(method("GET"))
+            .and(path("/down"))
+            .respond_with(ResponseTemplate::new(503))
+            .mount(&server)
+            .await;
+
+        let url = format!("{}/down", server.uri());
+        let err = client
+            .get_json::<serde_json::Value>(&url)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, TmdbErr::Server(503)));
+        // первая попытка + 3 ретрая
+        assert_eq!(server.received_requests().await.unwrap().len(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_get_json_rate_limited() {
+        use wiremock::matchers::{method, path};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        let client = TmdbClient::new_test("token".to_string(), server.uri());
+
+        Mock::given(method("GET"))
+            .and(path("/limited"))
+            .respond_with(ResponseTemplate::new(429).insert_header("retry-after", "1"))
+            .up_to_n_times(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/limited"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({ "ok": 1 })))
+            .mount(&server)
+            .await;
+
+        let url = format!("{}/limited", server.uri());
+        let started = std::time::Instant::now();
+        let v: serde_json::Value = client.get_json(&url).await.unwrap();
+        assert_eq!(v["ok"], 1);
+        // Retry-After: 1 длиннее первой паузы в 300 мс
+        assert!(started.elapsed() >= Duration::from_secs(1));
+        assert_eq!(server.received_requests().await.unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_get_json_gives_up_on_long_retry_after() {
+        use wiremock::matchers::{method, path};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        let client = TmdbClient::new_test("token".to_string(), server.uri());
+
+        Mock::given(method("GET"))
+            .and(path("/limited"))
+            .respond_with(ResponseTemplate::new(429).insert_header("retry-after", "60"))
+            .mount(&server)
+            .await;
+
+        let url = format!("{}/limited", server.uri());
+        let err = client
+            .get_json::<serde_json::Value>(&url)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, TmdbErr::RateLimited));
+        assert_eq!(server.received_requests().await.unwrap().len(), 1);
+    }
+
+    // попытки по сценарию, без сети: остановленные часы двигают только паузы между ретраями
+    async fn run_backoff(script: Vec<Attempt<u8>>) -> (Result<u8, TmdbErr>, Vec<u64>, u128) {
+        let mut script = script.into_iter();
+        let mut waits = Vec::new();
+        let started = tokio::time::Instant::now();
+        let res = with_backoff(
+            || std::future::ready(script.next().expect("лишняя попытка")),
+            |ms| waits.push(ms),
+        )
+        .await;
+        (res, waits, started.elapsed().as_millis())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_backoff_schedule() {
+        let (res, waits, elapsed) = run_backoff(vec![
+            Attempt::Retry(TmdbErr::Server(503), None),
+            Attempt::Retry(TmdbErr::Net, None),
+            Attempt::Retry(TmdbErr::Server(502), None),
+            Attempt::Retry(TmdbErr::Server(500), None),
+        ])
+        .await;
+        assert!(matches!(res, Err(TmdbErr::Server(500))));
+        assert_eq!(waits, vec![300, 800, 1500]);
+        assert_eq!(elapsed, 2600);
+
+        let (res, waits, elapsed) = run_backoff(vec![
+            Attempt::Retry(TmdbErr::Server(503), None),
+            Attempt::Done(Ok(7)),
+        ])
+        .await;
+        assert_eq!(res.unwrap(), 7);
+        assert_eq!(waits, vec![300]);
+        assert_eq!(elapsed, 300);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_backoff_honours_retry_after() {
+        // Retry-After длиннее паузы — ждём его, короче — ждём паузу
+        let (res, waits, elapsed) = run_backoff(vec![
+            Attempt::Retry(TmdbErr::RateLimited, Some(2)),
+            Attempt::Retry(TmdbErr::RateLimited, Some(1)),
+            Attempt::Retry(TmdbErr::RateLimited, Some(1)),
+            Attempt::Retry(TmdbErr::RateLimited, Some(1)),
+        ])
+        .await;
+        assert!(matches!(res, Err(TmdbErr::RateLimited)));
+        assert_eq!(waits, vec![2000, 1000, 1500]);
+        assert_eq!(elapsed, 4500);
+
+        // слишком долгий Retry-After — не ждём вовсе
+        let (res, waits, elapsed) = run_backoff(vec![Attempt::Retry(
+            TmdbErr::RateLimited,
+            Some(MAX_RETRY_AFTER_SECS + 1),
+        )])
+        .await;
+        assert!(matches!(res, Err(TmdbErr::RateLimited)));
+        assert!(waits.is_empty());
+        assert_eq!(elapsed, 0);
+    }
+
+    #[test]
+    fn test_auth_detection() {
+        let v3 = "0123456789abcdef0123456789ABCDEF".to_string();
+        assert_eq!(TmdbAuth::detect(v3.clone()), TmdbAuth::ApiKey(v3.clone()));
+        let v4 = "eyJhbGciOiJIUzI1NiJ9.eyJhdWQiOiJ4In0.sig".to_string();
+        assert_eq!(TmdbAuth::detect(v4.clone()), TmdbAuth::Bearer(v4.clone()));
+        assert_eq!(
+            TmdbAuth::from_style(v3.clone(), Some("bearer")),
+            TmdbAuth::Bearer(v3.clone())
+        );
+        assert_eq!(
+            TmdbAuth::from_style(v4.clone(), Some("API_KEY")),
+            TmdbAuth::ApiKey(v4)
+        );
+        assert_eq!(
+            TmdbAuth::from_style(v3.clone(), Some("oauth")),
+            TmdbAuth::ApiKey(v3)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_v3_key_sent_as_query_param() {
+        use wiremock::matchers::{header_exists, method, path, query_param};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        let key = "0123456789abcdef0123456789abcdef";
+        Mock::given(method("GET"))
+            .and(path("/movie/7/keywords"))
+            .and(header_exists("authorization"))
+            .respond_with(ResponseTemplate::new(401))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/movie/7/keywords"))
+            .and(query_param("api_key", key))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "id": 7,
+                "keywords": [{"id": 1, "name": "heist"}]
+            })))
+            .mount(&server)
+            .await;
+
+        let client = TmdbClient::new_test(key.to_string(), server.uri());
+        let item = MultiNorm {
+            id: 7,
+            media_type: MediaKind::Movie,
+            title: "T".to_string(),
+            original_title: "T".to_string(),
+            overview: "".to_string(),
+            release_date: None,
+            image_path: None,
+            vote_average: None,
+            vote_count: None,
+            runtime: None,
+            seasons: None,
+            tagline: None,
+            original_language: None,
+        };
+        assert_eq!(client.keywords(&item).await.unwrap(), vec!["heist"]);
+    }
+
+    #[tokio::test]
+    async fn test_get_json_does_not_retry_auth_errors() {
+        use wiremock::matchers::{method, path};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        let client = TmdbClient::new_test("token".to_string(), server.uri());
+
+        Mock::given(method("GET"))
+            .and(path("/auth"))
+            .respond_with(ResponseTemplate::new(401))
+            .mount(&server)
+            .await;
+
+        let url = format!("{}/auth", server.uri());
+        let err = client
+            .get_json::<serde_json::Value>(&url)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, TmdbErr::Auth));
+        assert_eq!(server.received_requests().await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_get_json_times_out_with_configured_timeout() {
+        use wiremock::matchers::{method, path};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/slow"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(serde_json::json!({}))
+                    .set_delay(Duration::from_secs(2)),
+            )
+            .mount(&server)
+            .await;
+
+        let client = TmdbClient::new_test("token".to_string(), server.uri())
+            .with_timeout(Duration::from_millis(200));
+        let started = std::time::Instant::now();
+        let err = client
+            .get_json::<serde_json::Value>(&format!("{}/slow", server.uri()))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, TmdbErr::Net));
+        // 4 попытки по 200 мс + паузы 2.6 с — заметно меньше, чем 4 × 2 с без таймаута
+        assert!(started.elapsed() < Duration::from_secs(6));
+    }
+
+    #[tokio::test]
+    async fn test_get_json_retries_dropped_connections() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Arc;
+
+        // сервер, который принимает соединение и сразу его закрывает
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let accepted = Arc::new(AtomicUsize::new(0));
+        let counter = accepted.clone();
+        tokio::spawn(async move {
+            while let Ok((sock, _)) = listener.accept().await {
+                counter.fetch_add(1, Ordering::SeqCst);
+                drop(sock);
+            }
+        });
+
+        let client = TmdbClient::new_test("token".to_string(), format!("http://{}", addr));
+        let url = format!("http://{}/drop", addr);
+        let err = client
+            .get_json::<serde_json::Value>(&url)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, TmdbErr::Net));
+        assert_eq!(accepted.load(Ordering::SeqCst), 4);
+    }
+
+    #[tokio::test]
+    async fn test_watch_providers_and_certification_by_region() {
+        use wiremock::matchers::{method, path};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        let client = TmdbClient::new_test("token".to_string(), server.uri());
+
+        Mock::given(method("GET"))
+            .and(path("/movie/1/watch/providers"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "id": 1,
+                "results": {
+                    "US": {
+                        "link": "https://www.themoviedb.org/movie/1/watch?locale=US",
+                        "flatrate": [{ "provider_name": "Netflix" }],
+                        "rent": [{ "provider_name": "Apple TV" }]
+                    }
+                }
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/movie/1"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "id": 1, "title": "Movie", "original_title": "Movie",
+                "poster_path": null, "release_date": null,
+                "release_dates": { "results": [
+                    { "iso_3166_1": "RU", "release_dates": [{ "certification": "16+", "type": 3 }] },
+                    { "iso_3166_1": "US", "release_dates": [
+                        { "certification": "", "type": 1 },
+                        { "certification": "PG-13", "type": 3 }
+                    ] }
+                ] }
+            })))
+            .mount(&server)
+            .await;
+
+        let item = MultiNorm {
+            id: 1,
+            media_type: MediaKind::Movie,
+            title: "Movie".to_string(),
+            original_title: "Movie".to_string(),
+            overview: "".to_string(),
+            release_date: None,
+            image_path: None,
+            vote_average: None,
+            vote_count: None,
+            runtime: None,
+            seasons: None,
+            tagline: None,
+            original_language: None,
+        };
+
+        let us = client.watch_providers(&item, "US").await.unwrap().unwrap();
+        assert_eq!(us.flatrate, vec!["Netflix".to_string()]);
+        assert_eq!(us.rent, vec!["Apple TV".to_string()]);
+        assert!(client.watch_providers(&item, "RU").await.unwrap().is_none());
+
+        let cert = |region: &'static str| {
+            let client = client.clone();
+            async move {
+                client
+                    .movie_details_full(1, MediaKind::Movie, region, "ru")
+                    .await
+                    .unwrap()
+                    .unwrap()
+                    .certification
+            }
+        };
+        assert_eq!(cert("US").await.as_deref(), Some("PG-13"));
+        assert_eq!(cert("RU").await.as_deref(), Some("16+"));
+        assert_eq!(cert("DE").await, None);
     }
 }